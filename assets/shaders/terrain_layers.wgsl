@@ -0,0 +1,188 @@
+// Composites raster overlays on top of the base color of the terrain, followed by analytic
+// overlays that are derived from the elevation and normal of the terrain mesh.
+
+#import bevy_pbr::{
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::alpha_discard,
+}
+
+#ifdef PREPASS_PIPELINE
+#import bevy_pbr::{
+    prepass_io::{VertexOutput, FragmentOutput},
+    pbr_deferred_functions::deferred_output,
+}
+#else
+#import bevy_pbr::{
+    forward_io::{VertexOutput, FragmentOutput},
+    pbr_functions::{apply_pbr_lighting, main_pass_post_lighting_processing},
+}
+#endif
+
+// Must match `LayerBlendMode::to_shader_index`
+const BLEND_NORMAL: u32 = 0u;
+const BLEND_MULTIPLY: u32 = 1u;
+const BLEND_SCREEN: u32 = 2u;
+const BLEND_OVERLAY: u32 = 3u;
+
+// Must match `AnalysisShading::to_shader_index`
+const SHADING_SLOPE: u32 = 1u;
+const SHADING_ASPECT: u32 = 2u;
+const SHADING_HYPSOMETRIC: u32 = 3u;
+
+const PI: f32 = 3.141592653589793;
+
+struct TerrainLayerSettings {
+    opacity: vec4<f32>,
+    blend_mode: vec4<u32>,
+    enabled: vec4<u32>,
+    // min.xy and size.zw of the chunk in the tiles
+    uv_rect: vec4<f32>,
+    sun_direction: vec4<f32>,
+    // hillshade, shading, contours
+    analysis: vec4<u32>,
+    // contour interval, opacity, hypsometric min and max
+    analysis_params: vec4<f32>,
+    // added to the world y to get the elevation above sea level
+    elevation_offset: f32,
+}
+
+@group(#{MATERIAL_BIND_GROUP}) @binding(100) var overlay_0_texture: texture_2d<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(101) var overlay_0_sampler: sampler;
+@group(#{MATERIAL_BIND_GROUP}) @binding(102) var overlay_1_texture: texture_2d<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(103) var overlay_1_sampler: sampler;
+@group(#{MATERIAL_BIND_GROUP}) @binding(104) var overlay_2_texture: texture_2d<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(105) var overlay_2_sampler: sampler;
+@group(#{MATERIAL_BIND_GROUP}) @binding(106) var<uniform> settings: TerrainLayerSettings;
+
+fn blend(base: vec3<f32>, layer: vec3<f32>, mode: u32) -> vec3<f32> {
+    switch mode {
+        case BLEND_MULTIPLY: {
+            return base * layer;
+        }
+        case BLEND_SCREEN: {
+            return 1.0 - (1.0 - base) * (1.0 - layer);
+        }
+        case BLEND_OVERLAY: {
+            let low = 2.0 * base * layer;
+            let high = 1.0 - 2.0 * (1.0 - base) * (1.0 - layer);
+            return select(high, low, base < vec3(0.5));
+        }
+        default: {
+            return layer;
+        }
+    }
+}
+
+fn composite(base: vec3<f32>, layer: vec4<f32>, i: u32) -> vec3<f32> {
+    if settings.enabled[i] == 0u {
+        return base;
+    }
+    let blended = blend(base, layer.rgb, settings.blend_mode[i]);
+    return mix(base, blended, layer.a * settings.opacity[i]);
+}
+
+fn hue_to_rgb(hue: f32) -> vec3<f32> {
+    let k = (vec3(0.0, 4.0, 2.0) + hue * 6.0) % 6.0;
+    return clamp(min(k, 4.0 - k), vec3(0.0), vec3(1.0));
+}
+
+fn slope_color(normal: vec3<f32>) -> vec3<f32> {
+    let slope = acos(clamp(normal.y, -1.0, 1.0));
+    let t = clamp(slope / (PI / 4.0), 0.0, 1.0);
+    return select(
+        mix(vec3(1.0, 1.0, 0.0), vec3(0.9, 0.1, 0.0), t * 2.0 - 1.0),
+        mix(vec3(0.1, 0.7, 0.2), vec3(1.0, 1.0, 0.0), t * 2.0),
+        t < 0.5,
+    );
+}
+
+fn aspect_color(normal: vec3<f32>) -> vec3<f32> {
+    // Clockwise from north, where north is -Z
+    let aspect = atan2(normal.x, -normal.z) / (2.0 * PI) + 0.5;
+    let flatness = smoothstep(0.9, 1.0, normal.y);
+    return mix(hue_to_rgb(aspect), vec3(0.5), flatness);
+}
+
+fn hypsometric_color(height: f32) -> vec3<f32> {
+    let range = settings.analysis_params.zw;
+    let t = clamp((height - range.x) / max(range.y - range.x, 1.0), 0.0, 1.0) * 3.0;
+    let low = vec3(0.2, 0.5, 0.25);
+    let middle = vec3(0.9, 0.85, 0.5);
+    let high = vec3(0.55, 0.35, 0.2);
+    let top = vec3(0.95, 0.95, 0.95);
+    if t < 1.0 {
+        return mix(low, middle, t);
+    } else if t < 2.0 {
+        return mix(middle, high, t - 1.0);
+    }
+    return mix(high, top, t - 2.0);
+}
+
+// Returns 1.0 on a contour line and 0.0 elsewhere, with lines of about a pixel wide
+fn contour_line(height: f32, interval: f32) -> f32 {
+    let distance = abs(fract(height / interval + 0.5) - 0.5) * interval;
+    let width = fwidth(height);
+    let major = abs(fract(height / (interval * 5.0) + 0.5) - 0.5) * interval * 5.0;
+    let minor_line = 1.0 - smoothstep(0.5 * width, 1.5 * width, distance);
+    let major_line = 1.0 - smoothstep(1.0 * width, 2.5 * width, major);
+    return max(0.6 * minor_line, major_line);
+}
+
+// `elevation` is in meters above sea level
+fn apply_analysis(color: vec3<f32>, normal: vec3<f32>, elevation: f32) -> vec3<f32> {
+    let opacity = settings.analysis_params.y;
+    var result = color;
+
+    switch settings.analysis.y {
+        case SHADING_SLOPE: {
+            result = mix(result, slope_color(normal), opacity);
+        }
+        case SHADING_ASPECT: {
+            result = mix(result, aspect_color(normal), opacity);
+        }
+        case SHADING_HYPSOMETRIC: {
+            result = mix(result, hypsometric_color(elevation), opacity);
+        }
+        default: {}
+    }
+
+    if settings.analysis.x != 0u {
+        let shade = max(dot(normal, settings.sun_direction.xyz), 0.0);
+        result = mix(result, result * shade, opacity);
+    }
+
+    if settings.analysis.z != 0u {
+        let line = contour_line(elevation, settings.analysis_params.x);
+        result = mix(result, vec3(0.25, 0.12, 0.05), line * opacity);
+    }
+    return result;
+}
+
+@fragment
+fn fragment(in: VertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+
+    // The same rotation as the `uv_transform` of the base layer, followed by the area of the
+    // chunk in the tiles.
+    let tile_uv = vec2(1.0 - in.uv.y, in.uv.x);
+    let uv = settings.uv_rect.xy + settings.uv_rect.zw * tile_uv;
+
+    var color = pbr_input.material.base_color.rgb;
+    color = composite(color, textureSample(overlay_0_texture, overlay_0_sampler, uv), 0u);
+    color = composite(color, textureSample(overlay_1_texture, overlay_1_sampler, uv), 1u);
+    color = composite(color, textureSample(overlay_2_texture, overlay_2_sampler, uv), 2u);
+    let elevation = pbr_input.world_position.y + settings.elevation_offset;
+    color = apply_analysis(color, normalize(pbr_input.world_normal), elevation);
+    pbr_input.material.base_color = vec4(color, pbr_input.material.base_color.a);
+
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+#ifdef PREPASS_PIPELINE
+    let out = deferred_output(in, pbr_input);
+#else
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+#endif
+    return out;
+}
@@ -19,6 +19,11 @@ struct WaterSettings {
     octave_scales: vec4<f32>,
     // How high the waves are in each octave.
     octave_strengths: vec4<f32>,
+    // Width of the foam along the shore and the distance over which shallow water fades to deep
+    // water, in meters.
+    shoreline: vec2<f32>,
+    // Colour of the water at the shore.
+    shallow_color: vec4<f32>,
 }
 
 @group(0) @binding(1) var<uniform> globals: Globals;
@@ -52,8 +57,29 @@ fn sample_noise(uv: vec2<f32>, time: f32) -> vec3<f32> {
 fn fragment(in: VertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
     // Create the PBR input.
     var pbr_input = pbr_input_from_standard_material(in, is_front);
+    // Sample the noise in world space, so that separate water meshes line up. The water plane
+    // spans 2e5 meters.
+    let uv = in.world_position.xz / 2e5 + 0.5;
     // Bump the normal.
-    pbr_input.N = sample_noise(in.uv, globals.time);
+    pbr_input.N = sample_noise(uv, globals.time);
+
+#ifdef VERTEX_UVS_B
+    // Meshes that know the distance to the shore fade from shallow to deep water and get foam
+    // along the shore.
+    let shore_distance = in.uv_b.x;
+    let depth = saturate(shore_distance / water_settings.shoreline.y);
+    pbr_input.material.base_color = mix(
+        water_settings.shallow_color,
+        pbr_input.material.base_color,
+        depth,
+    );
+
+    let ripple = sample_noise(uv * 4.0, globals.time * 2.0).x * 0.5 + 0.5;
+    let foam = (1.0 - smoothstep(0.0, water_settings.shoreline.x, shore_distance)) * ripple;
+    pbr_input.material.base_color = mix(pbr_input.material.base_color, vec4(1.0), foam);
+    pbr_input.material.perceptual_roughness = mix(pbr_input.material.perceptual_roughness, 1.0, foam);
+    pbr_input.N = normalize(mix(pbr_input.N, in.world_normal, foam));
+#endif
     // Send the rest to the deferred shader.
     return deferred_output(in, pbr_input);
 }
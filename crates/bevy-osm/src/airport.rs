@@ -0,0 +1,639 @@
+//! Procedural airports from the `aeroway` layer of the vector tiles.
+//!
+//! Runway centrelines become asphalt strips with threshold, designator and centreline markings,
+//! edge, threshold and end lights and an approach lighting system. Taxiway centrelines get a
+//! concrete strip with a yellow line and blue edge lights, aprons and other aeroway polygons are
+//! filled with concrete. Each runway is also spawned as a [`Runway`] entity, so a flight can
+//! start from its threshold.
+
+use bevy::{
+    asset::RenderAssetUsages,
+    mesh::{Indices, PrimitiveTopology},
+    prelude::*,
+};
+use bevy_terrain::mesh::HeightMap;
+use lyon::{math::Point, path::Path};
+use lyon_tessellation::{BuffersBuilder, FillOptions, FillTessellator};
+
+use crate::mesh::{VertexBuffers, VertexConstructor, subdivide};
+
+/// Width of runways, the vector tiles don't have it (meters)
+const RUNWAY_WIDTH: f32 = 45.0;
+/// Width of taxiways (meters)
+const TAXIWAY_WIDTH: f32 = 23.0;
+/// Maximum distance between the vertices of a surface, so it follows the terrain (meters)
+const DRAPE_SPACING: f32 = 20.0;
+/// Upper bound on the triangles of a single aeroway polygon
+const MAX_SURFACE_TRIANGLES: usize = 50_000;
+
+/// Heights above the terrain, so the surfaces and markings don't z-fight (meters)
+const APRON_OFFSET: f32 = 0.1;
+const TAXIWAY_OFFSET: f32 = 0.15;
+const RUNWAY_OFFSET: f32 = 0.2;
+const MARKING_OFFSET: f32 = 0.25;
+/// Height of the light fixtures above the terrain (meters)
+const LIGHT_OFFSET: f32 = 0.5;
+/// Size of a light fixture, the shared mesh is a unit cube (meters)
+const LIGHT_SIZE: f32 = 1.0;
+
+/// Distance from the runway end to the threshold markings (meters)
+const THRESHOLD_MARGIN: f32 = 6.0;
+/// Length of the threshold stripes (meters)
+const THRESHOLD_LENGTH: f32 = 30.0;
+const THRESHOLD_STRIPE_WIDTH: f32 = 1.8;
+/// Gap between the threshold markings and the designator (meters)
+const DESIGNATOR_MARGIN: f32 = 12.0;
+const DIGIT_HEIGHT: f32 = 9.0;
+const DIGIT_WIDTH: f32 = 4.5;
+const DIGIT_STROKE: f32 = 0.9;
+const CENTRELINE_STRIPE: f32 = 30.0;
+const CENTRELINE_GAP: f32 = 20.0;
+const CENTRELINE_WIDTH: f32 = 0.9;
+const TAXIWAY_LINE_WIDTH: f32 = 0.5;
+
+/// Distance between edge lights (meters)
+const EDGE_LIGHT_SPACING: f32 = 60.0;
+/// Distance between the lights across the threshold (meters)
+const THRESHOLD_LIGHT_SPACING: f32 = 3.0;
+/// Length of the approach lighting system before the threshold (meters)
+const APPROACH_LENGTH: f32 = 900.0;
+const APPROACH_LIGHT_SPACING: f32 = 30.0;
+/// Distance of the crossbar of the approach lights from the threshold (meters)
+const APPROACH_CROSSBAR: f32 = 300.0;
+
+const ASPHALT: Color = Color::linear_rgb(0.04, 0.04, 0.045);
+const CONCRETE: Color = Color::linear_rgb(0.25, 0.25, 0.24);
+const MARKING_WHITE: Color = Color::linear_rgb(0.9, 0.9, 0.9);
+const MARKING_YELLOW: Color = Color::linear_rgb(0.9, 0.7, 0.0);
+
+/// The segments of a seven-segment display that are lit for each digit, in the order top, top
+/// right, bottom right, bottom, bottom left, top left and middle
+const DIGIT_SEGMENTS: [[bool; 7]; 10] = [
+    [true, true, true, true, true, true, false],
+    [false, true, true, false, false, false, false],
+    [true, true, false, true, true, false, true],
+    [true, true, true, true, false, false, true],
+    [false, true, true, false, false, true, true],
+    [true, false, true, true, false, true, true],
+    [true, false, true, true, true, true, true],
+    [true, true, true, false, false, false, false],
+    [true, true, true, true, true, true, true],
+    [true, true, true, true, false, true, true],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AerowayKind {
+    Runway,
+    Taxiway,
+    /// Aprons, gates and helipads
+    Apron,
+}
+
+pub struct AerowayInstruction {
+    pub kind: AerowayKind,
+}
+
+/// A runway of an airport, as a child of its chunk
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct Runway {
+    /// The designator of each end, such as `09` and `27`
+    pub designators: [String; 2],
+    /// The ends of the runway on the terrain, in the local space of the chunk
+    pub thresholds: [Vec3; 2],
+    /// Width (meters)
+    pub width: f32,
+}
+
+impl Runway {
+    /// Returns the position at the threshold of an end of the runway, facing down the runway,
+    /// where `transform` is the transform of the runway entity
+    pub fn get_takeoff_transform(&self, end: usize, transform: &GlobalTransform) -> Transform {
+        let start = transform.transform_point(self.thresholds[end]);
+        let target = transform.transform_point(self.thresholds[1 - end]);
+        Transform::from_translation(start).looking_at(target.with_y(start.y), Vec3::Y)
+    }
+}
+
+/// Returns the designator of a runway that is used in `direction`, its heading in tens of
+/// degrees. Designators are based on the magnetic heading, this uses true north instead.
+///
+/// `direction` is on the XZ-plane, where north is -Z.
+pub fn get_designator(direction: Vec2) -> String {
+    let heading = direction
+        .x
+        .atan2(-direction.y)
+        .to_degrees()
+        .rem_euclid(360.0);
+    let designator = match (heading / 10.0).round() as u32 {
+        0 => 36,
+        designator => designator,
+    };
+    format!("{designator:02}")
+}
+
+/// The colors of the light fixtures, each is drawn with its own material
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AirportLightColor {
+    White,
+    Green,
+    Red,
+    Blue,
+}
+
+impl AirportLightColor {
+    pub const ALL: [AirportLightColor; 4] = [
+        AirportLightColor::White,
+        AirportLightColor::Green,
+        AirportLightColor::Red,
+        AirportLightColor::Blue,
+    ];
+
+    /// Lights are brighter than 1.0, so they bloom with an unlit material
+    pub fn get_color(&self) -> Color {
+        match self {
+            AirportLightColor::White => Color::linear_rgb(8.0, 8.0, 6.0),
+            AirportLightColor::Green => Color::linear_rgb(0.0, 8.0, 1.0),
+            AirportLightColor::Red => Color::linear_rgb(8.0, 0.0, 0.0),
+            AirportLightColor::Blue => Color::linear_rgb(0.0, 0.5, 8.0),
+        }
+    }
+}
+
+/// Whether a point in chunk space lies on the edge of the tile, where lines are clipped
+fn is_on_tile_edge(point: Vec2) -> bool {
+    point.abs().max_element() >= 0.5 - 1e-3
+}
+
+/// Whether a feature is a closed ring instead of a line
+fn is_polygon(points: &[Vec2]) -> bool {
+    points.len() > 3 && points.first() == points.last()
+}
+
+/// Collects the meshes of the airports in a chunk.
+///
+/// Geometry is constructed in meters relative to the chunk center and placed on the terrain, the
+/// output is in the local space of the chunk.
+pub struct AirportBuilder<'a> {
+    heights: &'a HeightMap,
+    /// Size of the chunk in meters
+    size: Vec2,
+    positions: Vec<[f32; 3]>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+    lights: Vec<(Transform, AirportLightColor)>,
+    runways: Vec<Runway>,
+}
+
+/// The output of [`AirportBuilder::build`]
+#[derive(Debug, Default)]
+pub struct AirportMeshes {
+    /// The surfaces and markings, with vertex colors
+    pub surfaces: Option<Mesh>,
+    /// The light fixtures in the local space of the chunk, drawn as instances of
+    /// [`crate::material::MapMaterialHandle::airport_light_mesh`]
+    pub lights: Vec<(Transform, AirportLightColor)>,
+    pub runways: Vec<Runway>,
+}
+
+impl<'a> AirportBuilder<'a> {
+    pub fn new(heights: &'a HeightMap, size: Vec2) -> Self {
+        Self {
+            heights,
+            size,
+            positions: Vec::new(),
+            colors: Vec::new(),
+            indices: Vec::new(),
+            lights: Vec::new(),
+            runways: Vec::new(),
+        }
+    }
+
+    /// Returns the point on the terrain at a position in meters
+    fn get_point(&self, position: Vec2, offset: f32) -> Vec3 {
+        let local = position / self.size;
+        Vec3::new(local.x, self.heights.sample(local) + offset, local.y)
+    }
+
+    fn add_vertex(&mut self, position: Vec2, offset: f32, color: Color) -> u32 {
+        let index = self.positions.len() as u32;
+        let point = self.get_point(position, offset);
+        self.positions.push(point.to_array());
+        self.colors.push(color.to_linear().to_f32_array());
+        index
+    }
+
+    /// Adds a triangle that faces up, whatever the order of its vertices
+    fn add_triangle(&mut self, a: u32, b: u32, c: u32) {
+        let position = |i: u32| Vec3::from_array(self.positions[i as usize]).xz();
+        let (ab, ac) = (position(b) - position(a), position(c) - position(a));
+        if ab.perp_dot(ac) < 0.0 {
+            self.indices.extend([a, b, c]);
+        } else {
+            self.indices.extend([a, c, b]);
+        }
+    }
+
+    /// Adds a rectangle from `start` to `end` (meters), split along its length so it follows the
+    /// terrain
+    fn add_strip(&mut self, start: Vec2, end: Vec2, width: f32, offset: f32, color: Color) {
+        let Some(direction) = (end - start).try_normalize() else {
+            return;
+        };
+        let side = direction.perp() * width / 2.0;
+        let steps = (start.distance(end) / DRAPE_SPACING).ceil().max(1.0) as usize;
+
+        let mut previous = None;
+        for i in 0..=steps {
+            let center = start.lerp(end, i as f32 / steps as f32);
+            let left = self.add_vertex(center - side, offset, color);
+            let right = self.add_vertex(center + side, offset, color);
+            if let Some((previous_left, previous_right)) = previous {
+                self.add_triangle(previous_left, previous_right, right);
+                self.add_triangle(previous_left, right, left);
+            }
+            previous = Some((left, right));
+        }
+    }
+
+    /// Adds a rectangle centered on `center` (meters), with its length along `direction`
+    fn add_rect(&mut self, center: Vec2, direction: Vec2, size: Vec2, color: Color) {
+        let half_length = direction * size.y / 2.0;
+        self.add_strip(
+            center - half_length,
+            center + half_length,
+            size.x,
+            MARKING_OFFSET,
+            color,
+        );
+    }
+
+    /// Fills a polygon in chunk space
+    fn add_polygon(&mut self, ring: &[Vec2], offset: f32, color: Color) {
+        let mut path_builder = Path::builder();
+        path_builder.begin(Point::new(ring[0].x, ring[0].y));
+        for p in &ring[1..] {
+            path_builder.line_to(Point::new(p.x, p.y));
+        }
+        path_builder.end(true);
+
+        let mut buffers = VertexBuffers::new();
+        if let Err(e) = FillTessellator::new().tessellate_path(
+            &path_builder.build(),
+            &FillOptions::default(),
+            &mut BuffersBuilder::new(&mut buffers, VertexConstructor { color }),
+        ) {
+            error!("FillTessellator error: {:?}", e);
+            return;
+        }
+        subdivide(
+            &mut buffers,
+            0,
+            DRAPE_SPACING / self.size.max_element(),
+            MAX_SURFACE_TRIANGLES,
+        );
+
+        let first = self.positions.len() as u32;
+        for vertex in &buffers.vertices {
+            self.add_vertex(Vec2::from_array(vertex.position) * self.size, offset, color);
+        }
+        for triangle in buffers.indices.chunks_exact(3) {
+            self.add_triangle(
+                first + triangle[0],
+                first + triangle[1],
+                first + triangle[2],
+            );
+        }
+    }
+
+    fn add_light(&mut self, position: Vec2, color: AirportLightColor) {
+        let transform = Transform::from_translation(self.get_point(position, LIGHT_OFFSET))
+            .with_scale(Vec3::new(
+                LIGHT_SIZE / self.size.x,
+                LIGHT_SIZE,
+                LIGHT_SIZE / self.size.y,
+            ));
+        self.lights.push((transform, color));
+    }
+
+    /// Adds lights along a line, `offset` to the side of it (meters)
+    fn add_edge_lights(&mut self, start: Vec2, end: Vec2, offset: f32, color: AirportLightColor) {
+        let Some(direction) = (end - start).try_normalize() else {
+            return;
+        };
+        let side = direction.perp() * offset;
+        let count = (start.distance(end) / EDGE_LIGHT_SPACING).ceil().max(1.0) as usize;
+        for i in 0..=count {
+            let center = start.lerp(end, i as f32 / count as f32);
+            self.add_light(center - side, color);
+            self.add_light(center + side, color);
+        }
+    }
+
+    /// Adds a row of lights across `center`, perpendicular to `direction`
+    fn add_light_bar(
+        &mut self,
+        center: Vec2,
+        direction: Vec2,
+        width: f32,
+        color: AirportLightColor,
+    ) {
+        let count = (width / THRESHOLD_LIGHT_SPACING).floor() as i32 / 2;
+        for i in -count..=count {
+            let offset = direction.perp() * i as f32 * THRESHOLD_LIGHT_SPACING;
+            self.add_light(center + offset, color);
+        }
+    }
+
+    /// Draws the digits of a designator, readable from the threshold. `bottom` is the middle of
+    /// the bottom edge of the text.
+    fn add_designator(&mut self, designator: &str, bottom: Vec2, direction: Vec2) {
+        // To the right of a pilot that is facing `direction`
+        let right = direction.perp();
+        let spacing = DIGIT_WIDTH * 1.5;
+        let digits: Vec<u32> = designator.chars().filter_map(|c| c.to_digit(10)).collect();
+        let first = -(digits.len() as f32 - 1.0) * spacing / 2.0;
+
+        for (i, digit) in digits.into_iter().enumerate() {
+            let center =
+                bottom + right * (first + i as f32 * spacing) + direction * DIGIT_HEIGHT / 2.0;
+            let (w, h, t) = (DIGIT_WIDTH, DIGIT_HEIGHT, DIGIT_STROKE);
+            // The (x, y) of the center of each segment and whether it is horizontal
+            let segments = [
+                (Vec2::new(0.0, (h - t) / 2.0), true),
+                (Vec2::new((w - t) / 2.0, h / 4.0), false),
+                (Vec2::new((w - t) / 2.0, -h / 4.0), false),
+                (Vec2::new(0.0, (t - h) / 2.0), true),
+                (Vec2::new((t - w) / 2.0, -h / 4.0), false),
+                (Vec2::new((t - w) / 2.0, h / 4.0), false),
+                (Vec2::ZERO, true),
+            ];
+            for ((offset, horizontal), lit) in
+                segments.into_iter().zip(DIGIT_SEGMENTS[digit as usize])
+            {
+                if !lit {
+                    continue;
+                }
+                let position = center + right * offset.x + direction * offset.y;
+                let (axis, size) = if horizontal {
+                    (right, Vec2::new(t, w))
+                } else {
+                    (direction, Vec2::new(t, h / 2.0))
+                };
+                self.add_rect(position, axis, size, MARKING_WHITE);
+            }
+        }
+    }
+
+    /// Adds the markings and lights of one end of a runway. `threshold` is the end of the
+    /// runway, `direction` points down the runway. Returns the distance from the threshold at
+    /// which the centreline starts.
+    fn add_runway_end(
+        &mut self,
+        threshold: Vec2,
+        direction: Vec2,
+        length: f32,
+        designator: &str,
+    ) -> f32 {
+        // Threshold stripes, symmetric around the centreline
+        let stripes = ((RUNWAY_WIDTH / 45.0 * 12.0 / 2.0).round() as usize).max(2);
+        let stripe_center = threshold + direction * (THRESHOLD_MARGIN + THRESHOLD_LENGTH / 2.0);
+        let stripe_spacing = (RUNWAY_WIDTH / 2.0 - 3.0) / stripes as f32;
+        for i in 0..stripes {
+            let offset = 3.0 + stripe_spacing * (i as f32 + 0.5);
+            for side in [-1.0, 1.0] {
+                self.add_rect(
+                    stripe_center + direction.perp() * offset * side,
+                    direction,
+                    Vec2::new(THRESHOLD_STRIPE_WIDTH, THRESHOLD_LENGTH),
+                    MARKING_WHITE,
+                );
+            }
+        }
+
+        let designator_start = THRESHOLD_MARGIN + THRESHOLD_LENGTH + DESIGNATOR_MARGIN;
+        if length > 2.0 * (designator_start + DIGIT_HEIGHT) {
+            self.add_designator(
+                designator,
+                threshold + direction * designator_start,
+                direction,
+            );
+        }
+
+        // Green threshold lights on the runway, red end lights for the opposite direction
+        // just before it
+        self.add_light_bar(
+            threshold + direction,
+            direction,
+            RUNWAY_WIDTH,
+            AirportLightColor::Green,
+        );
+        self.add_light_bar(
+            threshold - direction,
+            direction,
+            RUNWAY_WIDTH,
+            AirportLightColor::Red,
+        );
+
+        // Approach lights along the extended centreline, with a crossbar
+        let mut distance = APPROACH_LIGHT_SPACING;
+        while distance <= APPROACH_LENGTH {
+            let center = threshold - direction * distance;
+            self.add_light_bar(
+                center,
+                direction,
+                4.0 * THRESHOLD_LIGHT_SPACING,
+                AirportLightColor::White,
+            );
+            distance += APPROACH_LIGHT_SPACING;
+        }
+        self.add_light_bar(
+            threshold - direction * APPROACH_CROSSBAR,
+            direction,
+            30.0,
+            AirportLightColor::White,
+        );
+
+        designator_start + DIGIT_HEIGHT + DESIGNATOR_MARGIN
+    }
+
+    /// Adds a runway from its centreline. Ends that are `clipped` by the edge of the tile are not
+    /// the real ends of the runway, so they get no markings or lights, and the runway is only
+    /// exported as a [`Runway`] when both ends are in this tile.
+    fn add_runway(&mut self, start: Vec2, end: Vec2, clipped: [bool; 2]) {
+        let Some(direction) = (end - start).try_normalize() else {
+            return;
+        };
+        let length = start.distance(end);
+        let designators = [get_designator(direction), get_designator(-direction)];
+
+        self.add_strip(start, end, RUNWAY_WIDTH, RUNWAY_OFFSET, ASPHALT);
+        let start_margin = match clipped[0] {
+            true => 0.0,
+            false => self.add_runway_end(start, direction, length, &designators[0]),
+        };
+        let end_margin = match clipped[1] {
+            true => 0.0,
+            false => self.add_runway_end(end, -direction, length, &designators[1]),
+        };
+
+        // Dashed centreline between the designators
+        let mut distance = start_margin;
+        while distance + CENTRELINE_STRIPE <= length - end_margin {
+            self.add_rect(
+                start + direction * (distance + CENTRELINE_STRIPE / 2.0),
+                direction,
+                Vec2::new(CENTRELINE_WIDTH, CENTRELINE_STRIPE),
+                MARKING_WHITE,
+            );
+            distance += CENTRELINE_STRIPE + CENTRELINE_GAP;
+        }
+
+        self.add_edge_lights(
+            start,
+            end,
+            RUNWAY_WIDTH / 2.0 + 1.5,
+            AirportLightColor::White,
+        );
+
+        if clipped.contains(&true) {
+            return;
+        }
+        self.runways.push(Runway {
+            designators,
+            thresholds: [self.get_point(start, 0.0), self.get_point(end, 0.0)],
+            width: RUNWAY_WIDTH,
+        });
+    }
+
+    fn add_taxiway(&mut self, points: &[Vec2]) {
+        for segment in points.windows(2) {
+            let (start, end) = (segment[0], segment[1]);
+            self.add_strip(start, end, TAXIWAY_WIDTH, TAXIWAY_OFFSET, CONCRETE);
+            self.add_strip(
+                start,
+                end,
+                TAXIWAY_LINE_WIDTH,
+                MARKING_OFFSET,
+                MARKING_YELLOW,
+            );
+            self.add_edge_lights(
+                start,
+                end,
+                TAXIWAY_WIDTH / 2.0 + 1.0,
+                AirportLightColor::Blue,
+            );
+        }
+    }
+
+    /// Adds an aeroway feature in chunk space
+    pub fn add_feature(&mut self, instruction: &AerowayInstruction, points: &[Point]) {
+        let points: Vec<Vec2> = points.iter().map(|p| Vec2::new(p.x, p.y)).collect();
+        if points.len() < 2 {
+            return;
+        }
+
+        if is_polygon(&points) {
+            let (offset, color) = match instruction.kind {
+                AerowayKind::Runway => (RUNWAY_OFFSET, ASPHALT),
+                AerowayKind::Taxiway => (TAXIWAY_OFFSET, CONCRETE),
+                AerowayKind::Apron => (APRON_OFFSET, CONCRETE),
+            };
+            self.add_polygon(&points, offset, color);
+            return;
+        }
+
+        let meters: Vec<Vec2> = points.iter().map(|p| *p * self.size).collect();
+        let last = points.len() - 1;
+        match instruction.kind {
+            // Runways are straight, their centreline runs from one end to the other
+            AerowayKind::Runway => self.add_runway(
+                meters[0],
+                meters[last],
+                [is_on_tile_edge(points[0]), is_on_tile_edge(points[last])],
+            ),
+            AerowayKind::Taxiway => self.add_taxiway(&meters),
+            AerowayKind::Apron => {}
+        }
+    }
+
+    pub fn build(self) -> AirportMeshes {
+        let surfaces = (!self.indices.is_empty()).then(|| {
+            let mut mesh = Mesh::new(
+                PrimitiveTopology::TriangleList,
+                RenderAssetUsages::default(),
+            );
+            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+            mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+            mesh.insert_indices(Indices::U32(self.indices));
+            mesh.compute_normals();
+            mesh
+        });
+
+        AirportMeshes {
+            surfaces,
+            lights: self.lights,
+            runways: self.runways,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lyon::math::point;
+
+    use super::*;
+
+    #[test]
+    fn test_designators() {
+        assert_eq!(get_designator(Vec2::NEG_Y), "36");
+        assert_eq!(get_designator(Vec2::X), "09");
+        assert_eq!(get_designator(Vec2::Y), "18");
+        assert_eq!(get_designator(Vec2::NEG_X), "27");
+        // 184 degrees
+        assert_eq!(get_designator(Vec2::new(-0.07, 1.0)), "18");
+    }
+
+    #[test]
+    fn test_runway_from_centreline() {
+        let heights = HeightMap::new(IVec2::splat(8));
+        let mut builder = AirportBuilder::new(&heights, Vec2::splat(4000.0));
+        builder.add_feature(
+            &AerowayInstruction {
+                kind: AerowayKind::Runway,
+            },
+            &[point(-0.25, 0.0), point(0.25, 0.0)],
+        );
+        let airport = builder.build();
+
+        assert_eq!(airport.runways.len(), 1);
+        let runway = &airport.runways[0];
+        assert_eq!(runway.designators, ["09", "27"]);
+        assert_eq!(runway.thresholds[0], Vec3::new(-0.25, 0.0, 0.0));
+        assert!(airport.surfaces.is_some());
+        assert!(!airport.lights.is_empty());
+
+        let takeoff = runway.get_takeoff_transform(1, &GlobalTransform::default());
+        assert!(takeoff.forward().dot(Vec3::NEG_X) > 0.999);
+    }
+
+    #[test]
+    fn test_runway_clipped_by_tile_edge() {
+        let heights = HeightMap::new(IVec2::splat(8));
+        let runway = |end: f32| {
+            let mut builder = AirportBuilder::new(&heights, Vec2::splat(4000.0));
+            builder.add_feature(
+                &AerowayInstruction {
+                    kind: AerowayKind::Runway,
+                },
+                &[point(-0.25, 0.0), point(end, 0.0)],
+            );
+            builder.build()
+        };
+        assert_eq!(runway(0.45).runways.len(), 1);
+
+        // The part of a runway in the next tile is not a runway of its own
+        let clipped = runway(0.5);
+        assert!(clipped.runways.is_empty());
+        assert!(clipped.surfaces.is_some());
+    }
+}
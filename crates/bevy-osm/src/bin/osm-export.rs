@@ -0,0 +1,72 @@
+//! Exports the terrain, buildings and roads of a region to a glTF binary or OBJ file.
+//!
+//! Run from the root of the repository, so the tile cache in `assets/cache` is reused:
+//!
+//! ```sh
+//! cargo run -p bevy-osm --bin osm-export -- 52.36 4.88 52.38 4.92 amsterdam.glb
+//! ```
+
+use std::{env, path::PathBuf, process::ExitCode};
+
+use bevy::math::{Rect, Vec2};
+use bevy_osm::export::{ExportError, ExportRegion, export_region};
+
+const USAGE: &str =
+    "Usage: osm-export <min_lat> <min_lon> <max_lat> <max_lon> <output.glb|output.obj> [zoom]";
+const DEFAULT_ZOOM: i8 = 14;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !(5..=6).contains(&args.len()) {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    }
+
+    let Ok(coords) = args[..4]
+        .iter()
+        .map(|arg| arg.parse::<f32>())
+        .collect::<Result<Vec<f32>, _>>()
+    else {
+        eprintln!("Coordinates must be numbers in degrees\n{USAGE}");
+        return ExitCode::FAILURE;
+    };
+    let Ok(zoom) = args
+        .get(5)
+        .map_or(Ok(DEFAULT_ZOOM), |arg| arg.parse::<i8>())
+    else {
+        eprintln!("Zoom must be an integer\n{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    let region = ExportRegion {
+        bounds: Rect::from_corners(
+            Vec2::new(coords[0], coords[1]),
+            Vec2::new(coords[2], coords[3]),
+        ),
+        zoom,
+    };
+    let output = PathBuf::from(&args[4]);
+
+    match export_region(&region, &output) {
+        Ok(()) => {
+            println!(
+                "Exported {} chunks to {}",
+                region.chunks().len(),
+                output.display()
+            );
+            ExitCode::SUCCESS
+        }
+        Err(ExportError::IncompleteDownload) => {
+            eprintln!(
+                "{}, written to {}",
+                ExportError::IncompleteDownload,
+                output.display()
+            );
+            ExitCode::FAILURE
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
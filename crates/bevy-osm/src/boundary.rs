@@ -0,0 +1,247 @@
+//! Administrative boundaries of the vector tiles, drawn as dashed lines draped over the terrain.
+//!
+//! The width and dash pattern are relative to the tile, like the other strokes, so the borders of
+//! countries stay visible from far away while those of municipalities only show up close by.
+
+use bevy::{
+    asset::RenderAssetUsages,
+    mesh::{Indices, PrimitiveTopology},
+    prelude::*,
+};
+use bevy_terrain::mesh::HeightMap;
+use lyon::{
+    math::{Point, point},
+    path::Path,
+};
+use lyon_tessellation::{BuffersBuilder, LineCap, StrokeOptions, StrokeTessellator};
+
+use crate::{
+    mesh::{VertexBuffers, VertexConstructor, densify},
+    tag::Tag,
+};
+
+/// Maximum length of the segments that are draped over the terrain (fraction of the tile)
+const DRAPE_SPACING: f32 = 1.0 / 128.0;
+/// Height of the lines above the terrain (meters)
+const BOUNDARY_OFFSET: f32 = 1.0;
+const MARITIME_COLOR: Color = Color::linear_rgb(0.1, 0.3, 0.9);
+
+/// A boundary from the `boundary` layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoundaryInstruction {
+    /// 2 for countries, 4 for states or provinces, 8 for municipalities
+    pub admin_level: u8,
+    /// Whether the boundary lies at sea
+    pub maritime: bool,
+    /// Whether the boundary is disputed
+    pub disputed: bool,
+}
+
+/// How a boundary is drawn, sizes are fractions of the tile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundaryStyle {
+    pub color: Color,
+    pub width: f32,
+    pub dash: f32,
+    pub gap: f32,
+}
+
+impl BoundaryInstruction {
+    /// Reads the instruction from the tags of a feature, `None` if it has no `admin_level`
+    pub fn from_tags(tags: &[Tag]) -> Option<Self> {
+        let get = |key: &str| tags.iter().find(|tag| tag.key == key).map(|tag| &tag.val);
+        let is_set = |key: &str| get(key).is_some_and(|value| value == "1" || value == "true");
+        Some(Self {
+            admin_level: get("admin_level")?.parse().ok()?,
+            maritime: is_set("maritime"),
+            disputed: is_set("disputed"),
+        })
+    }
+
+    pub fn get_style(&self) -> BoundaryStyle {
+        let (color, width, dash, gap) = match self.admin_level {
+            0..=2 => (Color::linear_rgb(0.5, 0.1, 0.6), 8.0, 48.0, 24.0),
+            3..=4 => (Color::linear_rgb(0.6, 0.25, 0.65), 5.0, 32.0, 16.0),
+            5..=6 => (Color::linear_rgb(0.5, 0.4, 0.6), 3.0, 20.0, 12.0),
+            _ => (Color::linear_rgb(0.4, 0.4, 0.45), 2.0, 12.0, 10.0),
+        };
+        BoundaryStyle {
+            color: if self.maritime { MARITIME_COLOR } else { color },
+            width: width / 4096.,
+            dash: dash / 4096.,
+            // Disputed boundaries are drawn with longer gaps
+            gap: if self.disputed { dash } else { gap } / 4096.,
+        }
+    }
+}
+
+/// Splits a line into dashes of length `dash`, separated by `gap`
+fn split_dashes(points: &[Vec2], dash: f32, gap: f32) -> Vec<Vec<Vec2>> {
+    let mut dashes = Vec::new();
+    let mut current = vec![points[0]];
+    // Distance left in the current dash or gap
+    let mut left = dash;
+    let mut drawing = true;
+
+    for segment in points.windows(2) {
+        let (mut start, end) = (segment[0], segment[1]);
+        while start.distance(end) >= left {
+            start = start.move_towards(end, left);
+            if drawing {
+                current.push(start);
+                dashes.push(std::mem::take(&mut current));
+                left = gap;
+            } else {
+                current.push(start);
+                left = dash;
+            }
+            drawing = !drawing;
+        }
+        left -= start.distance(end);
+        if drawing && current.last() != Some(&end) {
+            current.push(end);
+        }
+    }
+    if drawing && current.len() >= 2 {
+        dashes.push(current);
+    }
+    dashes
+}
+
+/// Collects the boundaries of a tile into a single mesh in chunk space
+pub struct BoundaryBuilder<'a> {
+    heights: &'a HeightMap,
+    buffers: VertexBuffers,
+}
+
+impl<'a> BoundaryBuilder<'a> {
+    pub fn new(heights: &'a HeightMap) -> Self {
+        Self {
+            heights,
+            buffers: VertexBuffers::new(),
+        }
+    }
+
+    pub fn add_feature(&mut self, instruction: &BoundaryInstruction, line: &[Point]) {
+        if line.len() < 2 {
+            return;
+        }
+        let style = instruction.get_style();
+        let points: Vec<Vec2> = line.iter().map(|p| Vec2::new(p.x, p.y)).collect();
+
+        let mut builder = Path::builder();
+        for dash in split_dashes(&points, style.dash, style.gap) {
+            let dash = densify(&dash, DRAPE_SPACING);
+            builder.begin(point(dash[0].x, dash[0].y));
+            for p in &dash[1..] {
+                builder.line_to(point(p.x, p.y));
+            }
+            builder.end(false);
+        }
+
+        if let Err(e) = StrokeTessellator::new().tessellate_path(
+            &builder.build(),
+            &StrokeOptions::default()
+                .with_line_width(style.width)
+                .with_line_cap(LineCap::Butt),
+            &mut BuffersBuilder::new(&mut self.buffers, VertexConstructor { color: style.color }),
+        ) {
+            error!("StrokeTessellator error: {:?}", e);
+        }
+    }
+
+    pub fn build(self) -> Option<Mesh> {
+        if self.buffers.indices.is_empty() {
+            return None;
+        }
+        let mut mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        );
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            self.buffers
+                .vertices
+                .iter()
+                .map(|v| {
+                    let position = Vec2::from_array(v.position);
+                    let height = self.heights.sample(position) + BOUNDARY_OFFSET;
+                    [position.x, height, position.y]
+                })
+                .collect::<Vec<[f32; 3]>>(),
+        );
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_NORMAL,
+            vec![[0.0, 1.0, 0.0]; self.buffers.vertices.len()],
+        );
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_COLOR,
+            self.buffers
+                .vertices
+                .iter()
+                .map(|v| v.color)
+                .collect::<Vec<[f32; 4]>>(),
+        );
+        mesh.insert_indices(Indices::U32(self.buffers.indices));
+        Some(mesh)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::mesh::VertexAttributeValues;
+
+    use super::*;
+
+    #[test]
+    fn test_split_dashes() {
+        let line = [Vec2::ZERO, Vec2::new(2.0, 0.0), Vec2::new(2.0, 5.0)];
+        let dashes = split_dashes(&line, 3.0, 1.0);
+
+        // The first dash goes around the corner, the line ends in the second dash
+        assert_eq!(
+            dashes,
+            vec![
+                vec![Vec2::ZERO, Vec2::new(2.0, 0.0), Vec2::new(2.0, 1.0)],
+                vec![Vec2::new(2.0, 2.0), Vec2::new(2.0, 5.0)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_boundaries_are_draped() {
+        let mut heights = HeightMap::new(IVec2::splat(8));
+        for x in -1..=10 {
+            for z in -1..=10 {
+                heights.set(x, z, z as f32 * 10.0);
+            }
+        }
+        let tags = [
+            Tag {
+                key: "admin_level".into(),
+                val: "2".into(),
+            },
+            Tag {
+                key: "maritime".into(),
+                val: "0".into(),
+            },
+        ];
+        let instruction = BoundaryInstruction::from_tags(&tags).unwrap();
+        assert_eq!(instruction.admin_level, 2);
+        assert!(!instruction.maritime);
+
+        let mut builder = BoundaryBuilder::new(&heights);
+        builder.add_feature(&instruction, &[point(0.0, -0.4), point(0.0, 0.4)]);
+        let mesh = builder.build().expect("boundary should be tessellated");
+
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("mesh should have positions");
+        };
+        for position in positions {
+            let ground = heights.sample(Vec2::new(position[0], position[2]));
+            assert!((position[1] - ground - BOUNDARY_OFFSET).abs() < 1e-3);
+        }
+    }
+}
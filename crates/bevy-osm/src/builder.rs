@@ -0,0 +1,406 @@
+//! Builds the meshes of a chunk from its vector tile and heightmap.
+//!
+//! [`ChunkBuilder`] does not depend on the ECS or the filesystem, so the chunk loading tasks, the
+//! exporter and the tests share the same code.
+
+use std::time::Duration;
+
+use bevy::{platform::time::Instant, prelude::*};
+use bevy_terrain::mesh::{HeightMap, build_mesh_data};
+use mvt_reader::error::ParserError;
+use rand::{SeedableRng, rngs::StdRng};
+
+use crate::{
+    airport::{AirportBuilder, AirportMeshes},
+    boundary::BoundaryBuilder,
+    building::{polygon_building, spawn_building},
+    elevation::sample_heightmap,
+    labels::PlaceLabel,
+    mesh::{BuildInstruction, spawn_stroke_mesh},
+    schema::layer::OMTLayer,
+    tag::Tag,
+    theme::Theme,
+    vector::parse_pbf,
+    water::build_water_mesh,
+};
+
+/// Height of street lights above the terrain (meters)
+const LIGHT_HEIGHT: f32 = 2.0;
+
+/// Merges meshes with the same attributes into a single mesh, so they can be drawn at once
+pub fn merge_meshes(meshes: Vec<Mesh>) -> Option<Mesh> {
+    let mut meshes = meshes.into_iter();
+    let mut merged = meshes.next()?;
+    for mesh in meshes {
+        merged.merge(&mesh).expect("could not merge meshes");
+    }
+    Some(merged)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureKind {
+    Stroke,
+    Building,
+}
+
+/// Describes a feature of the vector tile that resulted in a mesh
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureMetadata {
+    pub kind: FeatureKind,
+    pub layer: OMTLayer,
+    pub tags: Vec<Tag>,
+    /// The first point of the feature in chunk space, placed on the terrain
+    pub anchor: Vec3,
+    /// Height of buildings (meters)
+    pub height: Option<f32>,
+}
+
+/// The output of [`ChunkBuilder::build`], in the local space of the chunk
+#[derive(Debug, Default)]
+pub struct ChunkMeshes {
+    /// All strokes of the tile, merged into a single mesh
+    pub strokes: Option<Mesh>,
+    /// All buildings of the tile, merged into a single mesh
+    pub buildings: Option<Mesh>,
+    /// All water areas of the tile, merged into a single mesh
+    pub water: Option<Mesh>,
+    /// The runways, taxiways and aprons of the tile
+    pub airport: AirportMeshes,
+    /// All administrative boundaries of the tile, merged into a single mesh
+    pub boundaries: Option<Mesh>,
+    /// The named places of the tile, with their position on the terrain
+    pub places: Vec<(PlaceLabel, Vec3)>,
+    /// Street lights, drawn as instances of [`crate::material::MapMaterialHandle::light_mesh`]
+    pub lights: Vec<Transform>,
+    pub features: Vec<FeatureMetadata>,
+    /// Time spent decoding the vector tile
+    pub parse_time: Duration,
+    /// Time spent building the meshes
+    pub mesh_build_time: Duration,
+}
+
+/// Turns a vector tile and a heightmap into mesh data.
+///
+/// Buildings without a height or number of levels get a random height, which is deterministic
+/// for a given seed.
+pub struct ChunkBuilder<'a, T: Theme> {
+    heights: &'a HeightMap,
+    theme: &'a T,
+    seed: u64,
+    size: Vec2,
+}
+
+impl<'a, T: Theme> ChunkBuilder<'a, T> {
+    pub fn new(heights: &'a HeightMap, theme: &'a T) -> Self {
+        Self {
+            heights,
+            theme,
+            seed: 0,
+            size: Vec2::ONE,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Sets the size of the chunk in meters, which water and airports are measured in
+    pub fn with_size(mut self, size: Vec2) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// Builds the terrain mesh, which spans -0.5..0.5 on the XZ-plane
+    pub fn build_terrain(&self) -> Mesh {
+        build_mesh_data(self.heights, self.heights.vertex_count())
+    }
+
+    fn get_height(&self, translation: Vec3) -> f32 {
+        sample_heightmap(self.heights, translation).unwrap_or(0.0)
+    }
+
+    /// Builds the meshes of the features in a vector tile
+    pub fn build(&self, bytes: Vec<u8>) -> Result<ChunkMeshes, ParserError> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut output = ChunkMeshes::default();
+        let mut strokes: Vec<Mesh> = Vec::new();
+        let mut buildings: Vec<Mesh> = Vec::new();
+        let mut water: Vec<Mesh> = Vec::new();
+        let mut airport = AirportBuilder::new(self.heights, self.size);
+        let mut boundaries = BoundaryBuilder::new(self.heights);
+
+        let start = Instant::now();
+        let features = parse_pbf(bytes)?;
+        output.parse_time = start.elapsed();
+
+        let start = Instant::now();
+        for (tags, layer, polygon) in features {
+            match self
+                .theme
+                .get_build_instruction(tags.clone(), layer.clone())
+            {
+                BuildInstruction::Stroke(stroke) => {
+                    let center = polygon[0];
+                    let ground = self.get_height(Vec3::new(center.x, 0.0, center.y));
+
+                    output.lights.push(Transform::from_translation(Vec3::new(
+                        center.x,
+                        ground + LIGHT_HEIGHT,
+                        center.y,
+                    )));
+                    output.features.push(FeatureMetadata {
+                        kind: FeatureKind::Stroke,
+                        layer,
+                        tags,
+                        anchor: Vec3::new(center.x, ground, center.y),
+                        height: None,
+                    });
+                    strokes.push(spawn_stroke_mesh(polygon, stroke));
+                }
+                BuildInstruction::Building(building_instr) => {
+                    let building = polygon_building(&building_instr, polygon, &mut rng);
+                    let anchor = building.get_translation();
+                    let ground = self.get_height(anchor);
+
+                    output.features.push(FeatureMetadata {
+                        kind: FeatureKind::Building,
+                        layer,
+                        tags,
+                        anchor: anchor + Vec3::Y * ground,
+                        height: Some(building.height),
+                    });
+                    buildings.push(spawn_building(&building).translated_by(Vec3::Y * ground));
+                }
+                BuildInstruction::Water(water_instr) => {
+                    water.extend(build_water_mesh(
+                        &polygon,
+                        &water_instr,
+                        self.heights,
+                        self.size,
+                    ));
+                }
+                BuildInstruction::Aeroway(aeroway) => airport.add_feature(&aeroway, &polygon),
+                BuildInstruction::Boundary(boundary) => boundaries.add_feature(&boundary, &polygon),
+                BuildInstruction::Place(place) => {
+                    let position = Vec3::new(polygon[0].x, 0.0, polygon[0].y);
+                    // Places in the buffer around the tile belong to a neighbouring tile
+                    let inside = position.x.abs() <= 0.5 && position.z.abs() <= 0.5;
+                    if let Some(label) = PlaceLabel::from_tags(&place, &tags).filter(|_| inside) {
+                        output
+                            .places
+                            .push((label, position.with_y(self.get_height(position))));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        output.strokes = merge_meshes(strokes);
+        output.buildings = merge_meshes(buildings);
+        output.water = merge_meshes(water);
+        output.airport = airport.build();
+        output.boundaries = boundaries.build();
+        output.mesh_build_time = start.elapsed();
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, env, fs, path::PathBuf};
+
+    use bevy::mesh::VertexAttributeValues;
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::{elevation::TILE_VERTEX_COUNT, theme::OpenFreeMapTheme};
+
+    const FIXTURES: [&str; 4] = ["169", "1349", "2700", "3212"];
+
+    fn fixture_path(name: &str, extension: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../../assets/osm")
+            .join(format!("{name}.{extension}"))
+    }
+
+    /// A heightmap that slopes up in both directions, so terrain placement is covered as well
+    fn sloped_heightmap() -> HeightMap {
+        let vertex_count = IVec2::splat(TILE_VERTEX_COUNT);
+        let mut heights = HeightMap::new(vertex_count);
+        for x in -1..=vertex_count.x + 2 {
+            for z in -1..=vertex_count.y + 2 {
+                heights.set(x, z, (x + 2 * z) as f32);
+            }
+        }
+        heights
+    }
+
+    fn build_fixture(name: &str) -> ChunkMeshes {
+        let bytes = fs::read(fixture_path(name, "pbf")).expect("fixture should exist");
+        let heights = sloped_heightmap();
+        ChunkBuilder::new(&heights, &OpenFreeMapTheme)
+            .with_seed(42)
+            .build(bytes)
+            .expect("fixture should parse")
+    }
+
+    fn round(value: f32) -> f64 {
+        (value as f64 * 1e4).round() / 1e4
+    }
+
+    fn summarize_mesh(mesh: &Mesh) -> Value {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("mesh should have positions");
+        };
+        let min = positions.iter().copied().map(Vec3::from).reduce(Vec3::min);
+        let max = positions.iter().copied().map(Vec3::from).reduce(Vec3::max);
+        json!({
+            "vertices": positions.len(),
+            "indices": mesh.indices().map_or(0, |indices| indices.len()),
+            "min": min.map(|v| v.to_array().map(round)),
+            "max": max.map(|v| v.to_array().map(round)),
+        })
+    }
+
+    /// The properties of a feature are stored in a hash map, so their order is not stable
+    fn get_sorted_tags(feature: &FeatureMetadata) -> Vec<String> {
+        let mut tags: Vec<String> = feature
+            .tags
+            .iter()
+            .map(|tag| format!("{}={}", tag.key, tag.val))
+            .collect();
+        tags.sort();
+        tags
+    }
+
+    /// The number of point features of each layer, only places should have them
+    fn summarize_points(name: &str) -> Value {
+        let bytes = fs::read(fixture_path(name, "pbf")).expect("fixture should exist");
+        let mut points: BTreeMap<String, usize> = BTreeMap::new();
+        for (_, layer, polygon) in parse_pbf(bytes).expect("fixture should parse") {
+            if polygon.len() == 1 {
+                *points.entry(format!("{layer:?}")).or_default() += 1;
+            }
+        }
+        json!(points)
+    }
+
+    fn summarize(meshes: &ChunkMeshes) -> Value {
+        json!({
+            "strokes": meshes.strokes.as_ref().map(summarize_mesh),
+            "buildings": meshes.buildings.as_ref().map(summarize_mesh),
+            "water": meshes.water.as_ref().map(summarize_mesh),
+            "airport": {
+                "surfaces": meshes.airport.surfaces.as_ref().map(summarize_mesh),
+                "lights": meshes.airport.lights.len(),
+                "runways": meshes.airport.runways.iter().map(|runway| json!({
+                    "designators": runway.designators,
+                    "thresholds": runway.thresholds.map(|point| point.to_array().map(round)),
+                    "width": round(runway.width),
+                })).collect::<Vec<_>>(),
+            },
+            "boundaries": meshes.boundaries.as_ref().map(summarize_mesh),
+            "places": meshes.places.iter().map(|(label, position)| json!({
+                "name": label.name,
+                "class": format!("{:?}", label.class),
+                "position": position.to_array().map(round),
+            })).collect::<Vec<_>>(),
+            "lights": meshes.lights.len(),
+            "features": meshes.features.iter().map(|feature| json!({
+                "kind": format!("{:?}", feature.kind),
+                "layer": format!("{:?}", feature.layer),
+                "tags": get_sorted_tags(feature),
+                "anchor": feature.anchor.to_array().map(round),
+                "height": feature.height.map(round),
+            })).collect::<Vec<_>>(),
+        })
+    }
+
+    /// Compares the output for each fixture to `assets/osm/<fixture>.golden.json`.
+    ///
+    /// Set `UPDATE_GOLDEN=1` to (re)generate the golden files after an intended change, and review
+    /// them before committing. A missing golden file fails the test.
+    #[test]
+    fn test_golden_chunks() {
+        let update = env::var("UPDATE_GOLDEN").is_ok_and(|value| value == "1");
+
+        for name in FIXTURES {
+            let mut summary = summarize(&build_fixture(name));
+            summary["points"] = summarize_points(name);
+            let golden_path = fixture_path(name, "golden.json");
+
+            if update {
+                fs::write(
+                    &golden_path,
+                    serde_json::to_string_pretty(&summary).unwrap(),
+                )
+                .expect("could not write golden file");
+                continue;
+            }
+
+            let golden = fs::read_to_string(&golden_path).unwrap_or_else(|_| {
+                panic!(
+                    "Golden file {} is missing, run with UPDATE_GOLDEN=1 to generate it",
+                    golden_path.display()
+                )
+            });
+            let golden: Value = serde_json::from_str(&golden).unwrap();
+            assert!(
+                golden == summary,
+                "Output for fixture {name} differs from {}, run with UPDATE_GOLDEN=1 if this is intended",
+                golden_path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn test_only_places_are_points() {
+        for name in FIXTURES {
+            let summary = summarize_points(name);
+            let layers: Vec<&String> = summary.as_object().unwrap().keys().collect();
+            assert!(
+                layers.iter().all(|layer| *layer == "Place"),
+                "fixture {name} has point features in {layers:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_build_is_deterministic() {
+        for name in FIXTURES {
+            assert_eq!(
+                summarize(&build_fixture(name)),
+                summarize(&build_fixture(name))
+            );
+        }
+    }
+
+    #[test]
+    fn test_features_are_placed_on_terrain() {
+        let heights = sloped_heightmap();
+        for name in FIXTURES {
+            let meshes = build_fixture(name);
+            assert!(
+                !meshes.features.is_empty(),
+                "fixture {name} has no features"
+            );
+            assert_eq!(
+                meshes.lights.len(),
+                meshes
+                    .features
+                    .iter()
+                    .filter(|feature| feature.kind == FeatureKind::Stroke)
+                    .count()
+            );
+
+            for feature in &meshes.features {
+                let expected =
+                    sample_heightmap(&heights, feature.anchor.with_y(0.0)).unwrap_or(0.0);
+                assert!((feature.anchor.y - expected).abs() < 1e-3);
+            }
+        }
+    }
+}
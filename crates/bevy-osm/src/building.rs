@@ -6,7 +6,6 @@ use geo::{LineString, Winding};
 use geo_types::Polygon;
 use lyon::geom::Point;
 use rand::RngExt;
-use rand::rngs::ThreadRng;
 use std::f32::consts::FRAC_PI_2;
 use std::ops::Sub;
 
@@ -33,7 +32,7 @@ impl Building {
 pub fn polygon_building(
     building_instruction: &BuildingInstruction,
     polygon: Vec<Point<f32>>,
-    rng: &mut ThreadRng,
+    rng: &mut impl RngExt,
 ) -> Building {
     let origin = polygon[0];
     let mut polygon = Polygon::new(
@@ -1,13 +1,17 @@
-use std::{env, fs::File, io::Write, path::Path};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 
 use bevy::log::debug;
-use dotenvy::dotenv;
 use ehttp::Response;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    chunk::{Chunk, ensure_cache_dir_exists, get_chunk_for_coord},
-    config::{OSMConfig, RasterTileSource},
+    chunk::Chunk,
+    config::RasterTileSource,
+    performance::LOADING_COUNTERS,
+    storage::{TILE_ASSET_SOURCE, tile_storage},
 };
 use bevy::prelude::*;
 
@@ -15,92 +19,141 @@ const ELEVATION_BASE_URL: &str = "https://tiles.mapterhorn.com";
 const VECTOR_TILES_VERSION: &str = "20260621_080001_pt";
 const VECTOR_TILES_BASE_URL: &str = "https://tiles.openfreemap.org/planet/20260621_080001_pt";
 
-pub fn get_osm_raster_cache_path(chunk: &Chunk, config: &OSMConfig) -> String {
+/// Key of a raster tile in the [`tile_storage`]
+pub fn get_osm_raster_cache_path(chunk: &Chunk, source: &RasterTileSource) -> String {
     let (z, x, y) = (chunk.z, chunk.x, chunk.y);
-    let name = config.raster_tile_source.get_name();
-    let extension = config.raster_tile_source.get_extension();
-    format!("assets/cache/{name}/{z}/{x}/{y}.{extension}")
+    let name = source.get_name();
+    let extension = source.get_extension();
+    format!("{name}/{z}/{x}/{y}.{extension}")
 }
-pub fn get_osm_raster_cache_path_bevy(chunk: &Chunk, config: &OSMConfig) -> String {
-    let (z, x, y) = (chunk.z, chunk.x, chunk.y);
-    let name = config.raster_tile_source.get_name();
-    let extension = config.raster_tile_source.get_extension();
-    format!("cache/{name}/{z}/{x}/{y}.{extension}")
+/// Asset path of a raster tile
+pub fn get_osm_raster_cache_path_bevy(chunk: &Chunk, source: &RasterTileSource) -> String {
+    let key = get_osm_raster_cache_path(chunk, source);
+    format!("{TILE_ASSET_SOURCE}://{key}")
 }
 pub fn get_token_cache_path(source: &RasterTileSource) -> String {
     let name = source.get_name();
-    format!("assets/cache/{name}/token.json")
+    format!("{name}/token.json")
 }
 pub fn get_osm_cache_path(chunk: &Chunk) -> String {
     let (z, x, y) = (chunk.z, chunk.x, chunk.y);
-    format!("assets/cache/osm/{z}/{x}/{y}.osm")
+    format!("osm/{z}/{x}/{y}.osm")
 }
+/// Key of an elevation tile in the [`tile_storage`]
 pub fn get_elevation_cache_path(chunk: &Chunk) -> String {
     let (z, x, y) = (chunk.z, chunk.x, chunk.y);
-    format!("assets/cache/elevation/{z}/{x}/{y}.webp")
+    format!("elevation/{z}/{x}/{y}.webp")
 }
+/// Asset path of an elevation tile
 pub fn get_elevation_cache_path_bevy(chunk: &Chunk) -> String {
-    let (z, x, y) = (chunk.z, chunk.x, chunk.y);
-    format!("cache/elevation/{z}/{x}/{y}.webp")
+    let key = get_elevation_cache_path(chunk);
+    format!("{TILE_ASSET_SOURCE}://{key}")
 }
+/// Key of a vector tile in the [`tile_storage`]
 pub fn get_openfreemap_cache_path(chunk: &Chunk) -> String {
     let (z, x, y) = (chunk.z, chunk.x, chunk.y);
-    format!("assets/cache/openfreemap/{VECTOR_TILES_VERSION}/{z}/{x}/{y}.pbf")
+    format!("openfreemap/{VECTOR_TILES_VERSION}/{z}/{x}/{y}.pbf")
 }
 
+/// Tracks the tile downloads that belong to a single chunk.
+#[derive(Debug, Clone, Default)]
+pub struct DownloadProgress {
+    pending: Arc<AtomicUsize>,
+    failed: Arc<AtomicBool>,
+}
+
+impl DownloadProgress {
+    pub fn is_finished(&self) -> bool {
+        self.pending.load(Ordering::Acquire) == 0
+    }
+    pub fn has_failed(&self) -> bool {
+        self.failed.load(Ordering::Acquire)
+    }
+    /// A progress that shares the pending downloads, but of which failures do not fail this one.
+    ///
+    /// Used for tiles a chunk can be loaded without, such as its raster overlays.
+    pub fn optional(&self) -> Self {
+        Self {
+            pending: self.pending.clone(),
+            failed: default(),
+        }
+    }
+    fn start(&self) {
+        self.pending.fetch_add(1, Ordering::AcqRel);
+    }
+    fn finish(&self, success: bool) {
+        if !success {
+            self.failed.store(true, Ordering::Release);
+        }
+        self.pending.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Downloads a tile if it is not cached yet.
+///
+/// `error_handler` returns whether the cached tile is usable after handling the error.
 fn cache_tile_for_chunk(
-    path_str: String,
+    key: String,
     url: String,
-    error_handler: impl 'static + Send + FnOnce(String, Result<Response, String>),
+    progress: &DownloadProgress,
+    error_handler: impl 'static + Send + FnOnce(String, Result<Response, String>) -> bool,
 ) {
-    let path = Path::new(&path_str);
-    ensure_cache_dir_exists(path);
+    let cached = tile_storage().contains(&key);
+    LOADING_COUNTERS.record_cache_lookup(cached);
 
-    if !path.exists() {
+    if !cached {
         let request = ehttp::Request::get(url.clone());
         debug!("Downloading tile for {url}");
 
+        let progress = progress.clone();
+        progress.start();
+
         ehttp::fetch(request, move |response| {
-            let path = Path::new(&path_str);
-            if let Ok(success) = &response
+            let success = if let Ok(success) = &response
                 && success.ok
             {
-                File::create(path)
-                    .unwrap()
-                    .write_all(&success.bytes)
-                    .expect("Could not write to tile cache");
+                LOADING_COUNTERS.record_download(success.bytes.len());
+                match tile_storage().write(&key, &success.bytes) {
+                    Ok(()) => true,
+                    Err(err) => {
+                        error!("Could not write {key} to the tile cache: {err}");
+                        false
+                    }
+                }
             } else {
-                error_handler(path_str, response);
-            }
+                error_handler(key, response)
+            };
+            progress.finish(success);
         });
     }
 }
 
-pub fn cache_elevation_for_chunk(chunk: &Chunk) {
+pub fn cache_elevation_for_chunk(chunk: &Chunk, progress: &DownloadProgress) {
     let (z, x, y) = (chunk.z, chunk.x, chunk.y);
     let url = format!("{ELEVATION_BASE_URL}/{z}/{x}/{y}.webp");
 
-    let on_error = move |path_str, _| {
-        let path = Path::new(&path_str);
-
-        File::create(path)
-            .unwrap()
-            .write_all(include_bytes!("../../../assets/osm/empty-tile.webp"))
+    let on_error = move |key: String, _| {
+        tile_storage()
+            .write(&key, include_bytes!("../../../assets/osm/empty-tile.webp"))
             .expect("Could not write to tile cache");
+        true
     };
 
-    let path_str = get_elevation_cache_path(chunk);
-    cache_tile_for_chunk(path_str, url, on_error);
+    let key = get_elevation_cache_path(chunk);
+    cache_tile_for_chunk(key, url, progress, on_error);
 }
 
-pub fn cache_vector_tile_for_chunk(chunk: &Chunk) {
+pub fn cache_vector_tile_for_chunk(chunk: &Chunk, progress: &DownloadProgress) {
     let (z, x, y) = (chunk.z, chunk.x, chunk.y);
     let url = format!("{VECTOR_TILES_BASE_URL}/{z}/{x}/{y}.pbf");
 
-    let on_error = move |s, r| error!("Could not download vector tile for {}: `{:?}`", s, r);
+    let on_error = move |s, r| {
+        error!("Could not download vector tile for {}: `{:?}`", s, r);
+        false
+    };
 
-    let path_str = get_openfreemap_cache_path(chunk);
-    cache_tile_for_chunk(path_str, url, on_error);
+    let key = get_openfreemap_cache_path(chunk);
+    cache_tile_for_chunk(key, url, progress, on_error);
 }
 
 #[derive(Debug)]
@@ -116,13 +169,12 @@ fn get_download_url(chunk: &Chunk, source: &RasterTileSource) -> Result<String,
         RasterTileSource::CesiumGoogleSatellite
         | RasterTileSource::CesiumGoogleRoadmaps
         | RasterTileSource::CesiumGoogleContour => {
-            dotenv().expect("Could not read .env");
-
             // TODO: don't read file for every tile fetch
-            let file = File::open(get_token_cache_path(source))
+            let bytes = tile_storage()
+                .read(&get_token_cache_path(source))
                 .or(Err(DownloadUrlError::TokenFileAbsent))?;
             let secrets: CesiumTokenResponse =
-                serde_json::from_reader(file).or(Err(DownloadUrlError::TokenFileInvalid))?;
+                serde_json::from_slice(&bytes).or(Err(DownloadUrlError::TokenFileInvalid))?;
 
             let asset_id = source.get_cesium_asset_id();
             let key = secrets.options.key;
@@ -138,13 +190,20 @@ fn get_download_url(chunk: &Chunk, source: &RasterTileSource) -> Result<String,
         RasterTileSource::OSMDefault | RasterTileSource::Debug => {
             Ok(format!("https://tile.openstreetmap.org/{z}/{x}/{y}.png"))
         }
+        RasterTileSource::Custom { url, .. } => Ok(url
+            .replace("{z}", &z.to_string())
+            .replace("{x}", &x.to_string())
+            .replace("{y}", &y.to_string())),
     }
 }
 
-pub fn cache_raster_tile_for_chunk(chunk: &Chunk, config: &OSMConfig) {
-    let path_str = get_osm_raster_cache_path(chunk, config);
+pub fn cache_raster_tile_for_chunk(
+    chunk: &Chunk,
+    source: &RasterTileSource,
+    progress: &DownloadProgress,
+) {
+    let key = get_osm_raster_cache_path(chunk, source);
 
-    let download_url = get_download_url(chunk, &config.raster_tile_source);
     let error_handler = |_, res: Result<Response, String>| {
         match res {
             Ok(res) => {
@@ -158,17 +217,22 @@ pub fn cache_raster_tile_for_chunk(chunk: &Chunk, config: &OSMConfig) {
                 error!("Raster tile unknown download error: {:?}", err);
             }
         };
+        false
     };
 
-    if let Ok(download_url) = download_url {
-        cache_tile_for_chunk(path_str, download_url, error_handler);
-    } else {
+    let download_url = get_download_url(chunk, source).or_else(|_| {
         // Try again
-        get_new_session(&config.raster_tile_source);
-
-        let download_url = get_download_url(chunk, &config.raster_tile_source)
-            .expect("Could not get session after retrying");
-        cache_tile_for_chunk(path_str, download_url, error_handler);
+        get_new_session(source);
+        get_download_url(chunk, source)
+    });
+    match download_url {
+        Ok(download_url) => cache_tile_for_chunk(key, download_url, progress, error_handler),
+        // In the browser the session is requested in the background, so the first tiles fail.
+        Err(err) => {
+            error!("Could not get session for raster tile: {err:?}");
+            progress.start();
+            progress.finish(false);
+        }
     }
 }
 
@@ -191,48 +255,105 @@ struct CesiumTokenResponse {
     options: CesiumTokenOptions,
 }
 
-pub fn get_new_session(source: &RasterTileSource) {
-    let asset_id = source.get_cesium_asset_id();
+/// The Cesium access token, from the environment or `.env`
+#[cfg(not(target_arch = "wasm32"))]
+fn get_cesium_access_token() -> Option<String> {
+    // The token may also be set in the environment without a `.env` file
+    let _ = dotenvy::dotenv();
+    std::env::var("CESIUM_ACCESS_TOKEN").ok()
+}
+
+/// The Cesium access token, which is embedded at compile time because the browser has no
+/// environment
+#[cfg(target_arch = "wasm32")]
+fn get_cesium_access_token() -> Option<String> {
+    option_env!("CESIUM_ACCESS_TOKEN").map(str::to_string)
+}
 
-    let binding = get_token_cache_path(source);
-    let path = Path::new(&binding);
-    ensure_cache_dir_exists(path);
+/// Stores the session of a token response
+fn store_session(
+    source: &RasterTileSource,
+    response: &ehttp::Result<Response>,
+) -> Result<(), String> {
+    let success = response.as_ref().map_err(Clone::clone)?;
+    if !success.ok {
+        return Err(format!("[{}] {:?}", success.status, success.text()));
+    }
 
-    let access_token = env::var("CESIUM_ACCESS_TOKEN").expect("Could not read CESIUM_ACCESS_TOKEN");
+    let json = success
+        .json::<CesiumTokenResponse>()
+        .map_err(|err| format!("Received invalid JSON: {err}"))?;
+    let bytes = serde_json::to_vec_pretty(&json)
+        .map_err(|err| format!("Could not serialize token.json: {err}"))?;
+    tile_storage()
+        .write(&get_token_cache_path(source), &bytes)
+        .map_err(|err| format!("Could not write token.json: {err}"))?;
+
+    info!("saved new token.json");
+    Ok(())
+}
+
+/// Requests a new Cesium session. Natively this blocks until the session is stored, in the
+/// browser the request finishes in the background.
+pub fn get_new_session(source: &RasterTileSource) {
+    let asset_id = source.get_cesium_asset_id();
+    let Some(access_token) = get_cesium_access_token() else {
+        error!("CESIUM_ACCESS_TOKEN is not set, Cesium tiles can not be downloaded");
+        return;
+    };
     let token_url =
         format!("https://api.cesium.com/v1/assets/{asset_id}/endpoint?access_token={access_token}");
+    let request = ehttp::Request::get(token_url);
 
-    let response = ehttp::fetch_blocking(&ehttp::Request::get(token_url));
+    // Without a session the raster tiles fail to download, and the chunks are retried later.
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Err(err) = store_session(source, &ehttp::fetch_blocking(&request)) {
+        error!("Could not get new session from Cesium: {err}");
+    }
 
-    if let Ok(success) = &response
-        && success.ok
+    #[cfg(target_arch = "wasm32")]
     {
-        let json = success
-            .json::<CesiumTokenResponse>()
-            .expect("Received invalid JSON when fetching new Cesium session");
-        let file =
-            File::create(get_token_cache_path(source)).expect("Could not open token.json file");
-        serde_json::to_writer_pretty(file, &json).expect("Could not write to token.json file");
-
-        info!("saved new token.json");
-    } else {
-        panic!("Could not get new session from Cesium")
+        // Every tile that is requested before the session arrives asks for one.
+        static SESSION_PENDING: AtomicBool = AtomicBool::new(false);
+        if SESSION_PENDING.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let source = source.clone();
+        ehttp::fetch(request, move |response| {
+            if let Err(err) = store_session(&source, &response) {
+                error!("Could not get new session from Cesium: {err}");
+            }
+            SESSION_PENDING.store(false, Ordering::Release);
+        });
     }
 }
 
 pub fn ensure_session_is_valid(source: &RasterTileSource) {
-    let sampled_chunk = get_chunk_for_coord(0.0, 0.0, 0);
+    if !source.is_cesium() {
+        return;
+    }
 
-    if let Ok(download_url) = get_download_url(&sampled_chunk, source) {
-        let response = ehttp::fetch_blocking(&ehttp::Request::get(download_url));
+    // Blocking requests are not possible in the browser, where the storage starts out empty.
+    #[cfg(target_arch = "wasm32")]
+    if !tile_storage().contains(&get_token_cache_path(source)) {
+        get_new_session(source);
+    }
 
-        if let Ok(success) = &response
-            && success.ok
-        {
-            // Session is still valid
-            return;
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let sampled_chunk = crate::chunk::get_chunk_for_coord(0.0, 0.0, 0);
+
+        if let Ok(download_url) = get_download_url(&sampled_chunk, source) {
+            let response = ehttp::fetch_blocking(&ehttp::Request::get(download_url));
+
+            if let Ok(success) = &response
+                && success.ok
+            {
+                // Session is still valid
+                return;
+            }
         }
-    }
 
-    get_new_session(source);
+        get_new_session(source);
+    }
 }
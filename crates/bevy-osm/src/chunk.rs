@@ -1,9 +1,4 @@
-use std::{
-    f32::consts::PI,
-    f64::consts::PI as PI_64,
-    fs::{self},
-    path::Path,
-};
+use std::{f32::consts::PI, f64::consts::PI as PI_64};
 
 use bevy::math::ops::{atan, powf, sinh};
 use bevy::prelude::*;
@@ -14,10 +9,6 @@ use crate::location::Location;
 // Assume at equator (110 km = 1 degree of longitude)
 pub const LAT_LON_TO_METERS_CONVERSION: Vec2 = Vec2::splat(1.1e5);
 
-pub fn ensure_cache_dir_exists(path: &Path) {
-    fs::create_dir_all(Path::new(path).parent().unwrap())
-        .expect("Could not create cache directory");
-}
 #[derive(Debug, PartialEq, Clone, Component)]
 pub struct Chunk {
     pub x: i32,
@@ -68,6 +59,11 @@ impl Chunk {
             }
         }
     }
+    /// A seed that is unique for every chunk, for procedural details that should not change
+    /// between runs
+    pub fn get_seed(&self) -> u64 {
+        ((self.z as u64) << 56) ^ ((self.x as u32 as u64) << 28) ^ (self.y as u32 as u64)
+    }
     pub fn get_rect(&self) -> Rect {
         Rect::new(
             (self.x) as f32,
@@ -0,0 +1,355 @@
+//! Collision geometry for the terrain and buildings of loaded chunks.
+//!
+//! Colliders are plain components on the chunk entities and their children, so they are streamed
+//! in and out together with the quadtree. [`Colliders`] answers ray casts and height queries
+//! without requiring a physics engine.
+//!
+//! Heights are either a `ground_y`, a y coordinate in chunk or world space, or an `elevation`, in
+//! meters above sea level. They differ by [`crate::elevation::HEIGHT_OFFSET`].
+
+use std::sync::Arc;
+
+use bevy::{
+    ecs::system::SystemParam,
+    mesh::{Indices, VertexAttributeValues},
+    prelude::*,
+};
+use bevy_terrain::{mesh::HeightMap, quadtree::QuadTreeNodeComponent};
+
+use crate::{chunk::lat_lon_to_world, config::OSMConfig, elevation::world_y_to_elevation};
+
+/// Number of bisection steps used to refine a ray hit on a heightfield
+const HEIGHTFIELD_REFINE_STEPS: usize = 16;
+
+/// Terrain collider of a chunk, in the local space of the chunk.
+#[derive(Component, Debug, Clone)]
+pub struct HeightFieldCollider {
+    heights: Arc<HeightMap>,
+    min_height: f32,
+    max_height: f32,
+}
+
+impl HeightFieldCollider {
+    pub fn new(heights: Arc<HeightMap>) -> Self {
+        let vertex_count = heights.vertex_count();
+        let (min_height, max_height) = (0..=vertex_count.x)
+            .flat_map(|x| (0..=vertex_count.y).map(move |z| (x, z)))
+            .map(|(x, z)| heights.get(x, z))
+            .fold((f32::MAX, f32::MIN), |(min, max), h| {
+                (min.min(h), max.max(h))
+            });
+
+        Self {
+            heights,
+            min_height,
+            max_height,
+        }
+    }
+
+    /// Returns the y of the ground in chunk space at a position in chunk space, or `None` if it
+    /// lies outside the chunk
+    pub fn ground_y(&self, position: Vec2) -> Option<f32> {
+        Rect::from_center_size(Vec2::ZERO, Vec2::ONE)
+            .contains(position)
+            .then(|| self.heights.sample(position))
+    }
+
+    /// Casts a ray in chunk space and returns the ray parameter `t` of the first hit, such that
+    /// the hit is at `origin + t * direction`.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_t: f32) -> Option<f32> {
+        let (t_enter, t_exit) = ray_aabb_intersection(
+            origin,
+            direction,
+            Vec3::new(-0.5, self.min_height, -0.5),
+            Vec3::new(0.5, self.max_height, 0.5),
+        )?;
+        let t_exit = t_exit.min(max_t);
+        if t_enter > t_exit {
+            return None;
+        }
+
+        let height_above_terrain = |t: f32| {
+            let point = origin + t * direction;
+            point.y - self.heights.sample(point.xz())
+        };
+        if height_above_terrain(t_enter) <= 0.0 {
+            return Some(t_enter);
+        }
+
+        // March with steps of half a cell, so no triangle is skipped.
+        let horizontal_length = direction.xz().length() * (t_exit - t_enter);
+        let cells = self.heights.vertex_count().max_element() as f32;
+        let steps = (horizontal_length * cells * 2.0).ceil().max(1.0) as usize;
+        let step = (t_exit - t_enter) / steps as f32;
+
+        let mut previous = t_enter;
+        for i in 1..=steps {
+            let t = t_enter + step * i as f32;
+            if height_above_terrain(t) <= 0.0 {
+                let (mut above, mut below) = (previous, t);
+                for _ in 0..HEIGHTFIELD_REFINE_STEPS {
+                    let middle = 0.5 * (above + below);
+                    if height_above_terrain(middle) <= 0.0 {
+                        below = middle;
+                    } else {
+                        above = middle;
+                    }
+                }
+                return Some(below);
+            }
+            previous = t;
+        }
+        None
+    }
+}
+
+/// Triangle mesh collider, in the local space of the entity it is attached to.
+#[derive(Component, Debug, Clone)]
+pub struct TriMeshCollider {
+    vertices: Vec<Vec3>,
+    triangles: Vec<[u32; 3]>,
+    min: Vec3,
+    max: Vec3,
+}
+
+impl TriMeshCollider {
+    /// Builds a collider from an indexed triangle list mesh
+    pub fn from_mesh(mesh: &Mesh) -> Option<Self> {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return None;
+        };
+        let indices: Vec<u32> = match mesh.indices()? {
+            Indices::U16(indices) => indices.iter().map(|&i| i as u32).collect(),
+            Indices::U32(indices) => indices.clone(),
+        };
+
+        let vertices: Vec<Vec3> = positions.iter().copied().map(Vec3::from).collect();
+        let min = vertices.iter().copied().reduce(Vec3::min)?;
+        let max = vertices.iter().copied().reduce(Vec3::max)?;
+
+        Some(Self {
+            vertices,
+            triangles: indices
+                .chunks_exact(3)
+                .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+                .collect(),
+            min,
+            max,
+        })
+    }
+
+    /// Casts a ray in local space and returns the ray parameter `t` of the first hit, such that
+    /// the hit is at `origin + t * direction`.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_t: f32) -> Option<f32> {
+        let (t_enter, _) = ray_aabb_intersection(origin, direction, self.min, self.max)?;
+        if t_enter > max_t {
+            return None;
+        }
+
+        self.triangles
+            .iter()
+            .filter_map(|[a, b, c]| {
+                ray_triangle_intersection(
+                    origin,
+                    direction,
+                    [
+                        self.vertices[*a as usize],
+                        self.vertices[*b as usize],
+                        self.vertices[*c as usize],
+                    ],
+                )
+            })
+            .filter(|t| *t <= max_t)
+            .min_by(f32::total_cmp)
+    }
+}
+
+/// Returns the range of the ray parameter for which the ray is inside the box
+fn ray_aabb_intersection(
+    origin: Vec3,
+    direction: Vec3,
+    min: Vec3,
+    max: Vec3,
+) -> Option<(f32, f32)> {
+    let inverse_direction = direction.recip();
+    let t0 = (min - origin) * inverse_direction;
+    let t1 = (max - origin) * inverse_direction;
+    let t_enter = t0.min(t1).max_element().max(0.0);
+    let t_exit = t0.max(t1).min_element();
+    (t_enter <= t_exit).then_some((t_enter, t_exit))
+}
+
+/// Möller–Trumbore ray-triangle intersection, hits on both sides of the triangle
+fn ray_triangle_intersection(origin: Vec3, direction: Vec3, triangle: [Vec3; 3]) -> Option<f32> {
+    let edge1 = triangle[1] - triangle[0];
+    let edge2 = triangle[2] - triangle[0];
+    let p = direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let inverse_determinant = determinant.recip();
+    let s = origin - triangle[0];
+    let u = s.dot(p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = direction.dot(q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(q) * inverse_determinant;
+    (t >= 0.0).then_some(t)
+}
+
+/// A hit returned by [`Colliders::raycast`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    pub entity: Entity,
+    pub point: Vec3,
+    pub distance: f32,
+}
+
+/// Queries the colliders of all loaded chunks, in world space.
+#[derive(SystemParam)]
+pub struct Colliders<'w, 's> {
+    heightfields: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static HeightFieldCollider,
+            &'static GlobalTransform,
+            &'static QuadTreeNodeComponent,
+        ),
+    >,
+    meshes: Query<'w, 's, (Entity, &'static TriMeshCollider, &'static GlobalTransform)>,
+    config: Res<'w, OSMConfig>,
+}
+
+impl Colliders<'_, '_> {
+    /// Returns the world y of the terrain at a world position on the XZ-plane.
+    ///
+    /// If chunks of multiple LODs overlap, the most detailed one is used.
+    pub fn ground_y_at_world(&self, position: Vec2) -> Option<f32> {
+        self.heightfields
+            .iter()
+            .filter_map(|(_, collider, transform, node)| {
+                let world_to_local = transform.affine().inverse();
+                let local = world_to_local.transform_point3(Vec3::new(position.x, 0.0, position.y));
+                let height = collider.ground_y(local.xz())?;
+                let world = transform.transform_point(Vec3::new(local.x, height, local.z));
+                Some((node.lod, world.y))
+            })
+            .max_by_key(|(lod, _)| *lod)
+            .map(|(_, height)| height)
+    }
+
+    /// Returns the elevation of the terrain above sea level at a coordinate in degrees (meters)
+    pub fn elevation_at(&self, lat: f32, lon: f32) -> Option<f32> {
+        let (x, z) = lat_lon_to_world(Vec2::new(lat, lon), self.config.location.get_world_center());
+        self.ground_y_at_world(Vec2::new(x as f32, z as f32))
+            .map(world_y_to_elevation)
+    }
+
+    /// Returns the closest hit of a ray with the terrain or buildings, within `max_distance`
+    pub fn raycast(&self, ray: Ray3d, max_distance: f32) -> Option<RayHit> {
+        let local_raycast = |transform: &GlobalTransform| {
+            let world_to_local = transform.affine().inverse();
+            (
+                world_to_local.transform_point3(ray.origin),
+                world_to_local.transform_vector3(*ray.direction),
+            )
+        };
+
+        let terrain_hits =
+            self.heightfields
+                .iter()
+                .filter_map(|(entity, collider, transform, _)| {
+                    let (origin, direction) = local_raycast(transform);
+                    Some((entity, collider.raycast(origin, direction, max_distance)?))
+                });
+        let mesh_hits = self
+            .meshes
+            .iter()
+            .filter_map(|(entity, collider, transform)| {
+                let (origin, direction) = local_raycast(transform);
+                Some((entity, collider.raycast(origin, direction, max_distance)?))
+            });
+
+        // The local direction is not normalized, so `t` is the distance in world space.
+        terrain_hits
+            .chain(mesh_hits)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(entity, distance)| RayHit {
+                entity,
+                point: ray.get_point(distance),
+                distance,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sloped_heightfield() -> HeightFieldCollider {
+        let vertex_count = IVec2::splat(4);
+        let mut heights = HeightMap::new(vertex_count);
+        for x in -1..=vertex_count.x + 2 {
+            for z in -1..=vertex_count.y + 2 {
+                heights.set(x, z, x as f32);
+            }
+        }
+        HeightFieldCollider::new(Arc::new(heights))
+    }
+
+    #[test]
+    fn test_heightfield_ground_y() {
+        let collider = sloped_heightfield();
+        assert_eq!(collider.ground_y(Vec2::new(-0.5, 0.0)), Some(0.0));
+        assert_eq!(collider.ground_y(Vec2::new(0.0, 0.3)), Some(2.0));
+        assert_eq!(collider.ground_y(Vec2::new(0.5, -0.5)), Some(4.0));
+        assert_eq!(collider.ground_y(Vec2::new(0.6, 0.0)), None);
+    }
+
+    #[test]
+    fn test_heightfield_raycast() {
+        let collider = sloped_heightfield();
+
+        let t = collider
+            .raycast(Vec3::new(0.125, 10.0, 0.0), Vec3::NEG_Y, 100.0)
+            .unwrap();
+        assert!((t - 7.5).abs() < 1e-3, "{t}");
+
+        assert_eq!(
+            collider.raycast(Vec3::new(0.125, 10.0, 0.0), Vec3::Y, 100.0),
+            None
+        );
+        assert_eq!(
+            collider.raycast(Vec3::new(0.125, 10.0, 0.0), Vec3::NEG_Y, 5.0),
+            None
+        );
+    }
+
+    #[test]
+    fn test_trimesh_raycast() {
+        let mesh = Mesh::from(Cuboid::from_size(Vec3::ONE));
+        let collider = TriMeshCollider::from_mesh(&mesh).unwrap();
+
+        let t = collider
+            .raycast(Vec3::new(0.0, 0.0, -2.0), Vec3::Z, 10.0)
+            .unwrap();
+        assert!((t - 1.5).abs() < 1e-5, "{t}");
+
+        assert_eq!(
+            collider.raycast(Vec3::new(2.0, 0.0, -2.0), Vec3::Z, 10.0),
+            None
+        );
+    }
+}
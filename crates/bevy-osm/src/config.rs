@@ -1,8 +1,12 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::location::Location;
+use crate::{
+    layers::{RasterLayer, TerrainAnalysis},
+    location::Location,
+};
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum RasterTileSource {
     OSMDefault,
     CesiumGoogleSatellite,
@@ -10,9 +14,24 @@ pub enum RasterTileSource {
     CesiumGoogleContour,
     Transport,
     Debug,
+    /// A tile server of our own, `url` contains `{z}`, `{x}` and `{y}` placeholders
+    Custom {
+        name: String,
+        url: String,
+    },
 }
 
 impl RasterTileSource {
+    /// All sources except [`RasterTileSource::Custom`]
+    pub const BUILT_IN: [RasterTileSource; 6] = [
+        RasterTileSource::Debug,
+        RasterTileSource::CesiumGoogleSatellite,
+        RasterTileSource::CesiumGoogleRoadmaps,
+        RasterTileSource::CesiumGoogleContour,
+        RasterTileSource::OSMDefault,
+        RasterTileSource::Transport,
+    ];
+
     pub fn get_name(&self) -> String {
         match self {
             RasterTileSource::OSMDefault => "osm-default".into(),
@@ -21,7 +40,21 @@ impl RasterTileSource {
             RasterTileSource::CesiumGoogleContour => "cesium-google-contour".into(),
             RasterTileSource::Transport => "transport".into(),
             RasterTileSource::Debug => "debug".into(),
+            RasterTileSource::Custom { name, .. } => name.clone(),
+        }
+    }
+    /// Finds a built-in source by [`RasterTileSource::get_name`], a url with `{z}`, `{x}` and
+    /// `{y}` placeholders becomes a custom source
+    pub fn from_name(name: &str) -> Option<Self> {
+        if ["{z}", "{x}", "{y}"].iter().all(|key| name.contains(key)) {
+            return Some(RasterTileSource::Custom {
+                name: "custom".into(),
+                url: name.into(),
+            });
         }
+        Self::BUILT_IN
+            .into_iter()
+            .find(|source| source.get_name() == name)
     }
     pub fn get_extension(&self) -> String {
         match self {
@@ -31,8 +64,21 @@ impl RasterTileSource {
             RasterTileSource::CesiumGoogleContour => "jpg".into(),
             RasterTileSource::Transport => "png".into(),
             RasterTileSource::Debug => "".into(),
+            RasterTileSource::Custom { url, .. } => match url.rsplit_once('.') {
+                Some((_, extension @ ("jpg" | "jpeg" | "webp"))) => extension.into(),
+                _ => "png".into(),
+            },
         }
     }
+    /// Whether tiles are requested through a Cesium session
+    pub fn is_cesium(&self) -> bool {
+        matches!(
+            self,
+            RasterTileSource::CesiumGoogleSatellite
+                | RasterTileSource::CesiumGoogleRoadmaps
+                | RasterTileSource::CesiumGoogleContour
+        )
+    }
     pub fn get_cesium_asset_id(&self) -> String {
         match self {
             RasterTileSource::CesiumGoogleSatellite => "3830182".into(),
@@ -43,11 +89,19 @@ impl RasterTileSource {
     }
 }
 
-#[derive(Resource)]
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
 pub struct OSMConfig {
     pub location: Location,
     pub ui_visible: bool,
+    /// The opaque bottom layer of the terrain
     pub raster_tile_source: RasterTileSource,
+    /// Layers that are composited on top of the base layer, at most
+    /// [`crate::layers::MAX_RASTER_OVERLAYS`]
+    pub raster_overlays: Vec<RasterLayer>,
+    pub terrain_analysis: TerrainAnalysis,
+    /// Whether the names of places are shown
+    pub place_labels: bool,
 }
 
 impl Default for OSMConfig {
@@ -56,6 +110,9 @@ impl Default for OSMConfig {
             location: Location::Amsterdam,
             ui_visible: true,
             raster_tile_source: RasterTileSource::CesiumGoogleSatellite,
+            raster_overlays: Vec::new(),
+            terrain_analysis: TerrainAnalysis::default(),
+            place_labels: true,
         }
     }
 }
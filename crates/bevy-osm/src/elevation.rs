@@ -1,24 +1,34 @@
-use std::f32::consts::PI;
+use std::{f32::consts::PI, sync::Arc};
 
 use bevy::{
     color::palettes::css::{
         BLUE, FUCHSIA, GHOST_WHITE, GREEN, INDIAN_RED, INDIGO, LIMEGREEN, ORANGE, POWDER_BLUE, RED,
         SALMON, TEAL, WHITE,
     },
+    ecs::world::CommandQueue,
     math::Affine2,
+    platform::time::Instant,
+    tasks::{AsyncComputeTaskPool, Task},
 };
 use bevy_terrain::{
-    mesh::{HeightMap, build_mesh_data, iterate_mesh_vertices},
+    mesh::{ChunkHeightMap, HeightMap, build_mesh_data, iterate_mesh_vertices},
     quadtree::ChunkLoaded,
 };
 
 use crate::{
     chunk::Chunk,
     config::{OSMConfig, RasterTileSource},
+    layers::{TerrainLayers, TerrainMaterial},
+    performance::LOADING_COUNTERS,
+    scheduler::ChunkLoadState,
 };
 use bevy::prelude::*;
 
-const HEIGHT_OFFSET: f32 = 130.0;
+/// Elevation that lies at y = 0 in chunk space, so the terrain around the origin is close to y = 0
+/// (meters)
+pub const HEIGHT_OFFSET: f32 = 130.0;
+/// World y of the origin of the chunks, see [`bevy_terrain::mesh::rect_to_transform`]
+const CHUNK_Y: f32 = 1.0;
 pub const TILE_VERTEX_COUNT: i32 = 64;
 pub const TILE_PIXEL_COUNT: i32 = 512;
 const DOWNSAMPLE_FACTOR: i32 = TILE_PIXEL_COUNT / TILE_VERTEX_COUNT;
@@ -31,6 +41,19 @@ pub fn elevation_color_to_height_meters(c: Color) -> f32 {
         - HEIGHT_OFFSET
 }
 
+/// Converts a world y coordinate to meters above sea level. World y is what the terrain and
+/// camera transforms use, the quadtree is assumed to be at the origin like in
+/// [`crate::build_terrain_tile`].
+pub fn world_y_to_elevation(y: f32) -> f32 {
+    y - CHUNK_Y + HEIGHT_OFFSET
+}
+
+/// Converts meters above sea level to a world y coordinate, the inverse of
+/// [`world_y_to_elevation`]
+pub fn elevation_to_world_y(elevation: f32) -> f32 {
+    elevation + CHUNK_Y - HEIGHT_OFFSET
+}
+
 pub fn get_elevation_local(image: &Image, local_coords: IVec2) -> f32 {
     elevation_color_to_height_meters(
         image
@@ -43,11 +66,77 @@ pub fn get_elevation_local(image: &Image, local_coords: IVec2) -> f32 {
     )
 }
 
-fn debug_material(
-    materials: &mut ResMut<Assets<StandardMaterial>>,
-    chunk: &Chunk,
-) -> MeshMaterial3d<StandardMaterial> {
-    MeshMaterial3d(materials.add(StandardMaterial {
+/// Interpolates the elevation at a position in pixels of an elevation tile
+fn get_elevation_bilinear(image: &Image, pixel: Vec2) -> f32 {
+    let max = (TILE_PIXEL_COUNT - 1) as f32;
+    let pixel = pixel.clamp(Vec2::ZERO, Vec2::splat(max));
+    let min = pixel.floor();
+    let fraction = pixel - min;
+    let get = |x: f32, y: f32| {
+        elevation_color_to_height_meters(
+            image
+                .get_color_at(x.min(max) as u32, y.min(max) as u32)
+                .unwrap(),
+        )
+    };
+
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+    let top = lerp(get(min.x, min.y), get(min.x + 1.0, min.y), fraction.x);
+    let bottom = lerp(
+        get(min.x, min.y + 1.0),
+        get(min.x + 1.0, min.y + 1.0),
+        fraction.x,
+    );
+    lerp(top, bottom, fraction.y)
+}
+
+/// The terrain mesh task of a chunk. It lives on the chunk entity, so the task is cancelled when
+/// the chunk is despawned before it finishes.
+#[derive(Component)]
+pub struct ComputeElevation(pub Task<CommandQueue>);
+
+/// Samples the heights of all vertices of a chunk from its elevation tile
+pub fn build_heightmap(image: &Image) -> HeightMap {
+    let vertex_count = IVec2::splat(TILE_VERTEX_COUNT);
+    let mut heights = HeightMap::new(vertex_count);
+    heights.extend(iterate_mesh_vertices(vertex_count, Rect::EMPTY).map(
+        |(x_local, y_local, ..)| {
+            (
+                (x_local, y_local),
+                get_elevation_local(image, IVec2::new(x_local, y_local)),
+            )
+        },
+    ));
+    heights
+}
+
+/// Samples the heights of all vertices of a chunk from a sub-rectangle of an ancestor's elevation
+/// tile.
+///
+/// `rect` is in the normalized space of the tile, as returned by [`Chunk::get_rect_inside_parent`].
+pub fn build_heightmap_in_rect(image: &Image, rect: Rect) -> HeightMap {
+    let vertex_count = IVec2::splat(TILE_VERTEX_COUNT);
+    let mut heights = HeightMap::new(vertex_count);
+    heights.extend(iterate_mesh_vertices(vertex_count, Rect::EMPTY).map(
+        |(x_local, y_local, ..)| {
+            let uv = IVec2::new(x_local, y_local).as_vec2() / TILE_VERTEX_COUNT as f32;
+            let pixel = (rect.min + rect.size() * uv) * TILE_PIXEL_COUNT as f32;
+            ((x_local, y_local), get_elevation_bilinear(image, pixel))
+        },
+    ));
+    heights
+}
+
+/// Returns the elevation at a translation in chunk space, in which the chunk spans -0.5..0.5
+pub fn sample_heightmap(heights: &HeightMap, translation: Vec3) -> Option<f32> {
+    Rect::from_center_size(Vec2::ZERO, Vec2::ONE)
+        .contains(translation.xz())
+        .then(|| heights.sample(translation.xz()))
+}
+
+fn debug_material(chunk: &Chunk) -> StandardMaterial {
+    StandardMaterial {
         base_color: match chunk.z {
             11 => TEAL.into(),
             12 => FUCHSIA.into(),
@@ -64,43 +153,188 @@ fn debug_material(
             _ => WHITE.into(),
         },
         ..Default::default()
-    }))
+    }
+}
+
+/// The tiles that the terrain of a chunk is currently built from.
+///
+/// Until its own tiles have loaded, a chunk shows a sub-rectangle of the tiles of an ancestor.
+#[derive(Component, Debug, Clone)]
+pub struct ChunkImagery {
+    pub elevation: Handle<Image>,
+    pub raster: Handle<Image>,
+    /// Textures of the raster overlays, `None` for overlays that could not be loaded
+    pub overlays: Vec<Option<Handle<Image>>>,
+    /// The area of the chunk in the normalized space of the tiles
+    pub rect: Rect,
+}
+
+impl ChunkImagery {
+    pub fn from_chunk(chunk: &Chunk, overlays: Vec<Option<Handle<Image>>>) -> Self {
+        Self {
+            elevation: chunk.elevation.clone(),
+            raster: chunk.raster.clone(),
+            overlays,
+            rect: Rect::new(0.0, 0.0, 1.0, 1.0),
+        }
+    }
+
+    /// Returns the part of this imagery that covers a descendant of `chunk`
+    pub fn for_descendant(&self, chunk: &Chunk, descendant: &Chunk) -> Self {
+        let rect = descendant.get_rect_inside_parent(chunk.clone());
+        Self {
+            elevation: self.elevation.clone(),
+            raster: self.raster.clone(),
+            overlays: self.overlays.clone(),
+            rect: Rect::from_corners(
+                self.rect.min + rect.min * self.rect.size(),
+                self.rect.min + rect.max * self.rect.size(),
+            ),
+        }
+    }
+
+    pub fn is_own(&self) -> bool {
+        self.rect == Rect::new(0.0, 0.0, 1.0, 1.0)
+    }
+}
+
+/// The terrain entity of a chunk that was built from the imagery of an ancestor, which is
+/// replaced once the chunk's own tiles have loaded
+#[derive(Component, Debug)]
+pub struct ChunkFallback(pub Entity);
+
+fn terrain_material(chunk: &Chunk, config: &OSMConfig, imagery: &ChunkImagery) -> TerrainMaterial {
+    let base = match config.raster_tile_source {
+        RasterTileSource::Debug => debug_material(chunk),
+        _ => StandardMaterial {
+            base_color_texture: Some(imagery.raster.clone()),
+            uv_transform: Affine2::from_translation(imagery.rect.min)
+                * Affine2::from_scale(imagery.rect.size())
+                * Affine2::from_angle_translation(PI * 0.5, Vec2::new(1.0, 0.0)),
+            perceptual_roughness: 0.8,
+            ..Default::default()
+        },
+    };
+    TerrainMaterial {
+        base,
+        extension: TerrainLayers::new(&imagery.overlays, imagery.rect, config),
+    }
 }
 
+/// Builds the terrain mesh of a chunk off the main thread.
+///
+/// Once the mesh has been added to the chunk, `on_loaded` is called with the heightmap so later
+/// stages can place objects on the terrain.
 pub fn spawn_elevation_meshes(
     commands: &mut Commands,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    materials: &mut ResMut<Assets<StandardMaterial>>,
-    heightmap: &Image,
+    heightmap: Image,
+    overlays: Vec<Option<Handle<Image>>>,
     entity: Entity,
     chunk: Chunk,
-    config: &Res<OSMConfig>,
+    config: &OSMConfig,
+    on_loaded: impl 'static + Send + FnOnce(&mut World, Arc<HeightMap>),
 ) {
-    let heights = iterate_mesh_vertices(IVec2::splat(TILE_VERTEX_COUNT), Rect::EMPTY)
-        .map(|(x_local, y_local, ..)| {
-            (
-                (x_local, y_local),
-                get_elevation_local(heightmap, IVec2::new(x_local, y_local)),
-            )
-        })
-        .collect::<HeightMap>();
-
-    let material = match config.as_ref().raster_tile_source {
-        RasterTileSource::Debug => debug_material(materials, &chunk),
-        _ => MeshMaterial3d(materials.add(StandardMaterial {
-            base_color_texture: Some(chunk.raster),
-            uv_transform: Affine2::from_angle_translation(PI * 0.5, Vec2::new(1.0, 0.0)),
-            perceptual_roughness: 0.8,
-            ..Default::default()
-        })),
-    };
+    let imagery = ChunkImagery::from_chunk(&chunk, overlays);
+    let material = terrain_material(&chunk, config, &imagery);
+
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        let start = Instant::now();
+        let heights = build_heightmap(&heightmap);
+        LOADING_COUNTERS.record_heightmap(start.elapsed());
+
+        let start = Instant::now();
+        let mesh = build_mesh_data(&heights, IVec2::splat(TILE_VERTEX_COUNT));
+        LOADING_COUNTERS.record_mesh_build(start.elapsed());
+        let heights = Arc::new(heights);
+
+        let mut command_queue = CommandQueue::default();
+        command_queue.push(move |world: &mut World| {
+            // If the chunk was despawned while the async task was running, discard
+            // the results to avoid a panic.
+            if world.get_entity(entity).is_err() {
+                return;
+            }
+
+            let mesh = world.resource_mut::<Assets<Mesh>>().add(mesh);
+            let material = world
+                .resource_mut::<Assets<TerrainMaterial>>()
+                .add(material);
+            let terrain = world.spawn((Mesh3d(mesh), MeshMaterial3d(material))).id();
+
+            world
+                .entity_mut(entity)
+                .add_child(terrain)
+                .insert((
+                    ChunkLoaded,
+                    ChunkLoadState::Loaded,
+                    ChunkHeightMap(heights.clone()),
+                    imagery,
+                ))
+                .remove::<ComputeElevation>();
+
+            if let Some(ChunkFallback(fallback)) = world.entity_mut(entity).take::<ChunkFallback>()
+            {
+                world.despawn(fallback);
+            }
+
+            #[cfg(feature = "colliders")]
+            world
+                .entity_mut(entity)
+                .insert(crate::collider::HeightFieldCollider::new(heights.clone()));
+
+            on_loaded(world, heights);
+        });
+        command_queue
+    });
+
+    commands.entity(entity).insert(ComputeElevation(task));
+}
+
+/// Builds a terrain mesh for a chunk from the imagery of an ancestor, so it can be shown before
+/// its own tiles have been downloaded.
+///
+/// The chunk is marked as loaded for the quadtree, but its [`ChunkLoadState`] is left alone so it
+/// keeps loading its own tiles.
+pub fn spawn_fallback_meshes(
+    commands: &mut Commands,
+    heightmap: Image,
+    imagery: ChunkImagery,
+    entity: Entity,
+    chunk: &Chunk,
+    config: &OSMConfig,
+) {
+    let material = terrain_material(chunk, config, &imagery);
+
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        let heights = build_heightmap_in_rect(&heightmap, imagery.rect);
+        let mesh = build_mesh_data(&heights, IVec2::splat(TILE_VERTEX_COUNT));
+        let heights = Arc::new(heights);
+
+        let mut command_queue = CommandQueue::default();
+        command_queue.push(move |world: &mut World| {
+            if world.get_entity(entity).is_err() {
+                return;
+            }
+
+            let mesh = world.resource_mut::<Assets<Mesh>>().add(mesh);
+            let material = world
+                .resource_mut::<Assets<TerrainMaterial>>()
+                .add(material);
+            let terrain = world.spawn((Mesh3d(mesh), MeshMaterial3d(material))).id();
+
+            world
+                .entity_mut(entity)
+                .add_child(terrain)
+                .insert((
+                    ChunkLoaded,
+                    ChunkHeightMap(heights),
+                    ChunkFallback(terrain),
+                    imagery,
+                ))
+                .remove::<ComputeElevation>();
+        });
+        command_queue
+    });
 
-    let mesh = commands
-        .spawn((
-            Mesh3d(meshes.add(build_mesh_data(heights, IVec2::splat(TILE_VERTEX_COUNT)))),
-            material,
-        ))
-        .id();
-    commands.entity(entity).insert(ChunkLoaded);
-    commands.entity(entity).add_child(mesh);
+    commands.entity(entity).insert(ComputeElevation(task));
 }
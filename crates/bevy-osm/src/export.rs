@@ -0,0 +1,622 @@
+//! Headless export of the terrain, buildings, roads and water of a region to a single glTF binary
+//! (`.glb`) or Wavefront OBJ file, for use in external tools such as Blender.
+//!
+//! Positions are in meters relative to the center of the region, with X pointing east, Y up and
+//! Z pointing south, like in the engine. Unlike in the engine, Y is the elevation above sea level. The georeference is stored in the `extras` of the glTF
+//! asset and in the header of the OBJ file.
+
+use std::{
+    fmt::{self, Display},
+    fs,
+    io::{self, Write},
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use bevy::{
+    asset::RenderAssetUsages,
+    image::{CompressedImageFormats, ImageSampler, ImageType},
+    mesh::{Indices, VertexAttributeValues},
+    prelude::*,
+};
+use bevy_terrain::mesh::HeightMap;
+use serde_json::json;
+
+use crate::{
+    builder::{ChunkBuilder, ChunkMeshes},
+    cache::{
+        DownloadProgress, cache_elevation_for_chunk, cache_vector_tile_for_chunk,
+        get_elevation_cache_path, get_openfreemap_cache_path,
+    },
+    chunk::{Chunk, LAT_LON_TO_METERS_CONVERSION, get_chunk_for_coord},
+    elevation::{HEIGHT_OFFSET, build_heightmap},
+    storage::tile_storage,
+    theme::OpenFreeMapTheme,
+};
+
+/// Height of roads above the terrain, to prevent z-fighting in external renderers
+const ROAD_HEIGHT_OFFSET: f32 = 0.2;
+
+/// A bounding box in lat, lon coordinates (degrees) and the zoom level of the tiles to export
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportRegion {
+    /// `min` is the (lat, lon) of the south-west corner, `max` of the north-east corner
+    pub bounds: Rect,
+    pub zoom: i8,
+}
+
+impl ExportRegion {
+    /// The origin of the exported scene in lat, lon coordinates (degrees)
+    pub fn origin(&self) -> Vec2 {
+        self.bounds.center()
+    }
+
+    /// All chunks that overlap with the bounding box
+    pub fn chunks(&self) -> Vec<Chunk> {
+        let north_west = get_chunk_for_coord(
+            self.bounds.max.x as f64,
+            self.bounds.min.y as f64,
+            self.zoom,
+        );
+        let south_east = get_chunk_for_coord(
+            self.bounds.min.x as f64,
+            self.bounds.max.y as f64,
+            self.zoom,
+        );
+
+        (north_west.x..=south_east.x)
+            .flat_map(|x| (north_west.y..=south_east.y).map(move |y| (x, y)))
+            .map(|(x, y)| Chunk {
+                x,
+                y,
+                z: self.zoom,
+                elevation: Handle::default(),
+                raster: Handle::default(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(io::Error),
+    InvalidElevationTile(String),
+    UnsupportedFormat(String),
+    /// Some tiles could not be downloaded, the export was written without them
+    IncompleteDownload,
+}
+
+impl Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Io(err) => write!(f, "I/O error: {err}"),
+            ExportError::InvalidElevationTile(path) => {
+                write!(f, "Could not decode elevation tile {path}")
+            }
+            ExportError::UnsupportedFormat(path) => {
+                write!(f, "Unsupported export format for {path}, use .glb or .obj")
+            }
+            ExportError::IncompleteDownload => {
+                write!(
+                    f,
+                    "Some tiles could not be downloaded, the export is incomplete"
+                )
+            }
+        }
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(err: io::Error) -> Self {
+        ExportError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportMaterial {
+    Terrain,
+    Buildings,
+    Roads,
+    Water,
+}
+
+impl ExportMaterial {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            ExportMaterial::Terrain => "terrain",
+            ExportMaterial::Buildings => "buildings",
+            ExportMaterial::Roads => "roads",
+            ExportMaterial::Water => "water",
+        }
+    }
+    /// Linear RGBA
+    pub fn get_base_color(&self) -> [f32; 4] {
+        match self {
+            ExportMaterial::Terrain => [0.25, 0.3, 0.2, 1.0],
+            ExportMaterial::Buildings => [0.3, 0.3, 0.3, 1.0],
+            ExportMaterial::Roads => [0.1, 0.1, 0.1, 1.0],
+            ExportMaterial::Water => [0.05, 0.15, 0.3, 1.0],
+        }
+    }
+    pub fn get_roughness(&self) -> f32 {
+        match self {
+            ExportMaterial::Terrain => 0.9,
+            ExportMaterial::Buildings => 0.7,
+            ExportMaterial::Roads => 0.8,
+            ExportMaterial::Water => 0.1,
+        }
+    }
+}
+
+/// The merged geometry of all chunks that share a material, in world space
+#[derive(Debug, Clone)]
+pub struct ExportMesh {
+    pub material: ExportMaterial,
+    pub positions: Vec<Vec3>,
+    pub indices: Vec<u32>,
+}
+
+impl ExportMesh {
+    pub fn new(material: ExportMaterial) -> Self {
+        Self {
+            material,
+            positions: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    /// Appends an indexed triangle list, after transforming it to world space
+    pub fn append(&mut self, mesh: &Mesh, transform: &Transform) {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return;
+        };
+
+        let offset = self.positions.len() as u32;
+        self.positions.extend(
+            positions
+                .iter()
+                .map(|position| transform.transform_point(Vec3::from(*position))),
+        );
+        match mesh.indices() {
+            Some(Indices::U16(indices)) => self
+                .indices
+                .extend(indices.iter().map(|i| offset + *i as u32)),
+            Some(Indices::U32(indices)) => self.indices.extend(indices.iter().map(|i| offset + i)),
+            None => self.indices.extend(offset..offset + positions.len() as u32),
+        }
+    }
+
+    /// Vertex normals, averaged over the triangles that share a vertex
+    pub fn compute_normals(&self) -> Vec<Vec3> {
+        let mut normals = vec![Vec3::ZERO; self.positions.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| self.positions[triangle[i] as usize]);
+            let normal = (b - a).cross(c - a);
+            for i in triangle {
+                normals[*i as usize] += normal;
+            }
+        }
+        normals
+            .into_iter()
+            .map(|normal| normal.try_normalize().unwrap_or(Vec3::Y))
+            .collect()
+    }
+}
+
+/// The geometry of a region, ready to be written to a file
+#[derive(Debug, Clone)]
+pub struct ExportScene {
+    /// The lat, lon coordinates (degrees) of the origin of the scene
+    pub origin: Vec2,
+    pub meshes: Vec<ExportMesh>,
+}
+
+impl ExportScene {
+    fn get_georeference(&self) -> serde_json::Value {
+        json!({
+            "origin_lat": self.origin.x,
+            "origin_lon": self.origin.y,
+            "meters_per_degree_lat": LAT_LON_TO_METERS_CONVERSION.x,
+            "meters_per_degree_lon": LAT_LON_TO_METERS_CONVERSION.y,
+            "axes": "x: east, y: up, z: south (meters)",
+            "vertical_datum": "y is the elevation above sea level (meters)",
+        })
+    }
+}
+
+/// Downloads the elevation and vector tiles of a region that are not cached yet, blocking until
+/// all downloads have finished.
+pub fn download_region(region: &ExportRegion) -> Result<(), ExportError> {
+    let progress = DownloadProgress::default();
+    for chunk in region.chunks() {
+        cache_elevation_for_chunk(&chunk, &progress);
+        cache_vector_tile_for_chunk(&chunk, &progress);
+    }
+    while !progress.is_finished() {
+        thread::sleep(Duration::from_millis(50));
+    }
+    if progress.has_failed() {
+        return Err(ExportError::IncompleteDownload);
+    }
+    Ok(())
+}
+
+/// Reads a cached elevation tile without an asset server
+fn read_elevation_tile(chunk: &Chunk) -> Result<Image, ExportError> {
+    let path = get_elevation_cache_path(chunk);
+    let bytes = tile_storage().read(&path)?;
+    Image::from_buffer(
+        &bytes,
+        ImageType::Extension("webp"),
+        CompressedImageFormats::NONE,
+        true,
+        ImageSampler::Default,
+        RenderAssetUsages::MAIN_WORLD,
+    )
+    .map_err(|_| ExportError::InvalidElevationTile(path))
+}
+
+/// Maps the local space of a chunk, which spans -0.5..0.5, to the meters of the scene. The
+/// heightmaps are relative to [`HEIGHT_OFFSET`], which is added back.
+fn get_chunk_transform(chunk: &Chunk, origin: Vec2) -> Transform {
+    let area = chunk.get_area_in_meters(origin);
+    Transform::from_translation(Vec3::new(area.center().x, HEIGHT_OFFSET, area.center().y))
+        .with_scale(Vec3::new(area.width(), 1.0, area.height()))
+}
+
+/// Places the vertices of a flat mesh on the terrain
+fn drape_mesh(mut mesh: Mesh, heights: &HeightMap, offset: f32) -> Mesh {
+    if let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+    {
+        for position in positions.iter_mut() {
+            position[1] += heights.sample(Vec2::new(position[0], position[2])) + offset;
+        }
+    }
+    mesh
+}
+
+/// Builds the geometry of a region from the tile cache, see [`download_region`].
+///
+/// Chunks without a cached vector tile only contribute terrain.
+pub fn build_region(region: &ExportRegion) -> Result<ExportScene, ExportError> {
+    let origin = region.origin();
+    let mut terrain = ExportMesh::new(ExportMaterial::Terrain);
+    let mut buildings = ExportMesh::new(ExportMaterial::Buildings);
+    let mut roads = ExportMesh::new(ExportMaterial::Roads);
+    let mut water = ExportMesh::new(ExportMaterial::Water);
+
+    for chunk in region.chunks() {
+        let heights = build_heightmap(&read_elevation_tile(&chunk)?);
+        let transform = get_chunk_transform(&chunk, origin);
+
+        let builder = ChunkBuilder::new(&heights, &OpenFreeMapTheme)
+            .with_seed(chunk.get_seed())
+            .with_size(chunk.get_size_in_meters());
+
+        terrain.append(&builder.build_terrain(), &transform);
+
+        let Ok(bytes) = tile_storage().read(&get_openfreemap_cache_path(&chunk)) else {
+            continue;
+        };
+        let ChunkMeshes {
+            strokes,
+            buildings: building_meshes,
+            water: water_mesh,
+            ..
+        } = builder.build(bytes).unwrap_or_default();
+
+        if let Some(mesh) = building_meshes {
+            buildings.append(&mesh, &transform);
+        }
+        if let Some(mesh) = strokes {
+            roads.append(&drape_mesh(mesh, &heights, ROAD_HEIGHT_OFFSET), &transform);
+        }
+        // The surface already lies on the terrain, the shore distances are not exported
+        if let Some(mesh) = water_mesh {
+            water.append(&mesh, &transform);
+        }
+    }
+
+    Ok(ExportScene {
+        origin,
+        meshes: vec![terrain, buildings, roads, water]
+            .into_iter()
+            .filter(|mesh| !mesh.indices.is_empty())
+            .collect(),
+    })
+}
+
+/// Writes a scene as a binary glTF file
+pub fn write_glb(scene: &ExportScene, path: &Path) -> Result<(), ExportError> {
+    const ARRAY_BUFFER: u32 = 34962;
+    const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+    const FLOAT: u32 = 5126;
+    const UNSIGNED_INT: u32 = 5125;
+
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut buffer_views = Vec::new();
+    let mut accessors = Vec::new();
+    let mut meshes = Vec::new();
+    let mut materials = Vec::new();
+    let mut nodes = Vec::new();
+
+    let mut push_view = |buffer: &mut Vec<u8>, bytes: &[u8], target: u32| {
+        let offset = buffer.len();
+        buffer.extend_from_slice(bytes);
+        buffer.resize(buffer.len().next_multiple_of(4), 0);
+        buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": offset,
+            "byteLength": bytes.len(),
+            "target": target,
+        }));
+        buffer_views.len() - 1
+    };
+
+    for mesh in &scene.meshes {
+        let normals = mesh.compute_normals();
+        let min = mesh.positions.iter().copied().reduce(Vec3::min);
+        let max = mesh.positions.iter().copied().reduce(Vec3::max);
+        let (Some(min), Some(max)) = (min, max) else {
+            continue;
+        };
+
+        let positions_view = push_view(&mut buffer, &vec3_bytes(&mesh.positions), ARRAY_BUFFER);
+        let normals_view = push_view(&mut buffer, &vec3_bytes(&normals), ARRAY_BUFFER);
+        let indices_view = push_view(
+            &mut buffer,
+            &mesh
+                .indices
+                .iter()
+                .flat_map(|i| i.to_le_bytes())
+                .collect::<Vec<u8>>(),
+            ELEMENT_ARRAY_BUFFER,
+        );
+
+        accessors.push(json!({
+            "bufferView": positions_view,
+            "componentType": FLOAT,
+            "count": mesh.positions.len(),
+            "type": "VEC3",
+            "min": min.to_array(),
+            "max": max.to_array(),
+        }));
+        accessors.push(json!({
+            "bufferView": normals_view,
+            "componentType": FLOAT,
+            "count": normals.len(),
+            "type": "VEC3",
+        }));
+        accessors.push(json!({
+            "bufferView": indices_view,
+            "componentType": UNSIGNED_INT,
+            "count": mesh.indices.len(),
+            "type": "SCALAR",
+        }));
+
+        let name = mesh.material.get_name();
+        materials.push(json!({
+            "name": name,
+            "pbrMetallicRoughness": {
+                "baseColorFactor": mesh.material.get_base_color(),
+                "metallicFactor": 0.0,
+                "roughnessFactor": mesh.material.get_roughness(),
+            },
+        }));
+        meshes.push(json!({
+            "name": name,
+            "primitives": [{
+                "attributes": {
+                    "POSITION": accessors.len() - 3,
+                    "NORMAL": accessors.len() - 2,
+                },
+                "indices": accessors.len() - 1,
+                "material": materials.len() - 1,
+            }],
+        }));
+        nodes.push(json!({ "name": name, "mesh": meshes.len() - 1 }));
+    }
+
+    let document = json!({
+        "asset": {
+            "version": "2.0",
+            "generator": "bevy-osm",
+            "extras": { "georeference": scene.get_georeference() },
+        },
+        "scene": 0,
+        "scenes": [{ "nodes": (0..nodes.len()).collect::<Vec<_>>() }],
+        "nodes": nodes,
+        "meshes": meshes,
+        "materials": materials,
+        "accessors": accessors,
+        "bufferViews": buffer_views,
+        "buffers": [{ "byteLength": buffer.len() }],
+    });
+
+    let mut json_chunk = serde_json::to_vec(&document).expect("glTF document should serialize");
+    json_chunk.resize(json_chunk.len().next_multiple_of(4), b' ');
+
+    let length = 12 + 8 + json_chunk.len() + 8 + buffer.len();
+    let mut file = io::BufWriter::new(fs::File::create(path)?);
+    file.write_all(b"glTF")?;
+    file.write_all(&2u32.to_le_bytes())?;
+    file.write_all(&(length as u32).to_le_bytes())?;
+    file.write_all(&(json_chunk.len() as u32).to_le_bytes())?;
+    file.write_all(b"JSON")?;
+    file.write_all(&json_chunk)?;
+    file.write_all(&(buffer.len() as u32).to_le_bytes())?;
+    file.write_all(b"BIN\0")?;
+    file.write_all(&buffer)?;
+    file.flush()?;
+    Ok(())
+}
+
+fn vec3_bytes(values: &[Vec3]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|v| v.to_array())
+        .flat_map(f32::to_le_bytes)
+        .collect()
+}
+
+/// Writes a scene as an OBJ file, with its materials in an MTL file next to it
+pub fn write_obj(scene: &ExportScene, path: &Path) -> Result<(), ExportError> {
+    let mtl_path = path.with_extension("mtl");
+    let mtl_name = mtl_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut mtl = io::BufWriter::new(fs::File::create(&mtl_path)?);
+    for mesh in &scene.meshes {
+        let [r, g, b, _] = mesh.material.get_base_color();
+        writeln!(mtl, "newmtl {}", mesh.material.get_name())?;
+        writeln!(mtl, "Kd {r} {g} {b}")?;
+        writeln!(mtl, "Ks 0 0 0")?;
+        writeln!(mtl)?;
+    }
+    mtl.flush()?;
+
+    let mut obj = io::BufWriter::new(fs::File::create(path)?);
+    writeln!(obj, "# Exported by bevy-osm")?;
+    writeln!(obj, "# georeference: {}", scene.get_georeference())?;
+    writeln!(obj, "mtllib {mtl_name}")?;
+
+    let mut offset = 1;
+    for mesh in &scene.meshes {
+        let name = mesh.material.get_name();
+        writeln!(obj, "o {name}")?;
+        writeln!(obj, "usemtl {name}")?;
+        for p in &mesh.positions {
+            writeln!(obj, "v {} {} {}", p.x, p.y, p.z)?;
+        }
+        for n in mesh.compute_normals() {
+            writeln!(obj, "vn {} {} {}", n.x, n.y, n.z)?;
+        }
+        for triangle in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangle[i] + offset);
+            writeln!(obj, "f {a}//{a} {b}//{b} {c}//{c}")?;
+        }
+        offset += mesh.positions.len() as u32;
+    }
+    obj.flush()?;
+    Ok(())
+}
+
+/// Downloads, builds and writes a region. The format is derived from the extension of `path`.
+///
+/// When tiles could not be downloaded, the region is still written from the tiles that are
+/// cached and [`ExportError::IncompleteDownload`] is returned.
+pub fn export_region(region: &ExportRegion, path: &Path) -> Result<(), ExportError> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    if !matches!(extension.as_deref(), Some("glb" | "obj")) {
+        return Err(ExportError::UnsupportedFormat(path.display().to_string()));
+    }
+
+    let downloaded = download_region(region);
+    let scene = build_region(region)?;
+
+    match extension.as_deref() {
+        Some("obj") => write_obj(&scene, path)?,
+        _ => write_glb(&scene, path)?,
+    }
+    downloaded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elevation::TILE_VERTEX_COUNT;
+
+    fn triangle(material: ExportMaterial, y: f32) -> ExportMesh {
+        ExportMesh {
+            material,
+            positions: vec![
+                Vec3::new(0.0, y, 0.0),
+                Vec3::new(1.0, y, 0.0),
+                Vec3::new(0.0, y, 1.0),
+            ],
+            indices: vec![0, 1, 2],
+        }
+    }
+
+    /// Reads the JSON chunk of a binary glTF file
+    fn read_glb_json(bytes: &[u8]) -> serde_json::Value {
+        assert_eq!(&bytes[0..4], b"glTF");
+        let length = u32::from_le_bytes(bytes[12..16].try_into().unwrap()) as usize;
+        assert_eq!(&bytes[16..20], b"JSON");
+        serde_json::from_slice(&bytes[20..20 + length]).unwrap()
+    }
+
+    #[test]
+    fn test_exported_heights_are_elevations() {
+        // Heightmaps store the elevation minus `HEIGHT_OFFSET`, like `build_heightmap`
+        let elevation = 250.0;
+        let vertex_count = IVec2::splat(TILE_VERTEX_COUNT);
+        let mut heights = HeightMap::new(vertex_count);
+        for x in -1..=vertex_count.x + 2 {
+            for z in -1..=vertex_count.y + 2 {
+                heights.set(x, z, elevation - HEIGHT_OFFSET);
+            }
+        }
+        let chunk = get_chunk_for_coord(52.0, 4.0, 14);
+        let origin = chunk.get_lat_lon_area().center();
+        let builder = ChunkBuilder::new(&heights, &OpenFreeMapTheme);
+
+        let mut terrain = ExportMesh::new(ExportMaterial::Terrain);
+        terrain.append(
+            &builder.build_terrain(),
+            &get_chunk_transform(&chunk, origin),
+        );
+        assert!(!terrain.positions.is_empty());
+        for position in &terrain.positions {
+            assert!((position.y - elevation).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        // The terrain has no geometry and is skipped, the other meshes keep their materials
+        let scene = ExportScene {
+            origin: Vec2::new(52.0, 4.0),
+            meshes: vec![
+                ExportMesh::new(ExportMaterial::Terrain),
+                triangle(ExportMaterial::Buildings, 10.0),
+                triangle(ExportMaterial::Roads, 0.0),
+            ],
+        };
+        let directory = std::env::temp_dir().join("bevy-osm-export-test");
+        fs::create_dir_all(&directory).unwrap();
+
+        let glb_path = directory.join("region.glb");
+        write_glb(&scene, &glb_path).unwrap();
+        let document = read_glb_json(&fs::read(&glb_path).unwrap());
+        let meshes = document["meshes"].as_array().unwrap();
+        assert_eq!(meshes.len(), 2);
+        for mesh in meshes {
+            let material = mesh["primitives"][0]["material"].as_u64().unwrap() as usize;
+            assert_eq!(document["materials"][material]["name"], mesh["name"]);
+        }
+        assert_eq!(
+            document["asset"]["extras"]["georeference"]["origin_lat"],
+            json!(52.0)
+        );
+
+        let obj_path = directory.join("region.obj");
+        write_obj(&scene, &obj_path).unwrap();
+        let obj = fs::read_to_string(&obj_path).unwrap();
+        assert_eq!(obj.lines().filter(|line| line.starts_with("v ")).count(), 6);
+        assert!(obj.lines().any(|line| line == "f 4//4 5//5 6//6"));
+        assert!(
+            fs::read_to_string(obj_path.with_extension("mtl"))
+                .unwrap()
+                .contains("newmtl roads")
+        );
+    }
+}
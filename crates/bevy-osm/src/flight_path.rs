@@ -0,0 +1,433 @@
+//! Scripted camera flights, used to benchmark the same route across commits.
+//!
+//! A [`FlightPath`] is loaded from a `.flight.json` asset and replayed by a [`FlightPathPlayer`]
+//! on the camera. While playing, the [`SessionRecorder`] records the performance of every frame,
+//! which is written to a file once the flight is over.
+//!
+//! A [`CameraFlyTo`] moves a camera to a single coordinate instead, for example to a search result.
+
+use std::{
+    error::Error,
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, io::Reader},
+    prelude::*,
+    tasks::ConditionalSendFuture,
+};
+use bevy_terrain::{
+    camera::{TerrainCamera, TerrainCameraMode, get_ground_height},
+    mesh::ChunkHeightMap,
+    quadtree::QuadTreeNodeComponent,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chunk::lat_lon_to_world, config::OSMConfig, elevation::elevation_to_world_y,
+    performance::SessionRecorder,
+};
+
+/// A keyframe of a flight path
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlightKeyframe {
+    /// Time since the start of the flight (seconds)
+    pub time: f32,
+    pub lat: f32,
+    pub lon: f32,
+    /// Altitude above sea level (meters)
+    pub altitude: f32,
+    /// Clockwise from north (degrees)
+    #[serde(default)]
+    pub heading: f32,
+    /// Positive is looking up (degrees)
+    #[serde(default)]
+    pub pitch: f32,
+    #[serde(default)]
+    pub roll: f32,
+}
+
+impl FlightKeyframe {
+    fn get_translation(&self, origin: Vec2) -> Vec3 {
+        let (x, z) = lat_lon_to_world(Vec2::new(self.lat, self.lon), origin);
+        Vec3::new(x as f32, elevation_to_world_y(self.altitude), z as f32)
+    }
+
+    fn get_rotation(&self) -> Quat {
+        Quat::from_euler(
+            EulerRot::YXZ,
+            -self.heading.to_radians(),
+            self.pitch.to_radians(),
+            -self.roll.to_radians(),
+        )
+    }
+}
+
+/// A camera path through keyframes that are sorted by time.
+///
+/// Positions are interpolated with a cubic Hermite spline (Catmull-Rom tangents), orientations
+/// are interpolated spherically.
+#[derive(Asset, TypePath, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlightPath {
+    pub keyframes: Vec<FlightKeyframe>,
+}
+
+impl FlightPath {
+    /// Length of the flight (seconds)
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// The tangent of the spline at a keyframe, in meters per second
+    fn get_tangent(&self, index: usize, origin: Vec2) -> Vec3 {
+        let previous = &self.keyframes[index.saturating_sub(1)];
+        let next = &self.keyframes[(index + 1).min(self.keyframes.len() - 1)];
+        let duration = next.time - previous.time;
+        if duration <= 0.0 {
+            return Vec3::ZERO;
+        }
+        (next.get_translation(origin) - previous.get_translation(origin)) / duration
+    }
+
+    /// Returns the camera transform at a time since the start of the flight.
+    ///
+    /// `origin` is the coordinate in degrees that corresponds to the world origin. Times outside
+    /// of the flight are clamped.
+    pub fn sample(&self, time: f32, origin: Vec2) -> Option<Transform> {
+        let first = self.keyframes.first()?;
+        let index = self
+            .keyframes
+            .iter()
+            .rposition(|keyframe| keyframe.time <= time)
+            .unwrap_or(0);
+
+        let start = &self.keyframes[index];
+        let Some(end) = self.keyframes.get(index + 1).filter(|_| time >= first.time) else {
+            return Some(
+                Transform::from_translation(start.get_translation(origin))
+                    .with_rotation(start.get_rotation()),
+            );
+        };
+
+        let duration = end.time - start.time;
+        let s = ((time - start.time) / duration).clamp(0.0, 1.0);
+        let (s2, s3) = (s * s, s * s * s);
+        let translation = (2.0 * s3 - 3.0 * s2 + 1.0) * start.get_translation(origin)
+            + (s3 - 2.0 * s2 + s) * duration * self.get_tangent(index, origin)
+            + (-2.0 * s3 + 3.0 * s2) * end.get_translation(origin)
+            + (s3 - s2) * duration * self.get_tangent(index + 1, origin);
+
+        Some(
+            Transform::from_translation(translation)
+                .with_rotation(start.get_rotation().slerp(end.get_rotation(), s)),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum FlightPathError {
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for FlightPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "could not read flight path: {err}"),
+            Self::Json(err) => write!(f, "invalid flight path: {err}"),
+        }
+    }
+}
+
+impl Error for FlightPathError {}
+
+#[derive(Default, TypePath)]
+pub struct FlightPathLoader;
+
+impl AssetLoader for FlightPathLoader {
+    type Asset = FlightPath;
+    type Settings = ();
+    type Error = FlightPathError;
+
+    fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> impl ConditionalSendFuture<Output = Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader
+                .read_to_end(&mut bytes)
+                .await
+                .map_err(FlightPathError::Io)?;
+
+            let mut path: FlightPath =
+                serde_json::from_slice(&bytes).map_err(FlightPathError::Json)?;
+            path.keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+            Ok(path)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["flight.json"]
+    }
+}
+
+/// Replays a flight path on the camera it is attached to.
+#[derive(Component, Debug, Clone)]
+pub struct FlightPathPlayer {
+    pub path: Handle<FlightPath>,
+    /// Time since the start of the flight (seconds)
+    pub elapsed: f32,
+    /// The recorded session is written to this file after the flight, as CSV or JSON depending on
+    /// the extension
+    pub output: Option<PathBuf>,
+    /// Exits the application after the flight, for unattended benchmarks
+    pub exit_when_finished: bool,
+    started: bool,
+    finished: bool,
+}
+
+impl FlightPathPlayer {
+    pub fn new(path: Handle<FlightPath>) -> Self {
+        Self {
+            path,
+            elapsed: 0.0,
+            output: None,
+            exit_when_finished: false,
+            started: false,
+            finished: false,
+        }
+    }
+
+    pub fn with_output(mut self, output: impl Into<PathBuf>) -> Self {
+        self.output = Some(output.into());
+        self
+    }
+
+    pub fn with_exit_when_finished(mut self) -> Self {
+        self.exit_when_finished = true;
+        self
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+fn write_session(recorder: &SessionRecorder, output: &Path) {
+    let bytes = match output.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => recorder.to_json().map_err(io::Error::from),
+        _ => recorder.to_csv().map_err(io::Error::other),
+    };
+    // The output is a path given on the command line, not a key in the data storage
+    let result = bytes.and_then(|bytes| std::fs::write(output, bytes));
+    match result {
+        Ok(()) => info!("Saved flight session to {}", output.display()),
+        Err(err) => error!("Could not save flight session: {err}"),
+    }
+}
+
+/// Moves cameras along their flight path and records the session while flying.
+pub fn play_flight_paths(
+    mut players: Query<(&mut FlightPathPlayer, &mut Transform)>,
+    paths: Res<Assets<FlightPath>>,
+    config: Res<OSMConfig>,
+    time: Res<Time>,
+    mut recorder: ResMut<SessionRecorder>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    for (mut player, mut transform) in &mut players {
+        if player.finished {
+            continue;
+        }
+        let Some(path) = paths.get(&player.path) else {
+            continue;
+        };
+
+        if !player.started {
+            player.started = true;
+            if player.output.is_some() {
+                recorder.start();
+            }
+        } else {
+            player.elapsed += time.delta_secs();
+        }
+
+        if let Some(sample) = path.sample(player.elapsed, config.location.get_world_center()) {
+            *transform = sample;
+        }
+
+        if player.elapsed >= path.duration() {
+            player.finished = true;
+            info!("Finished flight path after {:.1}s", player.elapsed);
+
+            if let Some(output) = &player.output {
+                recorder.stop();
+                write_session(&recorder, output);
+            }
+            if player.exit_when_finished {
+                exit.write(AppExit::Success);
+            }
+        }
+    }
+}
+
+/// Flies a camera to a coordinate, after which the component is removed.
+///
+/// Cameras with a [`TerrainCamera`] end up orbiting around the coordinate, other cameras look at
+/// it from the south.
+#[derive(Component, Debug, Clone)]
+pub struct CameraFlyTo {
+    /// (lat, lon) in degrees
+    pub lat_lon: Vec2,
+    /// Distance from the coordinate at the end of the flight (meters)
+    pub distance: f32,
+    /// (seconds)
+    pub duration: f32,
+    elapsed: f32,
+    /// The point the camera looks at and its distance to it at the start of the flight
+    start: Option<(Vec3, f32)>,
+}
+
+impl CameraFlyTo {
+    pub fn new(lat_lon: Vec2) -> Self {
+        Self {
+            lat_lon,
+            distance: 800.0,
+            duration: 3.0,
+            elapsed: 0.0,
+            start: None,
+        }
+    }
+}
+
+/// Moves cameras along an arc towards their [`CameraFlyTo`] target, which climbs higher the
+/// farther the target is.
+pub fn fly_cameras_to(
+    mut commands: Commands,
+    mut cameras: Query<(
+        Entity,
+        &mut CameraFlyTo,
+        &mut Transform,
+        Option<&mut TerrainCamera>,
+    )>,
+    chunks: Query<(&ChunkHeightMap, &GlobalTransform, &QuadTreeNodeComponent)>,
+    config: Res<OSMConfig>,
+    time: Res<Time>,
+) {
+    for (entity, mut flight, mut transform, controller) in &mut cameras {
+        let start = *flight.start.get_or_insert_with(|| match &controller {
+            Some(controller) if controller.mode == TerrainCameraMode::Orbit => {
+                (controller.focus, controller.distance)
+            }
+            _ => {
+                let focus = transform.translation + transform.forward() * 500.0;
+                (focus, 500.0)
+            }
+        });
+
+        let (x, z) = lat_lon_to_world(flight.lat_lon, config.location.get_world_center());
+        let mut target = Vec3::new(x as f32, start.0.y, z as f32);
+        target.y = get_ground_height(&chunks, target.xz()).unwrap_or(target.y);
+
+        flight.elapsed += time.delta_secs();
+        let t = (flight.elapsed / flight.duration.max(f32::EPSILON)).clamp(0.0, 1.0);
+        let s = t * t * (3.0 - 2.0 * t);
+        let focus = start.0.lerp(target, s);
+        let climb = start.0.xz().distance(target.xz()) * 0.5 * (t * std::f32::consts::PI).sin();
+        let distance = start.1 + (flight.distance - start.1) * s + climb;
+
+        match controller {
+            Some(mut controller) => {
+                // The terrain camera places itself relative to its focus point every frame.
+                controller.mode = TerrainCameraMode::Orbit;
+                controller.pitch = controller.pitch.min(-0.1);
+                controller.focus = focus;
+                controller.distance = distance;
+            }
+            None => {
+                *transform = Transform::from_translation(
+                    focus + Vec3::new(0.0, distance, distance) * std::f32::consts::FRAC_1_SQRT_2,
+                )
+                .looking_at(focus, Vec3::Y);
+            }
+        }
+
+        if t >= 1.0 {
+            commands.entity(entity).remove::<CameraFlyTo>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, lat: f32, lon: f32, heading: f32) -> FlightKeyframe {
+        FlightKeyframe {
+            time,
+            lat,
+            lon,
+            altitude: 100.0 + time,
+            heading,
+            pitch: 0.0,
+            roll: 0.0,
+        }
+    }
+
+    fn flight_path() -> FlightPath {
+        FlightPath {
+            keyframes: vec![
+                keyframe(0.0, 52.0, 4.0, 0.0),
+                keyframe(10.0, 52.01, 4.0, 90.0),
+                keyframe(30.0, 52.01, 4.02, 180.0),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_sample_passes_through_keyframes() {
+        let path = flight_path();
+        let origin = Vec2::new(52.0, 4.0);
+
+        for keyframe in &path.keyframes {
+            let transform = path.sample(keyframe.time, origin).unwrap();
+            assert!(
+                transform
+                    .translation
+                    .abs_diff_eq(keyframe.get_translation(origin), 1e-2),
+                "{transform:?}"
+            );
+            assert!(transform.rotation.angle_between(keyframe.get_rotation()) < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_sample_is_clamped() {
+        let path = flight_path();
+        let origin = Vec2::new(52.0, 4.0);
+
+        assert_eq!(path.sample(-5.0, origin), path.sample(0.0, origin));
+        assert_eq!(path.sample(100.0, origin), path.sample(30.0, origin));
+        assert_eq!(FlightPath { keyframes: vec![] }.sample(0.0, origin), None);
+    }
+
+    #[test]
+    fn test_heading_points_north() {
+        let forward = keyframe(0.0, 0.0, 0.0, 0.0).get_rotation() * Vec3::NEG_Z;
+        assert!(forward.abs_diff_eq(Vec3::NEG_Z, 1e-5));
+
+        let forward = keyframe(0.0, 0.0, 0.0, 90.0).get_rotation() * Vec3::NEG_Z;
+        assert!(forward.abs_diff_eq(Vec3::X, 1e-5), "{forward}");
+    }
+
+    #[test]
+    fn test_altitude_is_above_sea_level() {
+        let keyframe = keyframe(400.0, 52.0, 4.0, 0.0);
+        let translation = keyframe.get_translation(Vec2::new(52.0, 4.0));
+        assert!((crate::elevation::world_y_to_elevation(translation.y) - 500.0).abs() < 1e-3);
+    }
+}
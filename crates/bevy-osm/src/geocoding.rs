@@ -0,0 +1,462 @@
+//! Finds places by name for the search box of the UI.
+//!
+//! By default names are looked up in a [`PlaceIndex`] of the `place`, `poi` and
+//! `transportation_name` layers of the vector tiles that have been loaded, and of the cached tiles
+//! around the [`Location`]. A [`Geocoder`], for example one that queries a Nominatim server, can
+//! be plugged in with [`Geocoding::with_geocoder`]. Searches run in a task, so typing in the
+//! search box does not block a frame.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
+
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task, block_on, futures_lite::future},
+};
+use geo_types::Geometry;
+use mvt_reader::{Reader, error::ParserError, feature::Value};
+
+use crate::{
+    cache::get_openfreemap_cache_path,
+    chunk::{Chunk, get_chunk_for_coord, get_lat_lon},
+    config::OSMConfig,
+    location::Location,
+    schema::layer::OMTLayer,
+    storage::tile_storage,
+};
+
+/// Extent of the coordinates in a vector tile
+const TILE_EXTENT: f32 = 4096.0;
+/// Results with the same name and layer that are closer than this are considered duplicates,
+/// for example a city that is labelled in the tiles of every zoom level (degrees)
+const DUPLICATE_DISTANCE: f32 = 0.01;
+/// Number of results shown in the search box
+pub const MAX_SEARCH_RESULTS: usize = 10;
+/// Number of tiles in the [`PlaceIndex`], the tiles that were added first are removed first
+const MAX_INDEXED_TILES: usize = 512;
+/// Zoom levels of the cached tiles around the location that are added to the index at startup
+const CACHED_TILE_ZOOMS: [i8; 3] = [10, 12, 14];
+/// Number of cached tiles around the tile of the location in every direction
+const CACHED_TILE_RADIUS: i32 = 3;
+
+/// The (x, y, z) of a tile
+type TileKey = (i32, i32, i8);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeocodeResult {
+    pub name: String,
+    /// The layer of the vector tile the name was found in
+    pub layer: OMTLayer,
+    /// The `class` of the feature, such as `city`, `restaurant` or `primary`
+    pub class: Option<String>,
+    /// (lat, lon) in degrees
+    pub lat_lon: Vec2,
+}
+
+impl GeocodeResult {
+    pub fn get_description(&self) -> String {
+        let layer = match self.layer {
+            OMTLayer::Place => "place",
+            OMTLayer::Poi => "point of interest",
+            OMTLayer::TransportationName => "street",
+            _ => "feature",
+        };
+        match &self.class {
+            Some(class) => format!("{} ({layer}, {class})", self.name),
+            None => format!("{} ({layer})", self.name),
+        }
+    }
+}
+
+/// Looks up places by name. Searches run on a task pool, so they may block.
+pub trait Geocoder: Send + Sync + 'static {
+    /// Returns at most `limit` results, the best match first
+    fn search(&self, query: &str, limit: usize) -> Vec<GeocodeResult>;
+}
+
+/// How well a name matches a query, lower is better
+fn get_match_rank(name: &str, query: &str) -> Option<u8> {
+    if name == query {
+        Some(0)
+    } else if name.starts_with(query) {
+        Some(1)
+    } else if name
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| word.starts_with(query))
+    {
+        Some(2)
+    } else if name.contains(query) {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+/// Places are more likely to be searched for than points of interest, which are more likely to
+/// be searched for than streets
+fn get_layer_rank(layer: &OMTLayer) -> u8 {
+    match layer {
+        OMTLayer::Place => 0,
+        OMTLayer::Poi => 1,
+        _ => 2,
+    }
+}
+
+fn get_string(feature: &mvt_reader::feature::Feature<i32>, key: &str) -> Option<String> {
+    match feature.properties.as_ref()?.get(key)? {
+        Value::String(value) if !value.is_empty() => Some(value.clone()),
+        _ => None,
+    }
+}
+
+/// A representative coordinate of a feature in tile space, the middle vertex for lines
+fn get_anchor(geometry: &Geometry<i32>) -> Option<(i32, i32)> {
+    let middle =
+        |points: &[geo_types::Coord<i32>]| points.get(points.len() / 2).map(|c| (c.x, c.y));
+    match geometry {
+        Geometry::Point(point) => Some((point.x(), point.y())),
+        Geometry::MultiPoint(points) => points.0.first().map(|point| (point.x(), point.y())),
+        Geometry::LineString(line) => middle(&line.0),
+        Geometry::MultiLineString(lines) => lines.0.first().and_then(|line| middle(&line.0)),
+        Geometry::Polygon(polygon) => middle(&polygon.exterior().0),
+        Geometry::MultiPolygon(polygons) => polygons
+            .0
+            .first()
+            .and_then(|polygon| middle(&polygon.exterior().0)),
+        _ => None,
+    }
+}
+
+/// Reads the named features of the layers that are searchable from a vector tile
+pub fn get_named_features(
+    bytes: Vec<u8>,
+    chunk: &Chunk,
+) -> Result<Vec<GeocodeResult>, ParserError> {
+    let reader = Reader::new(bytes)?;
+    let mut results = Vec::new();
+
+    for layer in reader.get_layer_metadata()? {
+        let layer_name = OMTLayer::from_name(&layer.name);
+        if !matches!(
+            layer_name,
+            OMTLayer::Place | OMTLayer::Poi | OMTLayer::TransportationName
+        ) {
+            continue;
+        }
+
+        for feature in reader.get_features_as::<i32>(layer.layer_index)? {
+            let (Some(name), Some((x, y))) =
+                (get_string(&feature, "name"), get_anchor(&feature.geometry))
+            else {
+                continue;
+            };
+            let (lat, lon) = get_lat_lon(
+                chunk.x as f32 + x as f32 / TILE_EXTENT,
+                chunk.y as f32 + y as f32 / TILE_EXTENT,
+                chunk.z,
+            );
+            results.push(GeocodeResult {
+                name,
+                layer: layer_name.clone(),
+                class: get_string(&feature, "class"),
+                lat_lon: Vec2::new(lat as f32, lon as f32),
+            });
+        }
+    }
+    Ok(results)
+}
+
+/// A name in the index and the tiles it was found in
+#[derive(Debug, Clone)]
+struct IndexedPlace {
+    result: GeocodeResult,
+    tiles: Vec<TileKey>,
+}
+
+/// An index of the names in the vector tiles that have been loaded
+#[derive(Resource, Default, Clone)]
+pub struct PlaceIndex {
+    /// The places by name and layer. Shared with the search tasks, so a search does not copy the
+    /// index.
+    places: Arc<HashMap<(String, OMTLayer), Vec<IndexedPlace>>>,
+    /// The tiles in the index, in the order they were added
+    tiles: VecDeque<TileKey>,
+    tile_set: HashSet<TileKey>,
+    len: usize,
+}
+
+impl PlaceIndex {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains_tile(&self, chunk: &Chunk) -> bool {
+        self.tile_set.contains(&(chunk.x, chunk.y, chunk.z))
+    }
+
+    /// Adds the names of a tile, unless the tile was added before. Removes the oldest tiles when
+    /// there are more than [`MAX_INDEXED_TILES`].
+    pub fn insert_tile(&mut self, chunk: &Chunk, results: Vec<GeocodeResult>) {
+        let tile = (chunk.x, chunk.y, chunk.z);
+        if !self.tile_set.insert(tile) {
+            return;
+        }
+        self.tiles.push_back(tile);
+
+        let places = Arc::make_mut(&mut self.places);
+        for result in results {
+            let entries = places
+                .entry((result.name.clone(), result.layer.clone()))
+                .or_default();
+            let duplicate = entries
+                .iter_mut()
+                .find(|entry| entry.result.lat_lon.distance(result.lat_lon) < DUPLICATE_DISTANCE);
+            match duplicate {
+                Some(entry) if !entry.tiles.contains(&tile) => entry.tiles.push(tile),
+                Some(_) => {}
+                None => {
+                    entries.push(IndexedPlace {
+                        result,
+                        tiles: vec![tile],
+                    });
+                    self.len += 1;
+                }
+            }
+        }
+
+        while self.tiles.len() > MAX_INDEXED_TILES {
+            if let Some(oldest) = self.tiles.pop_front() {
+                self.remove_places_of_tile(oldest);
+            }
+        }
+    }
+
+    /// Removes the places that were only found in `tile`
+    fn remove_places_of_tile(&mut self, tile: TileKey) {
+        self.tile_set.remove(&tile);
+        let places = Arc::make_mut(&mut self.places);
+        let mut removed = 0;
+        places.retain(|_, entries| {
+            entries.retain_mut(|entry| {
+                entry.tiles.retain(|other| *other != tile);
+                removed += entry.tiles.is_empty() as usize;
+                !entry.tiles.is_empty()
+            });
+            !entries.is_empty()
+        });
+        self.len -= removed;
+    }
+}
+
+impl Geocoder for PlaceIndex {
+    fn search(&self, query: &str, limit: usize) -> Vec<GeocodeResult> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        let mut matches: Vec<((u8, u8, usize), &GeocodeResult)> = self
+            .places
+            .values()
+            .flatten()
+            .filter_map(|entry| {
+                let entry = &entry.result;
+                let rank = get_match_rank(&entry.name.to_lowercase(), &query)?;
+                Some((
+                    (rank, get_layer_rank(&entry.layer), entry.name.len()),
+                    entry,
+                ))
+            })
+            .collect();
+        matches.sort_by_key(|(key, _)| *key);
+        matches
+            .into_iter()
+            .take(limit)
+            .map(|(_, entry)| entry.clone())
+            .collect()
+    }
+}
+
+/// Reads the names of the cached vector tiles around a location
+fn read_cached_tiles(lat_lon: Vec2) -> Vec<(Chunk, Vec<GeocodeResult>)> {
+    let mut tiles = Vec::new();
+    for z in CACHED_TILE_ZOOMS {
+        let center = get_chunk_for_coord(lat_lon.x as f64, lat_lon.y as f64, z);
+        for dx in -CACHED_TILE_RADIUS..=CACHED_TILE_RADIUS {
+            for dy in -CACHED_TILE_RADIUS..=CACHED_TILE_RADIUS {
+                let chunk = Chunk {
+                    x: center.x + dx,
+                    y: center.y + dy,
+                    ..center.clone()
+                };
+                let Ok(bytes) = tile_storage().read(&get_openfreemap_cache_path(&chunk)) else {
+                    continue;
+                };
+                if let Ok(results) = get_named_features(bytes, &chunk) {
+                    tiles.push((chunk, results));
+                }
+            }
+        }
+    }
+    tiles
+}
+
+/// The state of the search box
+#[derive(Resource, Default)]
+pub struct Geocoding {
+    pub query: String,
+    pub results: Vec<GeocodeResult>,
+    /// Used instead of the [`PlaceIndex`] when set
+    geocoder: Option<Arc<dyn Geocoder>>,
+    search_task: Option<Task<Vec<GeocodeResult>>>,
+    cached_tiles_task: Option<Task<Vec<(Chunk, Vec<GeocodeResult>)>>>,
+}
+
+impl Geocoding {
+    pub fn with_geocoder(geocoder: impl Geocoder) -> Self {
+        Self {
+            geocoder: Some(Arc::new(geocoder)),
+            ..default()
+        }
+    }
+
+    /// Starts searching for the current query, the results are updated by [`update_geocoding`].
+    /// A search that is still running is cancelled.
+    pub fn search(&mut self, index: &PlaceIndex) {
+        let geocoder: Arc<dyn Geocoder> = match &self.geocoder {
+            Some(geocoder) => geocoder.clone(),
+            None => Arc::new(index.clone()),
+        };
+        let query = self.query.clone();
+        self.search_task = Some(
+            AsyncComputeTaskPool::get()
+                .spawn(async move { geocoder.search(&query, MAX_SEARCH_RESULTS) }),
+        );
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.search_task.is_some()
+    }
+}
+
+/// Receives the search results, and adds the cached tiles around the location to the index when
+/// the location changes
+pub fn update_geocoding(
+    mut geocoding: ResMut<Geocoding>,
+    mut index: ResMut<PlaceIndex>,
+    config: Res<OSMConfig>,
+    mut location: Local<Option<Location>>,
+) {
+    if location.as_ref() != Some(&config.location) {
+        *location = Some(config.location.clone());
+        let lat_lon = config.location.get_world_center();
+        geocoding.cached_tiles_task =
+            Some(AsyncComputeTaskPool::get().spawn(async move { read_cached_tiles(lat_lon) }));
+    }
+
+    if let Some(task) = &mut geocoding.search_task
+        && let Some(results) = block_on(future::poll_once(task))
+    {
+        geocoding.results = results;
+        geocoding.search_task = None;
+    }
+
+    if let Some(task) = &mut geocoding.cached_tiles_task
+        && let Some(tiles) = block_on(future::poll_once(task))
+    {
+        for (chunk, results) in tiles {
+            index.insert_tile(&chunk, results);
+        }
+        geocoding.cached_tiles_task = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(name: &str, layer: OMTLayer, lat_lon: Vec2) -> GeocodeResult {
+        GeocodeResult {
+            name: name.into(),
+            layer,
+            class: None,
+            lat_lon,
+        }
+    }
+
+    fn chunk(x: i32) -> Chunk {
+        Chunk {
+            x,
+            y: 0,
+            z: 14,
+            elevation: Handle::default(),
+            raster: Handle::default(),
+        }
+    }
+
+    #[test]
+    fn test_search_ranks_matches() {
+        let mut index = PlaceIndex::default();
+        index.insert_tile(
+            &chunk(0),
+            vec![
+                result(
+                    "Damrak",
+                    OMTLayer::TransportationName,
+                    Vec2::new(52.376, 4.896),
+                ),
+                result("Nieuwe Damstraat", OMTLayer::TransportationName, Vec2::ZERO),
+                result("Dam", OMTLayer::Poi, Vec2::new(52.373, 4.893)),
+                result("Amsterdam", OMTLayer::Place, Vec2::new(52.37, 4.89)),
+            ],
+        );
+
+        let names: Vec<String> = index
+            .search("dam", 10)
+            .into_iter()
+            .map(|result| result.name)
+            .collect();
+        assert_eq!(names, ["Dam", "Damrak", "Nieuwe Damstraat", "Amsterdam"]);
+        assert!(index.search("  ", 10).is_empty());
+    }
+
+    #[test]
+    fn test_duplicates_are_merged() {
+        let mut index = PlaceIndex::default();
+        let amsterdam = result("Amsterdam", OMTLayer::Place, Vec2::new(52.37, 4.89));
+        index.insert_tile(&chunk(0), vec![amsterdam.clone()]);
+        index.insert_tile(&chunk(1), vec![amsterdam.clone()]);
+        // The same tile is not indexed twice
+        index.insert_tile(
+            &chunk(1),
+            vec![result("Haarlem", OMTLayer::Place, Vec2::new(52.38, 4.64))],
+        );
+
+        assert_eq!(index.len(), 1);
+        assert!(index.contains_tile(&chunk(1)));
+    }
+
+    #[test]
+    fn test_oldest_tiles_are_removed() {
+        let mut index = PlaceIndex::default();
+        let amsterdam = result("Amsterdam", OMTLayer::Place, Vec2::new(52.37, 4.89));
+        let haarlem = result("Haarlem", OMTLayer::Place, Vec2::new(52.38, 4.64));
+        index.insert_tile(&chunk(0), vec![amsterdam.clone(), haarlem]);
+        index.insert_tile(&chunk(1), vec![amsterdam]);
+        for x in 2..=MAX_INDEXED_TILES as i32 {
+            index.insert_tile(&chunk(x), Vec::new());
+        }
+
+        // Amsterdam is still in a tile of the index, Haarlem is not
+        assert!(!index.contains_tile(&chunk(0)));
+        assert_eq!(index.len(), 1);
+        assert!(index.search("haarlem", 10).is_empty());
+        assert_eq!(index.search("amsterdam", 10).len(), 1);
+    }
+}
@@ -0,0 +1,147 @@
+//! Labels for the places of the vector tiles, such as cities, villages and islands.
+//!
+//! Every place is a [`PlaceLabel`] entity on the terrain, a child of its chunk. The labels are
+//! painted with egui on top of the 3D view, most important places first, and labels that would
+//! overlap one that is already painted are left out.
+
+use bevy::prelude::*;
+use bevy_egui::{
+    EguiContexts,
+    egui::{self, Align2, Color32, FontId, Pos2, Rect, Stroke},
+};
+
+use crate::{config::OSMConfig, schema::place::Place, tag::Tag};
+
+/// Radius of the dot that marks the place (pixels)
+const DOT_RADIUS: f32 = 3.0;
+/// Distance between the dot and the name (pixels)
+const LABEL_MARGIN: f32 = 4.0;
+
+/// A place from the `place` layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlaceInstruction {
+    pub class: Place,
+}
+
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct PlaceLabel {
+    pub name: String,
+    pub class: Place,
+}
+
+impl PlaceLabel {
+    /// Reads the name from the tags of a feature, preferring the latin spelling
+    pub fn from_tags(instruction: &PlaceInstruction, tags: &[Tag]) -> Option<Self> {
+        let get = |key: &str| tags.iter().find(|tag| tag.key == key).map(|tag| &tag.val);
+        let name = get("name:latin").or_else(|| get("name"))?;
+        Some(Self {
+            name: name.clone(),
+            class: instruction.class,
+        })
+    }
+
+    /// Labels with a lower priority are painted first
+    pub fn get_priority(&self) -> u8 {
+        match self.class {
+            Place::Continent => 0,
+            Place::Country => 1,
+            Place::State | Place::Province => 2,
+            Place::City => 3,
+            Place::Town => 4,
+            Place::Island => 5,
+            Place::Village => 6,
+            Place::Suburb => 7,
+            Place::Hamlet | Place::Quarter => 8,
+            Place::Neighbourhood | Place::IsolatedDwelling => 9,
+        }
+    }
+
+    /// Distance from the camera beyond which the label is hidden (meters)
+    pub fn get_max_distance(&self) -> f32 {
+        match self.class {
+            Place::Continent | Place::Country => f32::INFINITY,
+            Place::State | Place::Province => 1e6,
+            Place::City => 2e5,
+            Place::Town | Place::Island => 5e4,
+            Place::Village | Place::Suburb => 2e4,
+            Place::Hamlet | Place::Quarter => 8e3,
+            Place::Neighbourhood | Place::IsolatedDwelling => 3e3,
+        }
+    }
+
+    fn get_font_size(&self) -> f32 {
+        match self.get_priority() {
+            0..=1 => 20.0,
+            2..=3 => 17.0,
+            4..=6 => 14.0,
+            _ => 12.0,
+        }
+    }
+}
+
+/// Paints the names of the places in visible chunks
+pub fn draw_place_labels(
+    mut contexts: EguiContexts,
+    config: Res<OSMConfig>,
+    camera: Single<(&Camera, &GlobalTransform)>,
+    labels: Query<(&PlaceLabel, &GlobalTransform, &InheritedVisibility)>,
+) {
+    if !config.place_labels {
+        return;
+    }
+    let Ok(ctx) = contexts.ctx_mut() else {
+        return;
+    };
+    let (camera, camera_transform) = *camera;
+
+    let mut visible: Vec<(&PlaceLabel, Vec2, f32)> = labels
+        .iter()
+        .filter(|(_, _, visibility)| visibility.get())
+        .filter_map(|(label, transform, _)| {
+            let distance = transform
+                .translation()
+                .distance(camera_transform.translation());
+            if distance > label.get_max_distance() {
+                return None;
+            }
+            let position = camera
+                .world_to_viewport(camera_transform, transform.translation())
+                .ok()?;
+            Some((label, position, distance))
+        })
+        .collect();
+    visible.sort_by(|a, b| {
+        a.0.get_priority()
+            .cmp(&b.0.get_priority())
+            .then(a.2.total_cmp(&b.2))
+    });
+
+    let painter = ctx.layer_painter(egui::LayerId::background());
+    let mut occupied: Vec<Rect> = Vec::new();
+    for (label, position, _) in visible {
+        let dot = Pos2::new(position.x, position.y);
+        let galley = painter.layout_no_wrap(
+            label.name.clone(),
+            FontId::proportional(label.get_font_size()),
+            Color32::WHITE,
+        );
+        let rect = Align2::CENTER_BOTTOM.anchor_size(
+            dot - egui::vec2(0.0, DOT_RADIUS + LABEL_MARGIN),
+            galley.size(),
+        );
+        // The same place can be in the tiles of several zoom levels
+        if occupied.iter().any(|other| other.intersects(rect)) {
+            continue;
+        }
+        occupied.push(rect);
+
+        painter.circle(
+            dot,
+            DOT_RADIUS,
+            Color32::WHITE,
+            Stroke::new(1.0, Color32::BLACK),
+        );
+        painter.rect_filled(rect.expand(2.0), 3.0, Color32::from_black_alpha(140));
+        painter.galley(rect.min, galley, Color32::WHITE);
+    }
+}
@@ -0,0 +1,292 @@
+//! Raster layers that are composited on top of the base imagery of the terrain.
+//!
+//! The base layer is the `base_color_texture` of the terrain material, the overlays are sampled
+//! and blended in the [`TerrainLayers`] material extension. On top of those, the extension draws
+//! the [`TerrainAnalysis`] overlays.
+
+use bevy::{
+    pbr::{ExtendedMaterial, MaterialExtension},
+    prelude::*,
+    render::render_resource::{AsBindGroup, ShaderType},
+    shader::ShaderRef,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{OSMConfig, RasterTileSource},
+    elevation::world_y_to_elevation,
+};
+
+const SHADER_ASSET_PATH: &str = "shaders/terrain_layers.wgsl";
+
+/// Number of overlays the terrain material has texture slots for
+pub const MAX_RASTER_OVERLAYS: usize = 3;
+
+pub type TerrainMaterial = ExtendedMaterial<StandardMaterial, TerrainLayers>;
+
+/// How an overlay is combined with the layers below it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LayerBlendMode {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+}
+
+impl LayerBlendMode {
+    pub const ALL: [LayerBlendMode; 4] = [
+        LayerBlendMode::Normal,
+        LayerBlendMode::Multiply,
+        LayerBlendMode::Screen,
+        LayerBlendMode::Overlay,
+    ];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            LayerBlendMode::Normal => "Normal",
+            LayerBlendMode::Multiply => "Multiply",
+            LayerBlendMode::Screen => "Screen",
+            LayerBlendMode::Overlay => "Overlay",
+        }
+    }
+
+    /// Must match the constants in `terrain_layers.wgsl`
+    fn to_shader_index(self) -> u32 {
+        match self {
+            LayerBlendMode::Normal => 0,
+            LayerBlendMode::Multiply => 1,
+            LayerBlendMode::Screen => 2,
+            LayerBlendMode::Overlay => 3,
+        }
+    }
+}
+
+/// A raster tile source that is drawn on top of the base layer
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RasterLayer {
+    pub source: RasterTileSource,
+    pub opacity: f32,
+    pub blend_mode: LayerBlendMode,
+    pub visible: bool,
+}
+
+impl RasterLayer {
+    pub fn new(source: RasterTileSource) -> Self {
+        Self {
+            source,
+            opacity: 1.0,
+            blend_mode: LayerBlendMode::Normal,
+            visible: true,
+        }
+    }
+}
+
+/// Colouring of the terrain derived from its elevation
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AnalysisShading {
+    #[default]
+    None,
+    /// Steepness, from green (flat) to red (45 degrees or more)
+    Slope,
+    /// The compass direction a slope faces, as a hue
+    Aspect,
+    /// Colour by elevation
+    Hypsometric,
+}
+
+impl AnalysisShading {
+    pub const ALL: [AnalysisShading; 4] = [
+        AnalysisShading::None,
+        AnalysisShading::Slope,
+        AnalysisShading::Aspect,
+        AnalysisShading::Hypsometric,
+    ];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            AnalysisShading::None => "None",
+            AnalysisShading::Slope => "Slope",
+            AnalysisShading::Aspect => "Aspect",
+            AnalysisShading::Hypsometric => "Hypsometric tint",
+        }
+    }
+
+    /// Must match the constants in `terrain_layers.wgsl`
+    fn to_shader_index(self) -> u32 {
+        match self {
+            AnalysisShading::None => 0,
+            AnalysisShading::Slope => 1,
+            AnalysisShading::Aspect => 2,
+            AnalysisShading::Hypsometric => 3,
+        }
+    }
+}
+
+/// Analytic overlays that are computed from the terrain mesh, which is built from the cached
+/// elevation tiles, so they work offline.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct TerrainAnalysis {
+    pub hillshade: bool,
+    /// Clockwise from north (degrees)
+    pub sun_azimuth: f32,
+    /// Above the horizon (degrees)
+    pub sun_altitude: f32,
+    pub shading: AnalysisShading,
+    /// Elevations that map to the ends of the hypsometric tint (meters)
+    pub hypsometric_range: Vec2,
+    pub contours: bool,
+    /// Elevation between contour lines, every fifth line is drawn thicker (meters)
+    pub contour_interval: f32,
+    pub opacity: f32,
+}
+
+impl Default for TerrainAnalysis {
+    fn default() -> Self {
+        Self {
+            hillshade: false,
+            sun_azimuth: 315.0,
+            sun_altitude: 45.0,
+            shading: AnalysisShading::None,
+            hypsometric_range: Vec2::new(0.0, 3000.0),
+            contours: false,
+            contour_interval: 10.0,
+            opacity: 0.8,
+        }
+    }
+}
+
+impl TerrainAnalysis {
+    /// Direction towards the sun in world space, in which north is -Z
+    pub fn get_sun_direction(&self) -> Vec3 {
+        let (azimuth, altitude) = (
+            self.sun_azimuth.to_radians(),
+            self.sun_altitude.to_radians(),
+        );
+        Vec3::new(
+            azimuth.sin() * altitude.cos(),
+            altitude.sin(),
+            -azimuth.cos() * altitude.cos(),
+        )
+    }
+}
+
+/// The overlay textures of a chunk, in the order of [`OSMConfig::raster_overlays`]
+#[derive(Component, Debug, Clone)]
+pub struct ChunkOverlays(pub Vec<Handle<Image>>);
+
+/// Parameters of the terrain layer shader
+#[derive(ShaderType, Debug, Clone, Default)]
+pub struct TerrainLayerSettings {
+    /// Opacity of each overlay, zero if it is hidden
+    pub opacity: Vec4,
+    pub blend_mode: UVec4,
+    /// One if the overlay has a texture
+    pub enabled: UVec4,
+    /// The area of the chunk in the tiles, as min.xy and size.zw
+    pub uv_rect: Vec4,
+    /// Direction towards the sun for the hillshade in xyz
+    pub sun_direction: Vec4,
+    /// Whether hillshade is enabled, the shading and whether contours are enabled
+    pub analysis: UVec4,
+    /// Contour interval, opacity and hypsometric range
+    pub analysis_params: Vec4,
+    /// Added to the world y to get the elevation above sea level, see [`world_y_to_elevation`]
+    pub elevation_offset: f32,
+}
+
+/// Overlays of the terrain material of a chunk
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone, Default)]
+pub struct TerrainLayers {
+    #[texture(100)]
+    #[sampler(101)]
+    pub overlay_0: Option<Handle<Image>>,
+    #[texture(102)]
+    #[sampler(103)]
+    pub overlay_1: Option<Handle<Image>>,
+    #[texture(104)]
+    #[sampler(105)]
+    pub overlay_2: Option<Handle<Image>>,
+    #[uniform(106)]
+    pub settings: TerrainLayerSettings,
+}
+
+impl TerrainLayers {
+    /// `overlays` contains the texture of every overlay in the config, or `None` if it could not
+    /// be loaded. `rect` is the area of the chunk in the normalized space of the tiles.
+    pub fn new(overlays: &[Option<Handle<Image>>], rect: Rect, config: &OSMConfig) -> Self {
+        let get = |i: usize| overlays.get(i).cloned().flatten();
+        let mut layers = Self {
+            overlay_0: get(0),
+            overlay_1: get(1),
+            overlay_2: get(2),
+            settings: TerrainLayerSettings {
+                enabled: UVec4::from_array(std::array::from_fn(|i| get(i).is_some() as u32)),
+                uv_rect: Vec4::new(rect.min.x, rect.min.y, rect.width(), rect.height()),
+                elevation_offset: world_y_to_elevation(0.0),
+                ..default()
+            },
+        };
+        layers.apply_config(config);
+        layers
+    }
+
+    /// Updates the opacity and blend modes, which can change without reloading the chunk
+    pub fn apply_config(&mut self, config: &OSMConfig) {
+        for i in 0..MAX_RASTER_OVERLAYS {
+            let (opacity, blend_mode) = config.raster_overlays.get(i).map_or((0.0, 0), |layer| {
+                (
+                    if layer.visible { layer.opacity } else { 0.0 },
+                    layer.blend_mode.to_shader_index(),
+                )
+            });
+            self.settings.opacity[i] = opacity;
+            self.settings.blend_mode[i] = blend_mode;
+        }
+
+        let analysis = &config.terrain_analysis;
+        self.settings.sun_direction = analysis.get_sun_direction().extend(0.0);
+        self.settings.analysis = UVec4::new(
+            analysis.hillshade as u32,
+            analysis.shading.to_shader_index(),
+            analysis.contours as u32,
+            0,
+        );
+        self.settings.analysis_params = Vec4::new(
+            analysis.contour_interval.max(0.1),
+            analysis.opacity,
+            analysis.hypsometric_range.x,
+            analysis.hypsometric_range.y,
+        );
+    }
+}
+
+impl MaterialExtension for TerrainLayers {
+    fn fragment_shader() -> ShaderRef {
+        SHADER_ASSET_PATH.into()
+    }
+
+    fn deferred_fragment_shader() -> ShaderRef {
+        SHADER_ASSET_PATH.into()
+    }
+}
+
+/// Applies changes of the overlay opacity, blend mode and visibility and of the terrain analysis
+/// to all terrain materials
+pub fn update_terrain_layers(
+    config: Res<OSMConfig>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+    mut applied: Local<(Vec<RasterLayer>, TerrainAnalysis)>,
+) {
+    // The config is changed by the UI every frame, only touch the materials on actual changes.
+    if applied.0 == config.raster_overlays && applied.1 == config.terrain_analysis {
+        return;
+    }
+    applied.0.clone_from(&config.raster_overlays);
+    applied.1 = config.terrain_analysis.clone();
+
+    for (_, material) in materials.iter_mut() {
+        material.extension.apply_config(&config);
+    }
+}
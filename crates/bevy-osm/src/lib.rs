@@ -1,60 +1,185 @@
+pub mod airport;
+pub mod boundary;
+pub mod builder;
 pub mod building;
 pub mod cache;
 pub mod chunk;
+#[cfg(feature = "colliders")]
+pub mod collider;
 pub mod config;
 pub mod elevation;
+pub mod export;
+pub mod flight_path;
+pub mod geocoding;
+pub mod labels;
+pub mod layers;
 pub mod load_data;
 pub mod location;
 pub mod material;
+pub mod measure;
 pub mod mesh;
+pub mod minimap;
 pub mod osm_types;
+pub mod overlay;
 pub mod performance;
+pub mod quadtree_debug;
+pub mod routing;
+pub mod scheduler;
 pub mod schema;
+pub mod settings;
+pub mod storage;
 pub mod tag;
 pub mod theme;
+pub mod traffic;
 pub mod ui;
 pub mod vector;
+pub mod water;
 
 use crate::{
     cache::ensure_session_is_valid,
     chunk::{get_chunk_for_coord, get_root_chunk_for_location},
     config::OSMConfig,
-    load_data::{handle_vector_tasks, load_unloaded_chunks, preload_chunks},
+    elevation::TILE_VERTEX_COUNT,
+    flight_path::{FlightPath, FlightPathLoader, fly_cameras_to, play_flight_paths},
+    geocoding::{Geocoding, PlaceIndex, update_geocoding},
+    labels::draw_place_labels,
+    layers::{TerrainMaterial, update_terrain_layers},
+    load_data::{
+        fill_chunks_from_ancestors, handle_chunk_tasks, load_unloaded_chunks, preload_chunks,
+    },
     material::MapMaterialHandle,
-    performance::{OSMPerformance, update_performance},
+    measure::{Measurements, draw_measurements, measure_ui, setup_measurements},
+    minimap::{Minimap, minimap_ui},
+    overlay::{GeoOverlay, GeoOverlayLoader, update_geo_overlays},
+    performance::{OSMPerformance, SessionRecorder, update_performance},
+    quadtree_debug::{QuadTreeDebug, draw_quadtree_debug, quadtree_debug_ui},
+    routing::{RoadNetwork, remove_unloaded_roads, update_routes},
+    scheduler::{LoadingBudget, retry_failed_chunks, schedule_chunk_downloads},
+    settings::{SavedSettings, SettingsOverrides, setup_settings, update_settings},
+    storage::TileStoragePlugin,
+    traffic::{TrafficAssets, TrafficConfig, spawn_traffic, update_traffic},
     ui::setup_osm_ui,
 };
 use bevy::{diagnostic::FrameTimeDiagnosticsPlugin, prelude::*};
 use bevy_egui::EguiPrimaryContextPass;
 use bevy_terrain::{
+    camera::update_terrain_camera,
     mesh::build_mesh_cache,
     quadtree::{QuadTree, QuadTreeConfig},
     system::update_terrain_quadtree,
+    water::WaterMaterial,
 };
 
+const EARTH_RADIUS_METERS: f32 = 6.371e6;
+
+/// Renders the map around [`OSMConfig::location`]. Requires [`TileStoragePlugin`] to be
+/// added before `DefaultPlugins`, for the asset source the tiles are loaded from.
 pub struct OSMPlugin;
 
 impl Plugin for OSMPlugin {
     fn build(&self, app: &mut App) {
+        assert!(
+            app.is_plugin_added::<TileStoragePlugin>(),
+            "TileStoragePlugin must be added before DefaultPlugins and OSMPlugin"
+        );
+        // Water is drawn with the material of the `WaterPlugin`, which is only added once. It has
+        // to be there before `MapMaterialHandle` creates its materials.
+        if !app.is_plugin_added::<MaterialPlugin<WaterMaterial>>() {
+            app.add_plugins(MaterialPlugin::<WaterMaterial>::default());
+        }
         app.init_resource::<MapMaterialHandle>()
             .init_resource::<OSMConfig>()
             .init_resource::<OSMPerformance>()
-            .add_plugins(FrameTimeDiagnosticsPlugin::default())
-            .add_systems(EguiPrimaryContextPass, setup_osm_ui)
-            .add_systems(Startup, (build_terrain_tile, build_mesh_cache))
+            .init_resource::<SessionRecorder>()
+            .init_resource::<LoadingBudget>()
+            .init_resource::<PlaceIndex>()
+            .init_resource::<Geocoding>()
+            .init_resource::<RoadNetwork>()
+            .init_resource::<TrafficConfig>()
+            .init_resource::<TrafficAssets>()
+            .init_resource::<Measurements>()
+            .init_resource::<Minimap>()
+            .init_resource::<QuadTreeDebug>()
+            .init_asset::<GeoOverlay>()
+            .init_asset_loader::<GeoOverlayLoader>()
+            .add_plugins((
+                FrameTimeDiagnosticsPlugin::default(),
+                MaterialPlugin::<TerrainMaterial>::default(),
+            ))
+            .add_systems(
+                EguiPrimaryContextPass,
+                (
+                    setup_osm_ui,
+                    draw_place_labels,
+                    measure_ui,
+                    minimap_ui,
+                    quadtree_debug_ui,
+                ),
+            )
+            .add_systems(
+                Startup,
+                (build_terrain_tile, build_mesh_cache, setup_measurements),
+            )
             .add_systems(
                 Update,
                 (
                     update_terrain_quadtree,
-                    handle_vector_tasks.before(update_terrain_quadtree),
+                    handle_chunk_tasks
+                        .after(load_unloaded_chunks)
+                        .before(update_terrain_quadtree),
                     load_unloaded_chunks.before(update_terrain_quadtree),
                     preload_chunks.before(update_terrain_quadtree),
+                    schedule_chunk_downloads
+                        .after(preload_chunks)
+                        .before(load_unloaded_chunks),
+                    retry_failed_chunks.before(schedule_chunk_downloads),
+                    fill_chunks_from_ancestors
+                        .after(schedule_chunk_downloads)
+                        .before(update_terrain_quadtree),
                     update_performance,
+                    update_terrain_layers,
+                    update_geo_overlays.after(handle_chunk_tasks),
+                    (remove_unloaded_roads, update_routes)
+                        .chain()
+                        .after(handle_chunk_tasks)
+                        .before(update_geo_overlays),
+                    fly_cameras_to.before(update_terrain_camera),
+                    (spawn_traffic, update_traffic)
+                        .chain()
+                        .after(handle_chunk_tasks),
+                    draw_measurements,
+                    draw_quadtree_debug,
+                    update_geocoding,
                 ),
             );
     }
 }
 
+/// Replays [`flight_path::FlightPathPlayer`] components, requires [`OSMPlugin`]
+pub struct FlightPathPlugin;
+
+impl Plugin for FlightPathPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<FlightPath>()
+            .init_asset_loader::<FlightPathLoader>()
+            .add_systems(Update, play_flight_paths.before(update_terrain_quadtree));
+    }
+}
+
+/// Restores the [`settings::OSMSettings`] of the previous session at startup and saves them when
+/// they change, requires [`OSMPlugin`]. Insert [`SettingsOverrides`] to override the saved
+/// location or raster tile source.
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SettingsOverrides>()
+            .init_resource::<SavedSettings>()
+            .add_systems(PreStartup, setup_settings)
+            .add_systems(Last, update_settings);
+    }
+}
+
 pub fn build_terrain_tile(mut commands: Commands, osm_config: Res<OSMConfig>) {
     let origin = osm_config.location.get_world_center();
 
@@ -65,9 +190,20 @@ pub fn build_terrain_tile(mut commands: Commands, osm_config: Res<OSMConfig>) {
         size: get_chunk_for_coord(origin.x as f64, origin.y as f64, 9)
             .get_size_in_meters()
             .x,
+        frustum_culling: true,
+        horizon_radius: Some(EARTH_RADIUS_METERS),
+        min_height: -500.0,
+        max_height: 9000.0,
+        max_screen_space_error: None,
+        node_resolution: TILE_VERTEX_COUNT as u32,
+        max_nodes_per_frame: Some(16),
+        max_nodes_per_level: Some(64),
     };
 
     ensure_session_is_valid(&osm_config.raster_tile_source);
+    for layer in &osm_config.raster_overlays {
+        ensure_session_is_valid(&layer.source);
+    }
 
     commands.spawn((
         Transform::IDENTITY,
@@ -1,37 +1,45 @@
-use std::{io::Read, path::Path};
+use std::{collections::HashMap, sync::Arc};
 
+#[cfg(feature = "colliders")]
+use crate::collider::TriMeshCollider;
 use crate::{
-    building::{polygon_building, spawn_building},
+    builder::{ChunkBuilder, ChunkMeshes},
     cache::{
-        cache_elevation_for_chunk, cache_raster_tile_for_chunk, cache_vector_tile_for_chunk,
-        get_elevation_cache_path, get_elevation_cache_path_bevy, get_openfreemap_cache_path,
-        get_osm_raster_cache_path, get_osm_raster_cache_path_bevy,
+        get_elevation_cache_path_bevy, get_openfreemap_cache_path, get_osm_raster_cache_path_bevy,
     },
     chunk::Chunk,
     config::OSMConfig,
-    elevation::{TILE_VERTEX_COUNT, get_elevation_local, spawn_elevation_meshes},
+    elevation::{ChunkImagery, ComputeElevation, spawn_elevation_meshes, spawn_fallback_meshes},
+    geocoding::{PlaceIndex, get_named_features},
+    layers::{ChunkOverlays, MAX_RASTER_OVERLAYS},
     material::MapMaterialHandle,
-    mesh::{BuildInstruction, LightInstruction, Shape, spawn_stroke_mesh},
-    theme::get_way_build_instruction_openfreemap,
-    vector::parse_pbf,
+    mesh::Shape,
+    performance::LOADING_COUNTERS,
+    routing::{RoadNetwork, get_lat_lon_roads, get_tile_roads},
+    scheduler::{ChunkLoadState, ChunkPriority, LoadingBudget},
+    storage::tile_storage,
+    theme::OpenFreeMapTheme,
+    traffic::{MIN_TRAFFIC_ZOOM, TrafficNetwork},
 };
 use bevy::{
-    ecs::{system::SystemState, world::CommandQueue},
+    asset::LoadState,
+    ecs::world::CommandQueue,
     prelude::*,
     tasks::{AsyncComputeTaskPool, Task, block_on, futures_lite::future},
 };
-use bevy_terrain::quadtree::{ChunkLoaded, QuadTreeNodeComponent};
+use bevy_terrain::{mesh::HeightMap, quadtree::QuadTreeNodeComponent, water::WaterMaterial};
 
 #[derive(Component)]
 pub struct ComputeTransform(pub Task<CommandQueue>);
 
+/// The vector tile task of a chunk. It lives on the chunk entity, so the task is cancelled when
+/// the chunk is despawned before it finishes.
 #[derive(Component)]
 pub struct ComputeVectorTile(pub Task<CommandQueue>);
 
 pub fn preload_chunks(
     mut commands: Commands,
     nodes_to_load: Query<(Entity, &QuadTreeNodeComponent), Without<Chunk>>,
-    config: Res<OSMConfig>,
 ) {
     nodes_to_load.iter().for_each(|(entity, node)| {
         let chunk = Chunk {
@@ -41,239 +49,426 @@ pub fn preload_chunks(
             elevation: Handle::default(),
             raster: Handle::default(),
         };
-        cache_elevation_for_chunk(&chunk);
-        cache_raster_tile_for_chunk(&chunk, &config);
-        cache_vector_tile_for_chunk(&chunk);
-
-        commands.entity(entity).insert(chunk);
+        commands
+            .entity(entity)
+            .insert((chunk, ChunkLoadState::Queued));
     });
 }
 
+/// Shows chunks that are still downloading using the tiles of their closest loaded ancestor.
+pub fn fill_chunks_from_ancestors(
+    mut commands: Commands,
+    chunks: Query<
+        (Entity, &Chunk, &QuadTreeNodeComponent, &ChunkLoadState),
+        (Without<ChunkImagery>, Without<ComputeElevation>),
+    >,
+    ancestors: Query<(&Chunk, &ChunkImagery)>,
+    images: Res<Assets<Image>>,
+    config: Res<OSMConfig>,
+    budget: Res<LoadingBudget>,
+    priority: ChunkPriority,
+) {
+    let ancestors: HashMap<(i32, i32, i8), (&Chunk, &ChunkImagery)> = ancestors
+        .iter()
+        .map(|(chunk, imagery)| ((chunk.x, chunk.y, chunk.z), (chunk, imagery)))
+        .collect();
+    if ancestors.is_empty() {
+        return;
+    }
+
+    let waiting = priority.sort(
+        chunks
+            .iter()
+            .filter(|(.., state)| {
+                matches!(
+                    state,
+                    ChunkLoadState::Queued | ChunkLoadState::Downloading(_)
+                )
+            })
+            .map(|(entity, _, node, _)| (entity, node)),
+    );
+
+    let mut builds = 0;
+    for entity in waiting {
+        if builds >= budget.max_fallback_builds_per_frame {
+            break;
+        }
+        let (_, chunk, ..) = chunks.get(entity).unwrap();
+
+        let mut parent = chunk.get_parent();
+        let ancestor = loop {
+            if let Some(ancestor) = ancestors.get(&(parent.x, parent.y, parent.z)) {
+                break Some(ancestor);
+            }
+            if parent.z <= 0 {
+                break None;
+            }
+            parent = parent.get_parent();
+        };
+        let Some((ancestor, imagery)) = ancestor else {
+            continue;
+        };
+        let Some(image) = images.get(imagery.elevation.id()) else {
+            continue;
+        };
+
+        spawn_fallback_meshes(
+            &mut commands,
+            image.clone(),
+            imagery.for_descendant(ancestor, chunk),
+            entity,
+            chunk,
+            &config,
+        );
+        builds += 1;
+    }
+}
+
 #[expect(clippy::too_many_arguments)]
 pub fn load_unloaded_chunks(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
     map_materials: Res<MapMaterialHandle>,
-    mut chunks_to_load: Query<(Entity, &mut Chunk), Without<ChunkLoaded>>,
+    mut chunks_to_load: Query<(
+        Entity,
+        &mut Chunk,
+        &QuadTreeNodeComponent,
+        &mut ChunkLoadState,
+        Option<&ChunkOverlays>,
+    )>,
     asset_server: Res<AssetServer>,
     images: Res<Assets<Image>>,
     config: Res<OSMConfig>,
+    budget: Res<LoadingBudget>,
+    priority: ChunkPriority,
 ) {
-    chunks_to_load.iter_mut().for_each(|(entity, mut chunk)| {
-        let elevation_path_str = get_elevation_cache_path(&chunk);
-        let elevation_path = Path::new(&elevation_path_str);
-        let osm_raster_path_str = get_osm_raster_cache_path(&chunk, &config);
-        let osm_raster_path = Path::new(&osm_raster_path_str);
-        let vector_path_str = get_openfreemap_cache_path(&chunk);
-        let vector_path = Path::new(&vector_path_str);
+    // Start reading the tiles of chunks that finished downloading.
+    let downloaded = priority.sort(
+        chunks_to_load
+            .iter()
+            .filter(|(_, _, _, state, _)| {
+                matches!(state, ChunkLoadState::Downloading(progress) if progress.is_finished())
+            })
+            .map(|(entity, _, node, ..)| (entity, node)),
+    );
+    for entity in downloaded.into_iter().take(budget.max_disk_reads_per_frame) {
+        let (_, mut chunk, _, mut state, _) = chunks_to_load.get_mut(entity).unwrap();
 
-        if elevation_path.exists() && osm_raster_path.exists() && vector_path.exists() {
-            chunk.elevation = asset_server.load(get_elevation_cache_path_bevy(&chunk));
-            chunk.raster = asset_server.load(get_osm_raster_cache_path_bevy(&chunk, &config));
+        if let ChunkLoadState::Downloading(progress) = state.as_ref()
+            && progress.has_failed()
+        {
+            *state = ChunkLoadState::Failed;
+            continue;
+        }
 
-            if asset_server.is_loaded(chunk.elevation.id()) {
-                load_chunk(
-                    &mut commands,
-                    &mut meshes,
-                    &mut materials,
-                    &map_materials,
-                    &images,
-                    &config,
-                    entity,
-                    chunk.clone(),
-                )
-            }
+        chunk.elevation = asset_server.load(get_elevation_cache_path_bevy(&chunk));
+        chunk.raster = asset_server.load(get_osm_raster_cache_path_bevy(
+            &chunk,
+            &config.raster_tile_source,
+        ));
+        let overlays = config
+            .raster_overlays
+            .iter()
+            .take(MAX_RASTER_OVERLAYS)
+            .map(|layer| asset_server.load(get_osm_raster_cache_path_bevy(&chunk, &layer.source)))
+            .collect();
+        commands.entity(entity).insert(ChunkOverlays(overlays));
+        *state = ChunkLoadState::Parsing;
+    }
+
+    let is_settled = |image: &Handle<Image>| {
+        asset_server.is_loaded(image.id())
+            || matches!(asset_server.load_state(image.id()), LoadState::Failed(_))
+    };
+
+    // Build the meshes of chunks of which the heightmap and overlays have been decoded.
+    let parsed = priority.sort(
+        chunks_to_load
+            .iter()
+            .filter(|(_, chunk, _, state, overlays)| {
+                matches!(state, ChunkLoadState::Parsing)
+                    && is_settled(&chunk.elevation)
+                    && overlays.is_none_or(|overlays| overlays.0.iter().all(is_settled))
+            })
+            .map(|(entity, _, node, ..)| (entity, node)),
+    );
+    for entity in parsed.into_iter().take(budget.max_mesh_builds_per_frame) {
+        let (_, chunk, _, mut state, overlays) = chunks_to_load.get_mut(entity).unwrap();
+
+        if !asset_server.is_loaded(chunk.elevation.id()) {
+            *state = ChunkLoadState::Failed;
+            continue;
         }
-    });
+
+        load_chunk(
+            &mut commands,
+            &map_materials,
+            &images,
+            &config,
+            entity,
+            chunk.clone(),
+            overlays.map_or(&[][..], |overlays| &overlays.0),
+        );
+        // Otherwise the chunk is picked again next frame, which would restart its mesh task
+        *state = ChunkLoadState::Building;
+    }
 }
 
-#[expect(clippy::too_many_arguments)]
 pub fn load_chunk(
     commands: &mut Commands,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    materials: &mut ResMut<Assets<StandardMaterial>>,
-    map_materials: &Res<MapMaterialHandle>,
-    images: &Res<Assets<Image>>,
-    config: &Res<OSMConfig>,
+    map_materials: &MapMaterialHandle,
+    images: &Assets<Image>,
+    config: &OSMConfig,
     chunk_entity: Entity,
     chunk: Chunk,
+    overlays: &[Handle<Image>],
 ) {
-    let elevation = chunk.elevation.id();
     let heightmap = images
-        .get(elevation)
+        .get(chunk.elevation.id())
         .expect("Image should have loaded by now")
         .clone();
+    // Overlays that failed to load are left out, instead of keeping the chunk from rendering.
+    let overlays = overlays
+        .iter()
+        .map(|overlay| images.contains(overlay.id()).then(|| overlay.clone()))
+        .collect();
 
     // let vector_tile_chunk = match chunk.z > 14 {
     //     true => chunk.get_parent_at_z(14),
     //     false => chunk.clone(),
     // };
 
-    let thread_pool = AsyncComputeTaskPool::get();
-    let get_elevation = move |translation: Vec3, heightmap: &Image| {
-        if !Rect::from_center_size(Vec2::ZERO, Vec2::ONE).contains(translation.xz()) {
-            return None;
-        }
-        let local_coords =
-            ((Vec2::new(0.5, 0.5) + translation.xz()) * TILE_VERTEX_COUNT as f32).as_ivec2();
-        Some(get_elevation_local(heightmap, local_coords))
-    };
-
-    // Spawn an async task to process the vector tile off the main thread.
     let building_material = map_materials.unknown_building.clone();
     let light_material = map_materials.light.clone();
-    let vector_entity = commands.spawn_empty().id();
+    let water_material = map_materials.water.clone();
+    let airport_material = map_materials.airport.clone();
     let chunk_for_vector = chunk.clone();
 
-    let vector_task = thread_pool.spawn(async move {
-        let path = get_openfreemap_cache_path(&chunk_for_vector);
-        let mut bytes = Vec::new();
-        std::fs::File::open(&path)
-            .expect("Vector tile file should exist")
-            .read_to_end(&mut bytes)
-            .expect("Could not read vector tile file");
-
-        let instructions = parse_pbf(bytes).unwrap_or_default();
-
-        let mut rng = rand::rng();
-        let mut computed_strokes: Vec<Mesh> = Vec::new();
-        let mut computed_buildings: Vec<Mesh> = Vec::new();
-        let mut lights = Vec::new();
-
-        for (tags, layer, polygon) in instructions {
-            match get_way_build_instruction_openfreemap(tags, layer) {
-                BuildInstruction::Stroke(stroke) => {
-                    let center = polygon[0];
-                    lights.push(LightInstruction {
-                        trans: Vec3::new(
-                            center.x,
-                            get_elevation(Vec3::new(center.x, 0.0, center.y), &heightmap)
-                                .unwrap_or(0.0)
-                                + 2.0,
-                            center.y,
-                        ),
-                    });
-                    computed_strokes.push(spawn_stroke_mesh(polygon, stroke));
-                }
-                BuildInstruction::Building(building_instr) => {
-                    let building = polygon_building(&building_instr, polygon, &mut rng);
-                    let mesh = spawn_building(&building);
-                    computed_buildings.push(mesh.translated_by(
-                        Vec3::Y
-                            * get_elevation(building.get_translation(), &heightmap).unwrap_or(0.0),
-                    ));
-                }
-                _ => {}
-            }
-        }
+    // The vector tile is processed once the terrain is there, because objects are placed on it.
+    spawn_elevation_meshes(
+        commands,
+        heightmap,
+        overlays,
+        chunk_entity,
+        chunk,
+        config,
+        move |world, heights| {
+            let vector_task = spawn_vector_task(
+                chunk_for_vector,
+                chunk_entity,
+                heights,
+                building_material,
+                light_material,
+                water_material,
+                airport_material,
+            );
+            world
+                .entity_mut(chunk_entity)
+                .insert(ComputeVectorTile(vector_task));
+        },
+    );
+}
 
-        let mut merged_buildings: Vec<Mesh> = Vec::new();
+/// Spawns an async task to process the vector tile off the main thread.
+fn spawn_vector_task(
+    chunk: Chunk,
+    chunk_entity: Entity,
+    heights: Arc<HeightMap>,
+    building_material: Handle<StandardMaterial>,
+    light_material: Handle<StandardMaterial>,
+    water_material: Handle<WaterMaterial>,
+    airport_material: Handle<StandardMaterial>,
+) -> Task<CommandQueue> {
+    AsyncComputeTaskPool::get().spawn(async move {
+        let bytes = tile_storage()
+            .read(&get_openfreemap_cache_path(&chunk))
+            .expect("Vector tile should be cached");
 
-        if !computed_buildings.is_empty() {
-            let mut first = computed_buildings[0].clone();
-            for other in computed_buildings.iter().skip(1) {
-                first.merge(other).expect("could not merge buildings");
-            }
-            merged_buildings.push(first);
+        let names = get_named_features(bytes.clone(), &chunk).unwrap_or_default();
+        let roads = get_tile_roads(bytes.clone()).unwrap_or_default();
+        let traffic = (chunk.z >= MIN_TRAFFIC_ZOOM)
+            .then(|| TrafficNetwork::new(&roads, &heights, chunk.get_size_in_meters()));
+        let roads = get_lat_lon_roads(roads, &chunk);
+        let meshes = ChunkBuilder::new(&heights, &OpenFreeMapTheme)
+            .with_seed(chunk.get_seed())
+            .with_size(chunk.get_size_in_meters())
+            .build(bytes);
+        // A tile that failed to parse has no durations, which would skew the averages
+        if let Ok(meshes) = &meshes {
+            LOADING_COUNTERS.record_parse(meshes.parse_time);
+            LOADING_COUNTERS.record_mesh_build(meshes.mesh_build_time);
         }
+        let ChunkMeshes {
+            strokes,
+            buildings,
+            lights,
+            water,
+            airport,
+            boundaries,
+            places,
+            ..
+        } = meshes.unwrap_or_default();
 
-        let light_transforms = lights
-            .into_iter()
-            .map(|light| Transform::from_translation(light.trans))
-            .collect::<Vec<Transform>>();
+        #[cfg(feature = "colliders")]
+        let building_collider = buildings.as_ref().and_then(TriMeshCollider::from_mesh);
 
         let mut command_queue = CommandQueue::default();
         command_queue.push(move |world: &mut World| {
             // If the chunk was despawned while the async task was running, discard
-            // the results and clean up the task entity to avoid a panic.
+            // the results to avoid a panic.
             if world.get_entity(chunk_entity).is_err() {
-                if let Ok(ve) = world.get_entity_mut(vector_entity) {
-                    ve.despawn();
-                }
                 return;
             }
+            if let Some(mut index) = world.get_resource_mut::<PlaceIndex>() {
+                index.insert_tile(&chunk, names);
+            }
+            if let Some(mut network) = world.get_resource_mut::<RoadNetwork>() {
+                network.insert_tile(chunk_entity, &chunk, roads);
+            }
 
-            let mut meshes = SystemState::<ResMut<Assets<Mesh>>>::new(world)
-                .get_mut(world)
-                .unwrap();
-
-            let light_mesh = meshes.add(Cuboid::from_size(Vec3::new(0.003, 5.0, 0.003)));
-
-            let stroke_handles: Vec<Handle<Mesh>> = computed_strokes
-                .into_iter()
-                .map(|m| meshes.add(m))
-                .collect();
-
-            let building_handles: Vec<Mesh3d> = merged_buildings
-                .into_iter()
-                .map(|m| Mesh3d(meshes.add(m)))
-                .collect();
+            // Every material gets a single mesh per chunk, to keep the number of entities and
+            // draw calls low.
+            let mut meshes = world.resource_mut::<Assets<Mesh>>();
+            let strokes = strokes.map(|mesh| meshes.add(mesh));
+            let buildings = buildings.map(|mesh| meshes.add(mesh));
+            let water = water.map(|mesh| meshes.add(mesh));
+            let airport_surfaces = airport.surfaces.map(|mesh| meshes.add(mesh));
+            let boundaries = boundaries.map(|mesh| meshes.add(mesh));
 
-            for handle in stroke_handles {
-                let stroke = world
-                    .spawn((
-                        Mesh3d(handle),
-                        MeshMaterial3d(building_material.clone()),
-                        Shape,
-                    ))
-                    .id();
-                world.entity_mut(chunk_entity).add_child(stroke);
+            let mut children = Vec::new();
+            if let Some(strokes) = strokes {
+                children.push(
+                    world
+                        .spawn((
+                            Mesh3d(strokes),
+                            MeshMaterial3d(building_material.clone()),
+                            Shape,
+                        ))
+                        .id(),
+                );
+            }
+            if let Some(buildings) = buildings {
+                children.push(
+                    world
+                        .spawn((
+                            Mesh3d(buildings),
+                            MeshMaterial3d(building_material),
+                            Transform::IDENTITY,
+                        ))
+                        .id(),
+                );
+            }
+            // The lights share a mesh and material, so Bevy draws them as instances in a single
+            // draw call.
+            let light_mesh = world.resource::<MapMaterialHandle>().light_mesh.clone();
+            for transform in lights {
+                children.push(
+                    world
+                        .spawn((
+                            Mesh3d(light_mesh.clone()),
+                            MeshMaterial3d(light_material.clone()),
+                            transform,
+                        ))
+                        .id(),
+                );
+            }
+            if let Some(water) = water {
+                children.push(
+                    world
+                        .spawn((
+                            Mesh3d(water),
+                            MeshMaterial3d(water_material),
+                            Transform::IDENTITY,
+                        ))
+                        .id(),
+                );
+            }
+            if let Some(airport_surfaces) = airport_surfaces {
+                children.push(
+                    world
+                        .spawn((
+                            Mesh3d(airport_surfaces),
+                            MeshMaterial3d(airport_material),
+                            Transform::IDENTITY,
+                        ))
+                        .id(),
+                );
+            }
+            // Instanced like the street lights, with a material for each color
+            let handles = world.resource::<MapMaterialHandle>();
+            let airport_light_mesh = handles.airport_light_mesh.clone();
+            let airport_light_materials = handles.airport_lights.clone();
+            for (transform, color) in airport.lights {
+                children.push(
+                    world
+                        .spawn((
+                            Mesh3d(airport_light_mesh.clone()),
+                            MeshMaterial3d(airport_light_materials[&color].clone()),
+                            transform,
+                        ))
+                        .id(),
+                );
+            }
+            for runway in airport.runways {
+                children.push(world.spawn((runway, Transform::IDENTITY)).id());
+            }
+            if let Some(boundaries) = boundaries {
+                let boundary_material = world.resource::<MapMaterialHandle>().boundary.clone();
+                children.push(
+                    world
+                        .spawn((
+                            Mesh3d(boundaries),
+                            MeshMaterial3d(boundary_material),
+                            Transform::IDENTITY,
+                        ))
+                        .id(),
+                );
+            }
+            for (label, position) in places {
+                children.push(
+                    world
+                        .spawn((label, Transform::from_translation(position)))
+                        .id(),
+                );
             }
 
-            for mesh3d in building_handles {
-                let bm = world
-                    .spawn((
-                        mesh3d,
-                        MeshMaterial3d(building_material.clone()),
-                        Transform::IDENTITY,
-                    ))
-                    .id();
-                world.entity_mut(chunk_entity).add_child(bm);
+            #[cfg(feature = "colliders")]
+            if let Some(collider) = building_collider {
+                children.push(world.spawn((collider, Transform::IDENTITY)).id());
             }
 
-            for transform in light_transforms {
-                let l = world
-                    .spawn((
-                        Mesh3d(light_mesh.clone()),
-                        MeshMaterial3d(light_material.clone()),
-                        transform,
-                    ))
-                    .id();
-                world.entity_mut(chunk_entity).add_child(l);
+            let mut entity = world.entity_mut(chunk_entity);
+            entity.add_children(&children).remove::<ComputeVectorTile>();
+            if let Some(traffic) = traffic {
+                entity.insert(traffic);
             }
-            world
-                .entity_mut(vector_entity)
-                .remove::<ComputeVectorTile>();
         });
         command_queue
-    });
-
-    commands
-        .entity(vector_entity)
-        .insert(ComputeVectorTile(vector_task));
-
-    spawn_elevation_meshes(
-        commands,
-        meshes,
-        materials,
-        &images
-            .get(elevation)
-            .expect("Image should have loaded by now")
-            .clone(),
-        chunk_entity,
-        chunk.clone(),
-        config,
-    );
+    })
 }
 
-pub fn handle_vector_tasks(
+pub fn handle_chunk_tasks(
     mut commands: Commands,
+    mut elevation_tasks: Query<&mut ComputeElevation>,
     mut vector_tasks: Query<&mut ComputeVectorTile>,
+    budget: Res<LoadingBudget>,
 ) {
-    for mut task in &mut vector_tasks {
-        if let Some(mut commands_queue) = block_on(future::poll_once(&mut task.0)) {
+    let tasks = elevation_tasks
+        .iter_mut()
+        .map(|task| task.map_unchanged(|task| &mut task.0))
+        .chain(
+            vector_tasks
+                .iter_mut()
+                .map(|task| task.map_unchanged(|task| &mut task.0)),
+        );
+
+    let mut uploads = 0;
+    for mut task in tasks {
+        if uploads >= budget.max_gpu_uploads_per_frame {
+            break;
+        }
+        if let Some(mut commands_queue) = block_on(future::poll_once(&mut *task)) {
             commands.append(&mut commands_queue);
+            uploads += 1;
         }
     }
 }
@@ -1,7 +1,8 @@
 use bevy::math::Vec2;
+use serde::{Deserialize, Serialize};
 use strum_macros::Display;
 
-#[derive(PartialEq, Eq, Hash, Display, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Display, Clone)]
 pub enum Location {
     Amsterdam,
     London,
@@ -10,6 +11,13 @@ pub enum Location {
 }
 
 impl Location {
+    pub const ALL: [Location; 4] = [
+        Location::Amsterdam,
+        Location::London,
+        Location::Monaco,
+        Location::NewYork,
+    ];
+
     pub fn get_world_center(&self) -> Vec2 {
         match self {
             Self::Amsterdam => Vec2::new(52.2798, 4.6026),
@@ -26,4 +34,16 @@ impl Location {
             Self::NewYork => "New York".into(),
         }
     }
+    /// Finds a location by its name, ignoring case, spaces and dashes
+    pub fn from_name(name: &str) -> Option<Self> {
+        let normalize = |name: &str| {
+            name.chars()
+                .filter(|c| !matches!(c, ' ' | '-' | '_'))
+                .collect::<String>()
+                .to_lowercase()
+        };
+        Self::ALL
+            .into_iter()
+            .find(|location| normalize(&location.get_name()) == normalize(name))
+    }
 }
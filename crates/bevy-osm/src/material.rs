@@ -1,11 +1,16 @@
 use bevy::{
     color::LinearRgba,
-    prelude::{Assets, Color, FromWorld, Handle, Resource, StandardMaterial, World, default},
+    math::Vec3,
+    prelude::{
+        AssetServer, Assets, Color, Cuboid, FromWorld, Handle, Mesh, Resource, StandardMaterial,
+        World, default,
+    },
 };
+use bevy_terrain::water::{WaterMaterial, get_water_material};
 use std::collections::HashMap;
 use strum::IntoEnumIterator;
 
-use crate::osm_types::BuildingClass;
+use crate::{airport::AirportLightColor, osm_types::BuildingClass};
 
 type Reflectance = f32;
 type Roughness = f32;
@@ -29,8 +34,19 @@ pub struct MapMaterialHandle {
     pub roofs: HashMap<BuildingClass, Handle<StandardMaterial>>,
     pub walls: HashMap<BuildingClass, Handle<StandardMaterial>>,
     pub light: Handle<StandardMaterial>,
+    /// A street light in the local space of a chunk, shared by all lights so they are instanced
+    pub light_mesh: Handle<Mesh>,
     pub unknown_building: Handle<StandardMaterial>,
     pub unknown_building_roof: Handle<StandardMaterial>,
+    pub water: Handle<WaterMaterial>,
+    /// Runways, taxiways and aprons, colored by their vertex colors
+    pub airport: Handle<StandardMaterial>,
+    /// The materials of the airport lights, by color
+    pub airport_lights: HashMap<AirportLightColor, Handle<StandardMaterial>>,
+    /// A unit cube, shared by all airport lights so they are instanced
+    pub airport_light_mesh: Handle<Mesh>,
+    /// Administrative boundaries, colored by their vertex colors
+    pub boundary: Handle<StandardMaterial>,
     // pub road: HashMap<RoadClass, Handle<StandardMaterial>>,
 }
 impl FromWorld for MapMaterialHandle {
@@ -119,6 +135,48 @@ impl FromWorld for MapMaterialHandle {
         //         .or_insert_with_key(|_key| road_color_handle);
         // }
 
+        let light_mesh = world
+            .resource_mut::<Assets<Mesh>>()
+            .add(Cuboid::from_size(Vec3::new(0.003, 5.0, 0.003)));
+        let airport_light_mesh = world
+            .resource_mut::<Assets<Mesh>>()
+            .add(Cuboid::from_size(Vec3::ONE));
+
+        let mut standard_materials = world.resource_mut::<Assets<StandardMaterial>>();
+        let airport = standard_materials.add(StandardMaterial {
+            base_color: Color::WHITE,
+            reflectance: 0.3,
+            perceptual_roughness: 0.9,
+            ..default()
+        });
+
+        let airport_lights = AirportLightColor::ALL
+            .into_iter()
+            .map(|color| {
+                let material = standard_materials.add(StandardMaterial {
+                    base_color: color.get_color(),
+                    unlit: true,
+                    ..default()
+                });
+                (color, material)
+            })
+            .collect();
+
+        // The winding of the tessellated dashes depends on the direction of the boundary.
+        let boundary = standard_materials.add(StandardMaterial {
+            base_color: Color::WHITE,
+            unlit: true,
+            cull_mode: None,
+            depth_bias: 10.0,
+            ..default()
+        });
+
+        let mut water = get_water_material(world.resource::<AssetServer>());
+        // The winding of the tessellated water polygons depends on the vector tile, so both sides
+        // are drawn.
+        water.base.cull_mode = None;
+        let water = world.resource_mut::<Assets<WaterMaterial>>().add(water);
+
         Self {
             roof,
             roofs,
@@ -126,6 +184,12 @@ impl FromWorld for MapMaterialHandle {
             unknown_building,
             unknown_building_roof,
             light,
+            light_mesh,
+            water,
+            airport,
+            airport_lights,
+            airport_light_mesh,
+            boundary,
         }
     }
 }
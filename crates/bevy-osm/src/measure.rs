@@ -0,0 +1,563 @@
+//! Measurement tools and annotations for reviewing scenes.
+//!
+//! Points are picked on the terrain with the mouse. Distances and areas are measured on the
+//! globe through [`world_to_lat_lon`], heights come from the loaded elevation tiles. Annotations
+//! are pinned at a (lat, lon, elevation) and saved to [`ANNOTATIONS_KEY`] in the
+//! [`data_storage`], so they survive restarts and changes of location.
+
+use std::io;
+
+use bevy::{math::DVec2, prelude::*, window::PrimaryWindow};
+use bevy_egui::{
+    EguiContexts,
+    egui::{self, Align2, Color32, ComboBox, FontId, Pos2, Stroke},
+};
+use bevy_terrain::{
+    camera::get_ground_height, mesh::ChunkHeightMap, quadtree::QuadTreeNodeComponent,
+};
+use egui_plot::{Line, Plot, PlotPoints};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chunk::{lat_lon_to_world, world_to_lat_lon},
+    config::OSMConfig,
+    elevation::{elevation_to_world_y, world_y_to_elevation},
+    routing::{METERS_PER_DEGREE, get_distance},
+    storage::data_storage,
+};
+
+pub const ANNOTATIONS_KEY: &str = "annotations.json";
+/// Number of points the elevation profile is sampled at
+const PROFILE_SAMPLES: usize = 200;
+/// Farthest a picking ray is followed (meters)
+const MAX_PICK_DISTANCE: f32 = 2e5;
+const MAX_PICK_STEPS: usize = 2000;
+/// Step of a picking ray over terrain that is not loaded (meters)
+const UNLOADED_PICK_STEP: f32 = 50.0;
+/// Height of the measured lines above the terrain (meters)
+const LINE_OFFSET: f32 = 1.0;
+const MEASURE_COLOR: Color = Color::srgb(1.0, 0.85, 0.1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MeasureTool {
+    /// Between two points
+    #[default]
+    Distance,
+    /// Along any number of points
+    Polyline,
+    /// Enclosed by the points
+    Area,
+    /// Elevation along the points
+    Profile,
+    /// Pins an annotation at every click
+    Annotate,
+}
+
+impl MeasureTool {
+    pub const ALL: [MeasureTool; 5] = [
+        MeasureTool::Distance,
+        MeasureTool::Polyline,
+        MeasureTool::Area,
+        MeasureTool::Profile,
+        MeasureTool::Annotate,
+    ];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            MeasureTool::Distance => "Distance",
+            MeasureTool::Polyline => "Polyline length",
+            MeasureTool::Area => "Polygon area",
+            MeasureTool::Profile => "Elevation profile",
+            MeasureTool::Annotate => "Annotate",
+        }
+    }
+}
+
+/// A note pinned to the terrain
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub text: String,
+    /// (degrees)
+    pub lat: f32,
+    pub lon: f32,
+    /// Above sea level (meters)
+    pub elevation: f32,
+}
+
+impl Annotation {
+    pub fn new(text: String, position: Vec3, origin: Vec2) -> Self {
+        let (lat, lon) = world_to_lat_lon(position, origin);
+        Self {
+            text,
+            lat,
+            lon,
+            elevation: world_y_to_elevation(position.y),
+        }
+    }
+
+    pub fn get_world_position(&self, origin: Vec2) -> Vec3 {
+        let (x, z) = lat_lon_to_world(Vec2::new(self.lat, self.lon), origin);
+        Vec3::new(x as f32, elevation_to_world_y(self.elevation), z as f32)
+    }
+}
+
+pub fn load_annotations() -> io::Result<Vec<Annotation>> {
+    Ok(serde_json::from_slice(
+        &data_storage().read(ANNOTATIONS_KEY)?,
+    )?)
+}
+
+pub fn save_annotations(annotations: &[Annotation]) -> io::Result<()> {
+    data_storage().write(ANNOTATIONS_KEY, &serde_json::to_vec_pretty(annotations)?)
+}
+
+/// The state of the measurement tools
+#[derive(Resource, Debug, Default)]
+pub struct Measurements {
+    pub enabled: bool,
+    pub tool: MeasureTool,
+    /// The picked points in world space
+    pub points: Vec<Vec3>,
+    /// (distance along the line, elevation) in meters
+    pub profile: Vec<[f64; 2]>,
+    pub annotations: Vec<Annotation>,
+    /// Text of the next annotation
+    pub text: String,
+}
+
+impl Measurements {
+    pub fn clear(&mut self) {
+        self.points.clear();
+        self.profile.clear();
+    }
+
+    /// Pins an annotation and saves all annotations
+    pub fn pin(&mut self, text: String, position: Vec3, origin: Vec2) {
+        self.annotations
+            .push(Annotation::new(text, position, origin));
+        self.save();
+    }
+
+    pub fn save(&self) {
+        match save_annotations(&self.annotations) {
+            Ok(()) => info!("Saved annotations to {ANNOTATIONS_KEY}"),
+            Err(err) => error!("Could not save annotations: {err}"),
+        }
+    }
+}
+
+fn to_lat_lon(position: Vec3, origin: Vec2) -> DVec2 {
+    let (lat, lon) = world_to_lat_lon(position, origin);
+    DVec2::new(lat as f64, lon as f64)
+}
+
+/// Distance between two points along the ground, ignoring their heights (meters)
+pub fn get_ground_distance(a: Vec3, b: Vec3, origin: Vec2) -> f64 {
+    get_distance(to_lat_lon(a, origin), to_lat_lon(b, origin))
+}
+
+/// Straight-line distance between two points, including their heights (meters)
+pub fn get_slant_distance(a: Vec3, b: Vec3, origin: Vec2) -> f64 {
+    get_ground_distance(a, b, origin).hypot((b.y - a.y) as f64)
+}
+
+/// Sum of the ground distances between consecutive points (meters)
+pub fn get_polyline_length(points: &[Vec3], origin: Vec2) -> f64 {
+    points
+        .windows(2)
+        .map(|segment| get_ground_distance(segment[0], segment[1], origin))
+        .sum()
+}
+
+/// Area of the polygon through the points, projected on the ground (m²)
+pub fn get_polygon_area(points: &[Vec3], origin: Vec2) -> f64 {
+    let Some(first) = points.first() else {
+        return 0.0;
+    };
+    let center = to_lat_lon(*first, origin);
+    let local: Vec<DVec2> = points
+        .iter()
+        .map(|point| {
+            let lat_lon = to_lat_lon(*point, origin) - center;
+            DVec2::new(
+                lat_lon.y * METERS_PER_DEGREE * center.x.to_radians().cos(),
+                lat_lon.x * METERS_PER_DEGREE,
+            )
+        })
+        .collect();
+    let twice_area: f64 = local
+        .iter()
+        .zip(local.iter().cycle().skip(1))
+        .map(|(a, b)| a.perp_dot(*b))
+        .sum();
+    twice_area.abs() / 2.0
+}
+
+/// Samples the terrain at `samples` points evenly spread along the line, as (distance along the
+/// line, elevation above sea level) in meters. `ground` returns the world y of the terrain,
+/// points without terrain are left out.
+pub fn get_elevation_profile(
+    points: &[Vec3],
+    samples: usize,
+    origin: Vec2,
+    ground: impl Fn(Vec2) -> Option<f32>,
+) -> Vec<[f64; 2]> {
+    let length = get_polyline_length(points, origin);
+    if points.len() < 2 || samples < 2 || length <= 0.0 {
+        return Vec::new();
+    }
+
+    let mut profile = Vec::with_capacity(samples);
+    let mut start = 0.0;
+    // The next sample, samples on the last segment also take up the rounding errors
+    let mut next = 0;
+    let segments = points.len() - 1;
+    for (index, segment) in points.windows(2).enumerate() {
+        let segment_length = get_ground_distance(segment[0], segment[1], origin);
+        let end = start + segment_length;
+        while next < samples {
+            let distance = next as f64 / (samples - 1) as f64 * length;
+            if distance > end && index + 1 < segments {
+                break;
+            }
+            let t = ((distance - start) / segment_length.max(f64::EPSILON)).min(1.0) as f32;
+            if let Some(height) = ground(segment[0].lerp(segment[1], t).xz()) {
+                profile.push([distance, world_y_to_elevation(height) as f64]);
+            }
+            next += 1;
+        }
+        start = end;
+    }
+    profile
+}
+
+/// Follows a ray until it hits the terrain
+pub fn pick_terrain(ray: Ray3d, ground: impl Fn(Vec2) -> Option<f32>) -> Option<Vec3> {
+    let mut previous = 0.0;
+    let mut t = 0.0;
+    for _ in 0..MAX_PICK_STEPS {
+        let point = ray.get_point(t);
+        match ground(point.xz()) {
+            Some(height) if point.y <= height => {
+                // Refine between the last point above the terrain and this one
+                let (mut above, mut below) = (previous, t);
+                for _ in 0..20 {
+                    let middle = (above + below) / 2.0;
+                    let point = ray.get_point(middle);
+                    match ground(point.xz()) {
+                        Some(height) if point.y <= height => below = middle,
+                        _ => above = middle,
+                    }
+                }
+                let hit = ray.get_point(below);
+                return Some(hit.with_y(ground(hit.xz()).unwrap_or(hit.y)));
+            }
+            Some(height) => {
+                previous = t;
+                t += ((point.y - height) * 0.5).max(1.0);
+            }
+            None => {
+                previous = t;
+                t += UNLOADED_PICK_STEP;
+            }
+        }
+        if t > MAX_PICK_DISTANCE {
+            break;
+        }
+    }
+    None
+}
+
+fn format_distance(meters: f64) -> String {
+    if meters < 1000.0 {
+        format!("{meters:.1} m")
+    } else {
+        format!("{:.3} km", meters / 1000.0)
+    }
+}
+
+fn format_area(square_meters: f64) -> String {
+    if square_meters < 1e6 {
+        format!("{square_meters:.0} m²")
+    } else {
+        format!("{:.3} km²", square_meters / 1e6)
+    }
+}
+
+/// The result of the current measurement, `None` if there are not enough points
+fn get_result(measurements: &Measurements, origin: Vec2) -> Option<String> {
+    let points = &measurements.points;
+    match measurements.tool {
+        MeasureTool::Distance if points.len() == 2 => Some(format!(
+            "ground {}, slant {}",
+            format_distance(get_ground_distance(points[0], points[1], origin)),
+            format_distance(get_slant_distance(points[0], points[1], origin)),
+        )),
+        MeasureTool::Polyline | MeasureTool::Profile if points.len() >= 2 => Some(format!(
+            "length {}",
+            format_distance(get_polyline_length(points, origin))
+        )),
+        MeasureTool::Area if points.len() >= 3 => Some(format!(
+            "area {}, perimeter {}",
+            format_area(get_polygon_area(points, origin)),
+            format_distance(get_polyline_length(
+                &[points.as_slice(), &points[..1]].concat(),
+                origin
+            )),
+        )),
+        _ => None,
+    }
+}
+
+pub fn setup_measurements(mut measurements: ResMut<Measurements>) {
+    if !data_storage().contains(ANNOTATIONS_KEY) {
+        return;
+    }
+    match load_annotations() {
+        Ok(annotations) => measurements.annotations = annotations,
+        Err(err) => error!("Could not load annotations: {err}"),
+    }
+}
+
+fn measure_window(ui: &mut egui::Ui, measurements: &mut Measurements, origin: Vec2) {
+    let mut tool = measurements.tool;
+    ComboBox::from_label("tool")
+        .selected_text(tool.get_name())
+        .show_ui(ui, |ui| {
+            for option in MeasureTool::ALL {
+                ui.selectable_value(&mut tool, option, option.get_name());
+            }
+        });
+    if tool != measurements.tool {
+        measurements.tool = tool;
+        measurements.clear();
+    }
+
+    ui.label(format!("points: {}", measurements.points.len()));
+    let result = get_result(measurements, origin);
+    if let Some(result) = &result {
+        ui.label(result);
+    }
+    if measurements.tool == MeasureTool::Profile && !measurements.profile.is_empty() {
+        Plot::new("Elevation profile")
+            .height(150.0)
+            .x_axis_label("distance (m)")
+            .y_axis_label("elevation (m)")
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(
+                    "elevation",
+                    PlotPoints::from(measurements.profile.clone()),
+                ));
+            });
+    }
+
+    ui.horizontal(|ui| {
+        ui.label("note:");
+        ui.text_edit_singleline(&mut measurements.text);
+    });
+    ui.horizontal(|ui| {
+        if ui.button("Clear").clicked() {
+            measurements.clear();
+        }
+        // Pins the note, or the result of the measurement, at the last point
+        let last = measurements.points.last().copied();
+        if ui
+            .add_enabled(last.is_some(), egui::Button::new("Pin"))
+            .clicked()
+            && let Some(position) = last
+        {
+            let text = match (measurements.text.is_empty(), result) {
+                (false, _) | (true, None) => std::mem::take(&mut measurements.text),
+                (true, Some(result)) => result,
+            };
+            measurements.pin(text, position, origin);
+        }
+    });
+
+    ui.separator();
+    let mut removed = None;
+    for (index, annotation) in measurements.annotations.iter().enumerate() {
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "{} ({:.5}, {:.5}, {:.0} m)",
+                annotation.text, annotation.lat, annotation.lon, annotation.elevation
+            ));
+            if ui.small_button("x").clicked() {
+                removed = Some(index);
+            }
+        });
+    }
+    if let Some(index) = removed {
+        measurements.annotations.remove(index);
+        measurements.save();
+    }
+}
+
+/// Shows the measurement window, picks points on the terrain and paints the annotations. The
+/// tools are toggled with M.
+#[expect(clippy::too_many_arguments)]
+pub fn measure_ui(
+    mut contexts: EguiContexts,
+    mut measurements: ResMut<Measurements>,
+    config: Res<OSMConfig>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    window: Single<&Window, With<PrimaryWindow>>,
+    camera: Single<(&Camera, &GlobalTransform)>,
+    chunks: Query<(&ChunkHeightMap, &GlobalTransform, &QuadTreeNodeComponent)>,
+) {
+    let Ok(ctx) = contexts.ctx_mut() else {
+        return;
+    };
+    let (camera, camera_transform) = *camera;
+    let origin = config.location.get_world_center();
+    let ground = |position: Vec2| get_ground_height(&chunks, position);
+
+    if keys.just_pressed(KeyCode::KeyM) {
+        measurements.enabled = !measurements.enabled;
+    }
+    if config.ui_visible && measurements.enabled {
+        let mut enabled = measurements.enabled;
+        egui::Window::new("Measure")
+            .open(&mut enabled)
+            .default_pos(Pos2::new(10.0, 10.0))
+            .show(ctx, |ui| measure_window(ui, &mut measurements, origin));
+        measurements.enabled = enabled;
+    }
+
+    let clicked = mouse.just_pressed(MouseButton::Left)
+        && !ctx.wants_pointer_input()
+        && !ctx.is_pointer_over_area();
+    if measurements.enabled
+        && clicked
+        && let Some(cursor) = window.cursor_position()
+        && let Ok(ray) = camera.viewport_to_world(camera_transform, cursor)
+        && let Some(position) = pick_terrain(ray, ground)
+    {
+        match measurements.tool {
+            MeasureTool::Annotate => {
+                let text = match measurements.text.is_empty() {
+                    true => format!("{:.0} m", world_y_to_elevation(position.y)),
+                    false => std::mem::take(&mut measurements.text),
+                };
+                measurements.pin(text, position, origin);
+            }
+            MeasureTool::Distance if measurements.points.len() >= 2 => {
+                measurements.points = vec![position];
+            }
+            _ => measurements.points.push(position),
+        }
+        if measurements.tool == MeasureTool::Profile {
+            measurements.profile =
+                get_elevation_profile(&measurements.points, PROFILE_SAMPLES, origin, ground);
+        }
+    }
+
+    let painter = ctx.layer_painter(egui::LayerId::background());
+    for annotation in &measurements.annotations {
+        let Ok(position) =
+            camera.world_to_viewport(camera_transform, annotation.get_world_position(origin))
+        else {
+            continue;
+        };
+        let pin = Pos2::new(position.x, position.y);
+        painter.circle(pin, 4.0, Color32::YELLOW, Stroke::new(1.0, Color32::BLACK));
+        painter.text(
+            pin + egui::vec2(8.0, 0.0),
+            Align2::LEFT_CENTER,
+            &annotation.text,
+            FontId::proportional(14.0),
+            Color32::YELLOW,
+        );
+    }
+}
+
+/// Draws the picked points and the lines between them
+pub fn draw_measurements(measurements: Res<Measurements>, mut gizmos: Gizmos) {
+    if !measurements.enabled || measurements.points.is_empty() {
+        return;
+    }
+    let points: Vec<Vec3> = measurements
+        .points
+        .iter()
+        .map(|point| *point + Vec3::Y * LINE_OFFSET)
+        .collect();
+    for point in &points {
+        gizmos.sphere(Isometry3d::from_translation(*point), 2.0, MEASURE_COLOR);
+    }
+    match measurements.tool {
+        MeasureTool::Area => gizmos.linestrip(
+            points.iter().copied().chain(points.first().copied()),
+            MEASURE_COLOR,
+        ),
+        _ => gizmos.linestrip(points, MEASURE_COLOR),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGIN: Vec2 = Vec2::new(52.0, 4.0);
+
+    /// A point `north` and `east` meters from the origin, at a height
+    fn point(north: f32, east: f32, height: f32) -> Vec3 {
+        let (x, z) = lat_lon_to_world(
+            ORIGIN
+                + Vec2::new(
+                    north / METERS_PER_DEGREE as f32,
+                    east / (METERS_PER_DEGREE as f32 * ORIGIN.x.to_radians().cos()),
+                ),
+            ORIGIN,
+        );
+        Vec3::new(x as f32, height, z as f32)
+    }
+
+    #[test]
+    fn test_distances_and_area() {
+        let a = point(0.0, 0.0, 0.0);
+        let b = point(300.0, 0.0, 0.0);
+        let c = point(300.0, 400.0, 400.0);
+
+        // World positions are single precision, which is about half a meter in latitude
+        assert!((get_ground_distance(a, b, ORIGIN) - 300.0).abs() < 1.0);
+        assert!((get_ground_distance(a, c, ORIGIN) - 500.0).abs() < 1.0);
+        assert!((get_slant_distance(b, c, ORIGIN) - 400.0 * 2f64.sqrt()).abs() < 1.0);
+        assert!((get_polyline_length(&[a, b, c], ORIGIN) - 700.0).abs() < 2.0);
+        assert!((get_polygon_area(&[a, b, c], ORIGIN) - 60_000.0).abs() < 500.0);
+    }
+
+    #[test]
+    fn test_elevation_profile() {
+        let points = [point(0.0, 0.0, 0.0), point(0.0, 1000.0, 0.0)];
+        // The terrain rises from sea level to 100 meters from the start to the end
+        let profile = get_elevation_profile(&points, 11, ORIGIN, |position| {
+            let t = (position.x - points[0].x) / (points[1].x - points[0].x);
+            Some(elevation_to_world_y(t * 100.0))
+        });
+
+        assert_eq!(profile.len(), 11);
+        for (i, [distance, elevation]) in profile.into_iter().enumerate() {
+            assert!((distance - i as f64 * 100.0).abs() < 1.0);
+            assert!((elevation - distance / 10.0).abs() < 0.5);
+        }
+    }
+
+    #[test]
+    fn test_annotation_elevation() {
+        let position = point(100.0, 200.0, elevation_to_world_y(350.0));
+        let annotation = Annotation::new("note".to_string(), position, ORIGIN);
+        assert!((annotation.elevation - 350.0).abs() < 1e-3);
+        assert!((annotation.get_world_position(ORIGIN) - position).length() < 1.0);
+    }
+
+    #[test]
+    fn test_pick_terrain() {
+        let ray = Ray3d::new(
+            Vec3::new(0.0, 100.0, 0.0),
+            Dir3::new(Vec3::new(1.0, -1.0, 0.0)).unwrap(),
+        );
+        let hit = pick_terrain(ray, |_| Some(20.0)).expect("ray should hit the terrain");
+        assert!((hit - Vec3::new(80.0, 20.0, 0.0)).length() < 0.1);
+    }
+}
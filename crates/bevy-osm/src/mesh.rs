@@ -9,7 +9,10 @@ use lyon_tessellation::{
     StrokeOptions, StrokeTessellator, StrokeVertex, StrokeVertexConstructor,
 };
 
-use crate::osm_types::BuildingClass;
+use crate::{
+    airport::AerowayInstruction, boundary::BoundaryInstruction, labels::PlaceInstruction,
+    osm_types::BuildingClass,
+};
 
 type IndexType = u32;
 /// A vertex with all the necessary attributes to be inserted into a Bevy
@@ -81,6 +84,13 @@ pub struct FillInstruction {
     pub layer: Layer,
 }
 
+/// A water area, rendered with the animated water material
+pub struct WaterInstruction {
+    /// Whether the surface is level, as for lakes and the sea. Rivers follow the height of their
+    /// banks instead.
+    pub level: bool,
+}
+
 pub struct LightInstruction {
     pub trans: Vec3,
 }
@@ -90,6 +100,10 @@ pub enum BuildInstruction {
     Stroke(StrokeInstruction),
     Building(BuildingInstruction),
     Light(LightInstruction),
+    Water(WaterInstruction),
+    Aeroway(AerowayInstruction),
+    Boundary(BoundaryInstruction),
+    Place(PlaceInstruction),
     None,
 }
 
@@ -145,6 +159,71 @@ pub fn spawn_fill_mesh(points: Vec<Point>, instruction: FillInstruction) -> Mesh
     build_mesh(&buffers, instruction.layer.get_z())
 }
 
+/// Inserts points so no segment is longer than `spacing`
+pub fn densify(points: &[Vec2], spacing: f32) -> Vec<Vec2> {
+    let mut result = Vec::with_capacity(points.len());
+    for segment in points.windows(2) {
+        let (start, end) = (segment[0], segment[1]);
+        let steps = (start.distance(end) / spacing).ceil().max(1.0) as usize;
+        result.extend((0..steps).map(|i| start.lerp(end, i as f32 / steps as f32)));
+    }
+    result.extend(points.last());
+    result
+}
+
+/// Splits the longest edge of the triangles from `first_index` on until all edges are shorter
+/// than `spacing`, or until there are `max_triangles` triangles
+pub fn subdivide(
+    buffers: &mut VertexBuffers,
+    first_index: usize,
+    spacing: f32,
+    max_triangles: usize,
+) {
+    let mut pending: Vec<[u32; 3]> = buffers.indices[first_index..]
+        .chunks_exact(3)
+        .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+        .collect();
+    buffers.indices.truncate(first_index);
+
+    let position = |vertices: &[Vertex], i: u32| Vec2::from_array(vertices[i as usize].position);
+    while let Some(triangle) = pending.pop() {
+        let (edge, length) = (0..3)
+            .map(|i| {
+                let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+                (
+                    i,
+                    position(&buffers.vertices, a).distance(position(&buffers.vertices, b)),
+                )
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+
+        let triangles = (buffers.indices.len() - first_index) / 3 + pending.len();
+        if length <= spacing || triangles >= max_triangles {
+            buffers.indices.extend(triangle);
+            continue;
+        }
+
+        let (a, b, c) = (
+            triangle[edge],
+            triangle[(edge + 1) % 3],
+            triangle[(edge + 2) % 3],
+        );
+        let (start, end) = (buffers.vertices[a as usize], buffers.vertices[b as usize]);
+        let middle = buffers.vertices.len() as u32;
+        buffers.vertices.push(Vertex {
+            position: position(&buffers.vertices, a)
+                .midpoint(position(&buffers.vertices, b))
+                .to_array(),
+            color: Vec4::from_array(start.color)
+                .midpoint(Vec4::from_array(end.color))
+                .to_array(),
+        });
+        pending.push([a, middle, c]);
+        pending.push([middle, b, c]);
+    }
+}
+
 pub fn build_mesh(buffers: &VertexBuffers, z: f32) -> Mesh {
     let mut mesh = Mesh::new(
         PrimitiveTopology::TriangleList,
@@ -0,0 +1,328 @@
+//! An overview map in the corner of the screen.
+//!
+//! The minimap shows the raster tiles around the camera at a lower zoom level than the terrain,
+//! with the camera frustum on top and the outlines of the loaded quadtree nodes. Clicking on the
+//! map moves the camera there. Only the tiles that are shown are kept.
+
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use bevy_egui::{
+    EguiContexts, EguiTextureHandle,
+    egui::{self, Color32, Pos2, Rect as EguiRect, Sense, Shape, Stroke, StrokeKind},
+};
+use bevy_terrain::quadtree::QuadTreeNodeComponent;
+
+use crate::{
+    cache::{DownloadProgress, cache_raster_tile_for_chunk, get_osm_raster_cache_path_bevy},
+    chunk::{Chunk, get_chunk_for_coord, world_to_lat_lon},
+    config::{OSMConfig, RasterTileSource},
+    flight_path::CameraFlyTo,
+    scheduler::ChunkLoadState,
+};
+
+/// Size of the map on screen (pixels)
+const MINIMAP_SIZE: f32 = 256.0;
+const MIN_ZOOM: i8 = 2;
+const MAX_ZOOM: i8 = 16;
+/// Number of tiles around the tile of the camera that are shown in each direction
+const TILE_RADIUS: i32 = 1;
+/// Length of the frustum lines, relative to the half width of the map
+const FRUSTUM_LENGTH: f32 = 0.5;
+
+/// A raster tile of the minimap
+struct MinimapTile {
+    chunk: Chunk,
+    progress: DownloadProgress,
+    image: Option<Handle<Image>>,
+    texture: Option<egui::TextureId>,
+}
+
+#[derive(Resource)]
+pub struct Minimap {
+    pub visible: bool,
+    pub zoom: i8,
+    /// Where the tiles come from, a source without sessions by default
+    pub source: RasterTileSource,
+    tiles: HashMap<(i32, i32, i8), MinimapTile>,
+}
+
+impl Default for Minimap {
+    fn default() -> Self {
+        Self {
+            visible: true,
+            zoom: 11,
+            source: RasterTileSource::OSMDefault,
+            tiles: HashMap::new(),
+        }
+    }
+}
+
+/// Returns the tile that is shown at (x, y) of a zoom level, wrapping x around the antimeridian.
+/// There are no tiles beyond the poles.
+fn get_wrapped_tile(x: i32, y: i32, zoom: i8, template: &Chunk) -> Option<Chunk> {
+    let count = 1 << zoom;
+    (0..count).contains(&y).then(|| Chunk {
+        x: x.rem_euclid(count),
+        y,
+        z: zoom,
+        ..template.clone()
+    })
+}
+
+impl Minimap {
+    /// Drops the tiles that are no longer shown, together with their textures
+    fn retain_tiles(&mut self, shown: &HashSet<(i32, i32, i8)>, contexts: &mut EguiContexts) {
+        self.tiles.retain(|key, tile| {
+            let keep = shown.contains(key);
+            if !keep
+                && tile.texture.is_some()
+                && let Some(image) = &tile.image
+            {
+                contexts.remove_image(image.id());
+            }
+            keep
+        });
+    }
+
+    /// Returns the texture of a tile once it is cached and decoded, and starts downloading it
+    /// otherwise
+    fn get_texture(
+        &mut self,
+        chunk: Chunk,
+        contexts: &mut EguiContexts,
+        asset_server: &AssetServer,
+    ) -> Option<egui::TextureId> {
+        let source = &self.source;
+        let tile = self
+            .tiles
+            .entry((chunk.x, chunk.y, chunk.z))
+            .or_insert_with(|| {
+                let progress = DownloadProgress::default();
+                cache_raster_tile_for_chunk(&chunk, source, &progress);
+                MinimapTile {
+                    chunk,
+                    progress,
+                    image: None,
+                    texture: None,
+                }
+            });
+
+        if tile.image.is_none() && tile.progress.is_finished() && !tile.progress.has_failed() {
+            tile.image =
+                Some(asset_server.load(get_osm_raster_cache_path_bevy(&tile.chunk, source)));
+        }
+        if tile.texture.is_none()
+            && let Some(image) = &tile.image
+            && asset_server.is_loaded(image.id())
+        {
+            tile.texture = Some(contexts.add_image(EguiTextureHandle::Strong(image.clone())));
+        }
+        tile.texture
+    }
+}
+
+/// Maps world positions on the XZ-plane to the minimap and back, north is up
+struct MinimapProjection {
+    rect: EguiRect,
+    center: Vec2,
+    /// Half of the width of the map (meters)
+    extent: f32,
+}
+
+impl MinimapProjection {
+    fn to_screen(&self, world: Vec2) -> Pos2 {
+        let relative = (world - self.center) / self.extent * self.rect.width() / 2.0;
+        self.rect.center() + egui::vec2(relative.x, relative.y)
+    }
+
+    fn to_world(&self, screen: Pos2) -> Vec2 {
+        let relative = screen - self.rect.center();
+        self.center + Vec2::new(relative.x, relative.y) / (self.rect.width() / 2.0) * self.extent
+    }
+
+    fn rect_to_screen(&self, rect: Rect) -> EguiRect {
+        EguiRect::from_two_pos(self.to_screen(rect.min), self.to_screen(rect.max))
+    }
+}
+
+/// The color of the outline of a quadtree node
+fn get_node_color(state: Option<&ChunkLoadState>) -> Color32 {
+    match state {
+        Some(ChunkLoadState::Loaded) => Color32::from_rgb(60, 200, 60),
+        Some(ChunkLoadState::Failed) => Color32::from_rgb(220, 50, 50),
+        _ => Color32::from_rgb(230, 180, 40),
+    }
+}
+
+/// Shows the minimap and moves the camera to where it is clicked. Toggled with N.
+#[expect(clippy::too_many_arguments)]
+pub fn minimap_ui(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut minimap: ResMut<Minimap>,
+    config: Res<OSMConfig>,
+    keys: Res<ButtonInput<KeyCode>>,
+    asset_server: Res<AssetServer>,
+    camera: Single<(Entity, &GlobalTransform, &Projection), With<Camera>>,
+    nodes: Query<(&QuadTreeNodeComponent, Option<&ChunkLoadState>)>,
+) {
+    if keys.just_pressed(KeyCode::KeyN) {
+        minimap.visible = !minimap.visible;
+    }
+    if !config.ui_visible || !minimap.visible {
+        return;
+    }
+    let (camera_entity, camera_transform, projection) = *camera;
+    let origin = config.location.get_world_center();
+    let position = camera_transform.translation();
+    let (lat, lon) = world_to_lat_lon(position, origin);
+    let center_tile = get_chunk_for_coord(lat as f64, lon as f64, minimap.zoom);
+    let extent = center_tile.get_size_in_meters().x * (TILE_RADIUS as f32 + 0.5);
+
+    // Collect the textures first, they need the contexts mutably
+    let mut tiles = Vec::new();
+    let mut shown = HashSet::new();
+    for dx in -TILE_RADIUS..=TILE_RADIUS {
+        for dy in -TILE_RADIUS..=TILE_RADIUS {
+            let (x, y) = (center_tile.x + dx, center_tile.y + dy);
+            let Some(chunk) = get_wrapped_tile(x, y, minimap.zoom, &center_tile) else {
+                continue;
+            };
+            // Placed where it is next to the camera, not where the wrapped tile is
+            let area = Chunk { x, ..chunk.clone() }.get_area_in_meters(origin);
+            shown.insert((chunk.x, chunk.y, chunk.z));
+            if let Some(texture) = minimap.get_texture(chunk, &mut contexts, &asset_server) {
+                tiles.push((texture, area));
+            }
+        }
+    }
+    minimap.retain_tiles(&shown, &mut contexts);
+    let Ok(ctx) = contexts.ctx_mut() else {
+        return;
+    };
+
+    let mut zoom = minimap.zoom;
+    egui::Window::new("Minimap")
+        .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-10.0, -10.0))
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("-").clicked() {
+                    zoom = (zoom - 1).max(MIN_ZOOM);
+                }
+                ui.label(format!("zoom {zoom}"));
+                if ui.button("+").clicked() {
+                    zoom = (zoom + 1).min(MAX_ZOOM);
+                }
+            });
+
+            let (response, painter) =
+                ui.allocate_painter(egui::Vec2::splat(MINIMAP_SIZE), Sense::click());
+            let projection_2d = MinimapProjection {
+                rect: response.rect,
+                center: position.xz(),
+                extent,
+            };
+            painter.rect_filled(response.rect, 0.0, Color32::from_gray(40));
+            let uv = EguiRect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0));
+            for (texture, area) in &tiles {
+                painter.image(
+                    *texture,
+                    projection_2d.rect_to_screen(*area),
+                    uv,
+                    Color32::WHITE,
+                );
+            }
+
+            for (node, state) in &nodes {
+                painter.rect_stroke(
+                    projection_2d.rect_to_screen(node.rect),
+                    0.0,
+                    Stroke::new(1.0, get_node_color(state)),
+                    StrokeKind::Inside,
+                );
+            }
+
+            // The camera frustum and heading
+            let forward = camera_transform.forward().xz().normalize_or(Vec2::NEG_Y);
+            let half_fov = match projection {
+                Projection::Perspective(perspective) => {
+                    ((perspective.fov / 2.0).tan() * perspective.aspect_ratio).atan()
+                }
+                _ => std::f32::consts::FRAC_PI_4,
+            };
+            let length = extent * FRUSTUM_LENGTH;
+            let camera_point = projection_2d.to_screen(position.xz());
+            let left = projection_2d
+                .to_screen(position.xz() + Vec2::from_angle(-half_fov).rotate(forward) * length);
+            let right = projection_2d
+                .to_screen(position.xz() + Vec2::from_angle(half_fov).rotate(forward) * length);
+            painter.add(Shape::convex_polygon(
+                vec![camera_point, left, right],
+                Color32::from_rgba_unmultiplied(255, 255, 255, 40),
+                Stroke::new(1.0, Color32::WHITE),
+            ));
+            painter.line_segment(
+                [
+                    camera_point,
+                    projection_2d.to_screen(position.xz() + forward * length * 1.2),
+                ],
+                Stroke::new(2.0, Color32::RED),
+            );
+            painter.circle_filled(camera_point, 3.0, Color32::RED);
+
+            if response.clicked()
+                && let Some(pointer) = response.interact_pointer_pos()
+            {
+                let target = projection_2d.to_world(pointer);
+                let (lat, lon) = world_to_lat_lon(Vec3::new(target.x, 0.0, target.y), origin);
+                let mut flight = CameraFlyTo::new(Vec2::new(lat, lon));
+                flight.duration = 0.0;
+                commands.entity(camera_entity).insert(flight);
+            }
+        });
+    minimap.zoom = zoom;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tiles_wrap_around() {
+        let template = Chunk {
+            x: 0,
+            y: 0,
+            z: 2,
+            elevation: Handle::default(),
+            raster: Handle::default(),
+        };
+        let east = get_wrapped_tile(4, 1, 2, &template).unwrap();
+        assert_eq!((east.x, east.y), (0, 1));
+        let west = get_wrapped_tile(-1, 1, 2, &template).unwrap();
+        assert_eq!((west.x, west.y), (3, 1));
+        assert!(get_wrapped_tile(0, -1, 2, &template).is_none());
+        assert!(get_wrapped_tile(0, 4, 2, &template).is_none());
+    }
+
+    #[test]
+    fn test_projection_round_trip() {
+        let projection = MinimapProjection {
+            rect: EguiRect::from_min_size(Pos2::new(100.0, 50.0), egui::Vec2::splat(200.0)),
+            center: Vec2::new(1000.0, -500.0),
+            extent: 2000.0,
+        };
+        // North is up
+        let north = projection.to_screen(Vec2::new(1000.0, -1500.0));
+        assert_eq!(north, Pos2::new(200.0, 100.0));
+
+        let world = Vec2::new(1500.0, 250.0);
+        assert!(
+            projection
+                .to_world(projection.to_screen(world))
+                .distance(world)
+                < 1e-2
+        );
+    }
+}
@@ -0,0 +1,814 @@
+//! Vector overlays from GeoJSON, KML and GPX files, draped over the terrain.
+//!
+//! Load a [`GeoOverlay`] with the asset server and spawn a [`GeoOverlayLayer`] with it:
+//!
+//! ```ignore
+//! commands.spawn(GeoOverlayLayer::new(asset_server.load("overlays/route.gpx")));
+//! ```
+//!
+//! The features are projected with [`lat_lon_to_world`] and follow the loaded terrain. The
+//! overlay is rebuilt when the file changes on disk, when its style changes and when the terrain
+//! below it is refined.
+
+use std::{error::Error, fmt, io, time::Duration};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, RenderAssetUsages, io::Reader},
+    mesh::{Indices, PrimitiveTopology},
+    prelude::*,
+    tasks::ConditionalSendFuture,
+};
+use bevy_terrain::{
+    camera::get_ground_height, mesh::ChunkHeightMap, quadtree::QuadTreeNodeComponent,
+};
+use lyon::{math::point, path::Path};
+use lyon_tessellation::{
+    BuffersBuilder, FillOptions, FillTessellator, LineCap, LineJoin, StrokeOptions,
+    StrokeTessellator,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    chunk::lat_lon_to_world,
+    config::OSMConfig,
+    mesh::{VertexBuffers, VertexConstructor, densify, subdivide},
+};
+
+/// Maximum distance between the vertices of an overlay, so it follows the terrain (meters)
+const DRAPE_SPACING: f32 = 25.0;
+/// Upper bound on the triangles a filled polygon is subdivided into
+const MAX_FILL_TRIANGLES: usize = 200_000;
+/// Number of segments of the circle that marks a point
+const POINT_SEGMENTS: usize = 16;
+/// Minimum time between rebuilds caused by the terrain loading
+const TERRAIN_REBUILD_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The geometry of a feature, with coordinates as (lat, lon) in degrees
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoGeometry {
+    Point(Vec2),
+    LineString(Vec<Vec2>),
+    /// The outer ring followed by the holes
+    Polygon(Vec<Vec<Vec2>>),
+}
+
+/// Styling of a single feature that overrides the [`OverlayStyle`] of the layer
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeatureStyle {
+    pub stroke: Option<Color>,
+    /// (meters)
+    pub stroke_width: Option<f32>,
+    pub fill: Option<Color>,
+    pub fill_opacity: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoFeature {
+    pub geometry: GeoGeometry,
+    pub name: Option<String>,
+    pub style: FeatureStyle,
+}
+
+/// The features of a GeoJSON, KML or GPX file
+#[derive(Asset, TypePath, Debug, Clone, Default, PartialEq)]
+pub struct GeoOverlay {
+    pub features: Vec<GeoFeature>,
+}
+
+#[derive(Debug)]
+pub enum GeoOverlayError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Xml(roxmltree::Error),
+    Invalid(String),
+    UnsupportedFormat(String),
+}
+
+impl fmt::Display for GeoOverlayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "could not read overlay: {err}"),
+            Self::Json(err) => write!(f, "invalid GeoJSON: {err}"),
+            Self::Xml(err) => write!(f, "invalid XML: {err}"),
+            Self::Invalid(reason) => write!(f, "invalid overlay: {reason}"),
+            Self::UnsupportedFormat(extension) => {
+                write!(f, "unsupported overlay format: {extension}")
+            }
+        }
+    }
+}
+
+impl Error for GeoOverlayError {}
+
+/// Reads a `[lon, lat, ...]` position
+fn parse_geojson_position(value: &Value) -> Result<Vec2, GeoOverlayError> {
+    let position = value
+        .as_array()
+        .filter(|position| position.len() >= 2)
+        .ok_or_else(|| GeoOverlayError::Invalid(format!("invalid position {value}")))?;
+    let get = |i: usize| {
+        position[i]
+            .as_f64()
+            .ok_or_else(|| GeoOverlayError::Invalid(format!("invalid position {value}")))
+    };
+    Ok(Vec2::new(get(1)? as f32, get(0)? as f32))
+}
+
+fn parse_geojson_positions(value: &Value) -> Result<Vec<Vec2>, GeoOverlayError> {
+    value
+        .as_array()
+        .ok_or_else(|| GeoOverlayError::Invalid(format!("invalid positions {value}")))?
+        .iter()
+        .map(parse_geojson_position)
+        .collect()
+}
+
+fn parse_geojson_rings(value: &Value) -> Result<Vec<Vec<Vec2>>, GeoOverlayError> {
+    value
+        .as_array()
+        .ok_or_else(|| GeoOverlayError::Invalid(format!("invalid polygon {value}")))?
+        .iter()
+        .map(parse_geojson_positions)
+        .collect()
+}
+
+fn parse_geojson_geometry(
+    geometry: &Value,
+    geometries: &mut Vec<GeoGeometry>,
+) -> Result<(), GeoOverlayError> {
+    let coordinates = &geometry["coordinates"];
+    let parts = || {
+        coordinates
+            .as_array()
+            .ok_or_else(|| GeoOverlayError::Invalid(format!("invalid geometry {geometry}")))
+    };
+    match geometry["type"].as_str() {
+        Some("Point") => geometries.push(GeoGeometry::Point(parse_geojson_position(coordinates)?)),
+        Some("MultiPoint") => {
+            for position in parts()? {
+                geometries.push(GeoGeometry::Point(parse_geojson_position(position)?));
+            }
+        }
+        Some("LineString") => geometries.push(GeoGeometry::LineString(parse_geojson_positions(
+            coordinates,
+        )?)),
+        Some("MultiLineString") => {
+            for line in parts()? {
+                geometries.push(GeoGeometry::LineString(parse_geojson_positions(line)?));
+            }
+        }
+        Some("Polygon") => geometries.push(GeoGeometry::Polygon(parse_geojson_rings(coordinates)?)),
+        Some("MultiPolygon") => {
+            for polygon in parts()? {
+                geometries.push(GeoGeometry::Polygon(parse_geojson_rings(polygon)?));
+            }
+        }
+        Some("GeometryCollection") => {
+            for geometry in geometry["geometries"].as_array().into_iter().flatten() {
+                parse_geojson_geometry(geometry, geometries)?;
+            }
+        }
+        Some(other) => {
+            return Err(GeoOverlayError::Invalid(format!(
+                "unknown geometry type {other}"
+            )));
+        }
+        // A feature without geometry
+        None => {}
+    }
+    Ok(())
+}
+
+/// Reads a color as `#rgb` or `#rrggbb`
+fn parse_hex_color(value: &Value) -> Option<Color> {
+    Srgba::hex(value.as_str()?).ok().map(Color::from)
+}
+
+/// Reads the [simplestyle](https://github.com/mapbox/simplestyle-spec) properties of a feature
+fn parse_geojson_style(properties: &Value) -> FeatureStyle {
+    FeatureStyle {
+        stroke: parse_hex_color(&properties["stroke"])
+            .or_else(|| parse_hex_color(&properties["marker-color"])),
+        stroke_width: properties["stroke-width"]
+            .as_f64()
+            .map(|width| width as f32),
+        fill: parse_hex_color(&properties["fill"]),
+        fill_opacity: properties["fill-opacity"]
+            .as_f64()
+            .map(|opacity| opacity as f32),
+    }
+}
+
+/// Parses a GeoJSON `FeatureCollection`, `Feature` or bare geometry
+pub fn parse_geojson(bytes: &[u8]) -> Result<GeoOverlay, GeoOverlayError> {
+    let root: Value = serde_json::from_slice(bytes).map_err(GeoOverlayError::Json)?;
+    let features = match root["type"].as_str() {
+        Some("FeatureCollection") => root["features"].as_array().cloned().unwrap_or_default(),
+        Some("Feature") => vec![root],
+        _ => vec![serde_json::json!({ "type": "Feature", "geometry": root })],
+    };
+
+    let mut overlay = GeoOverlay::default();
+    for feature in &features {
+        let properties = &feature["properties"];
+        let name = properties["name"].as_str().map(str::to_string);
+        let style = parse_geojson_style(properties);
+
+        let mut geometries = Vec::new();
+        parse_geojson_geometry(&feature["geometry"], &mut geometries)?;
+        overlay
+            .features
+            .extend(geometries.into_iter().map(|geometry| GeoFeature {
+                geometry,
+                name: name.clone(),
+                style: style.clone(),
+            }));
+    }
+    Ok(overlay)
+}
+
+fn get_child<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn get_child_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    get_child(node, name).and_then(|child| child.text())
+}
+
+/// Reads KML coordinates, which are whitespace separated `lon,lat[,alt]` tuples
+fn parse_kml_coordinates(node: roxmltree::Node) -> Result<Vec<Vec2>, GeoOverlayError> {
+    get_child_text(node, "coordinates")
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(|tuple| {
+            let mut values = tuple.split(',').map(str::parse::<f32>);
+            match (values.next(), values.next()) {
+                (Some(Ok(lon)), Some(Ok(lat))) => Ok(Vec2::new(lat, lon)),
+                _ => Err(GeoOverlayError::Invalid(format!(
+                    "invalid KML coordinate {tuple}"
+                ))),
+            }
+        })
+        .collect()
+}
+
+/// Reads a KML color, which is written as `aabbggrr`
+fn parse_kml_color(text: &str) -> Option<Color> {
+    let value = u32::from_str_radix(text.trim(), 16).ok()?;
+    let [a, b, g, r] = value.to_be_bytes();
+    Some(Color::srgba_u8(r, g, b, a))
+}
+
+/// Reads the inline `Style` of a placemark. Shared styles through `styleUrl` are not resolved.
+fn parse_kml_style(placemark: roxmltree::Node) -> FeatureStyle {
+    let Some(style) = get_child(placemark, "Style") else {
+        return FeatureStyle::default();
+    };
+    let line = get_child(style, "LineStyle");
+    let poly = get_child(style, "PolyStyle");
+    let fill = poly
+        .and_then(|poly| get_child_text(poly, "color"))
+        .and_then(parse_kml_color);
+    FeatureStyle {
+        stroke: line
+            .and_then(|line| get_child_text(line, "color"))
+            .and_then(parse_kml_color),
+        stroke_width: line
+            .and_then(|line| get_child_text(line, "width"))
+            .and_then(|width| width.trim().parse().ok()),
+        fill,
+        // The alpha of the KML color is the opacity
+        fill_opacity: fill.map(|_| 1.0),
+    }
+}
+
+fn parse_kml_geometry(
+    node: roxmltree::Node,
+    geometries: &mut Vec<GeoGeometry>,
+) -> Result<(), GeoOverlayError> {
+    match node.tag_name().name() {
+        "Point" => {
+            if let Some(position) = parse_kml_coordinates(node)?.first() {
+                geometries.push(GeoGeometry::Point(*position));
+            }
+        }
+        "LineString" | "LinearRing" => {
+            geometries.push(GeoGeometry::LineString(parse_kml_coordinates(node)?));
+        }
+        "Polygon" => {
+            let mut rings = Vec::new();
+            for boundary in ["outerBoundaryIs", "innerBoundaryIs"] {
+                for boundary in node.children().filter(|child| child.has_tag_name(boundary)) {
+                    if let Some(ring) = get_child(boundary, "LinearRing") {
+                        rings.push(parse_kml_coordinates(ring)?);
+                    }
+                }
+            }
+            geometries.push(GeoGeometry::Polygon(rings));
+        }
+        "MultiGeometry" => {
+            for child in node.children().filter(|child| child.is_element()) {
+                parse_kml_geometry(child, geometries)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Parses the placemarks of a KML document
+pub fn parse_kml(text: &str) -> Result<GeoOverlay, GeoOverlayError> {
+    let document = roxmltree::Document::parse(text).map_err(GeoOverlayError::Xml)?;
+
+    let mut overlay = GeoOverlay::default();
+    for placemark in document
+        .descendants()
+        .filter(|node| node.has_tag_name("Placemark"))
+    {
+        let name = get_child_text(placemark, "name").map(str::to_string);
+        let style = parse_kml_style(placemark);
+
+        let mut geometries = Vec::new();
+        for child in placemark.children().filter(|child| child.is_element()) {
+            parse_kml_geometry(child, &mut geometries)?;
+        }
+        overlay
+            .features
+            .extend(geometries.into_iter().map(|geometry| GeoFeature {
+                geometry,
+                name: name.clone(),
+                style: style.clone(),
+            }));
+    }
+    Ok(overlay)
+}
+
+fn parse_gpx_point(node: roxmltree::Node) -> Result<Vec2, GeoOverlayError> {
+    let get = |name: &str| {
+        node.attribute(name)
+            .and_then(|value| value.parse::<f32>().ok())
+            .ok_or_else(|| GeoOverlayError::Invalid(format!("GPX point without {name}")))
+    };
+    Ok(Vec2::new(get("lat")?, get("lon")?))
+}
+
+fn parse_gpx_points(node: roxmltree::Node, name: &str) -> Result<Vec<Vec2>, GeoOverlayError> {
+    node.children()
+        .filter(|child| child.has_tag_name(name))
+        .map(parse_gpx_point)
+        .collect()
+}
+
+/// Parses the waypoints, routes and tracks of a GPX file
+pub fn parse_gpx(text: &str) -> Result<GeoOverlay, GeoOverlayError> {
+    let document = roxmltree::Document::parse(text).map_err(GeoOverlayError::Xml)?;
+    let feature = |geometry, node: roxmltree::Node| GeoFeature {
+        geometry,
+        name: get_child_text(node, "name").map(str::to_string),
+        style: FeatureStyle::default(),
+    };
+
+    let mut overlay = GeoOverlay::default();
+    for node in document.root_element().children() {
+        match node.tag_name().name() {
+            "wpt" => overlay
+                .features
+                .push(feature(GeoGeometry::Point(parse_gpx_point(node)?), node)),
+            "rte" => overlay.features.push(feature(
+                GeoGeometry::LineString(parse_gpx_points(node, "rtept")?),
+                node,
+            )),
+            "trk" => {
+                for segment in node.children().filter(|child| child.has_tag_name("trkseg")) {
+                    overlay.features.push(feature(
+                        GeoGeometry::LineString(parse_gpx_points(segment, "trkpt")?),
+                        node,
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(overlay)
+}
+
+#[derive(Default, TypePath)]
+pub struct GeoOverlayLoader;
+
+impl AssetLoader for GeoOverlayLoader {
+    type Asset = GeoOverlay;
+    type Settings = ();
+    type Error = GeoOverlayError;
+
+    fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> impl ConditionalSendFuture<Output = Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader
+                .read_to_end(&mut bytes)
+                .await
+                .map_err(GeoOverlayError::Io)?;
+
+            let extension = load_context
+                .path()
+                .path()
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .unwrap_or_default()
+                .to_lowercase();
+            let text = || {
+                std::str::from_utf8(&bytes).map_err(|err| GeoOverlayError::Invalid(err.to_string()))
+            };
+            match extension.as_str() {
+                "geojson" | "json" => parse_geojson(&bytes),
+                "kml" => parse_kml(text()?),
+                "gpx" => parse_gpx(text()?),
+                _ => Err(GeoOverlayError::UnsupportedFormat(extension)),
+            }
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["geojson", "kml", "gpx"]
+    }
+}
+
+/// The default look of the features of a layer
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct OverlayStyle {
+    pub color: Color,
+    /// (meters)
+    pub line_width: f32,
+    /// (meters)
+    pub point_radius: f32,
+    /// Opacity of polygon fills, relative to the color
+    pub fill_opacity: f32,
+    /// Height above the terrain, to keep the overlay from disappearing into it (meters)
+    pub height_offset: f32,
+}
+
+impl Default for OverlayStyle {
+    fn default() -> Self {
+        Self {
+            color: Color::srgb(1.0, 0.35, 0.1),
+            line_width: 6.0,
+            point_radius: 15.0,
+            fill_opacity: 0.3,
+            height_offset: 2.0,
+        }
+    }
+}
+
+/// A [`GeoOverlay`] that is drawn on the terrain
+#[derive(Component, Debug, Clone)]
+#[require(Transform, Visibility)]
+pub struct GeoOverlayLayer {
+    pub overlay: Handle<GeoOverlay>,
+    pub style: OverlayStyle,
+}
+
+impl GeoOverlayLayer {
+    pub fn new(overlay: Handle<GeoOverlay>) -> Self {
+        Self {
+            overlay,
+            style: OverlayStyle::default(),
+        }
+    }
+
+    pub fn with_style(mut self, style: OverlayStyle) -> Self {
+        self.style = style;
+        self
+    }
+}
+
+/// The mesh of a [`GeoOverlayLayer`], spawned as its child
+#[derive(Component)]
+pub struct GeoOverlayMesh;
+
+fn build_path(rings: &[Vec<Vec2>], close: bool) -> Path {
+    let mut builder = Path::builder();
+    for ring in rings.iter().filter(|ring| ring.len() >= 2) {
+        builder.begin(point(ring[0].x, ring[0].y));
+        for p in &ring[1..] {
+            builder.line_to(point(p.x, p.y));
+        }
+        builder.end(close);
+    }
+    builder.build()
+}
+
+fn stroke(buffers: &mut VertexBuffers, rings: &[Vec<Vec2>], close: bool, width: f32, color: Color) {
+    let rings: Vec<Vec<Vec2>> = rings
+        .iter()
+        .map(|ring| {
+            let mut ring = ring.clone();
+            if close && ring.len() > 2 && ring.first() != ring.last() {
+                ring.push(ring[0]);
+            }
+            densify(&ring, DRAPE_SPACING)
+        })
+        .collect();
+
+    if let Err(e) = StrokeTessellator::new().tessellate_path(
+        &build_path(&rings, false),
+        &StrokeOptions::default()
+            .with_line_width(width)
+            .with_line_join(LineJoin::Round)
+            .with_line_cap(LineCap::Round),
+        &mut BuffersBuilder::new(buffers, VertexConstructor { color }),
+    ) {
+        error!("StrokeTessellator error: {:?}", e);
+    }
+}
+
+fn fill(buffers: &mut VertexBuffers, rings: &[Vec<Vec2>], color: Color) {
+    let first_index = buffers.indices.len();
+    if let Err(e) = FillTessellator::new().tessellate_path(
+        &build_path(rings, true),
+        &FillOptions::default(),
+        &mut BuffersBuilder::new(buffers, VertexConstructor { color }),
+    ) {
+        error!("FillTessellator error: {:?}", e);
+    }
+    subdivide(buffers, first_index, DRAPE_SPACING, MAX_FILL_TRIANGLES);
+}
+
+/// Builds the mesh of an overlay in world space.
+///
+/// `origin` is the coordinate in degrees that corresponds to the world origin and `ground`
+/// returns the height of the terrain at a world position on the XZ plane, if it is loaded.
+pub fn build_overlay_mesh(
+    overlay: &GeoOverlay,
+    style: &OverlayStyle,
+    origin: Vec2,
+    ground: impl Fn(Vec2) -> Option<f32>,
+) -> Option<Mesh> {
+    let project = |lat_lon: &Vec2| {
+        let (x, z) = lat_lon_to_world(*lat_lon, origin);
+        Vec2::new(x as f32, z as f32)
+    };
+    let project_all = |points: &[Vec2]| points.iter().map(project).collect::<Vec<_>>();
+
+    let mut buffers = VertexBuffers::new();
+    for feature in &overlay.features {
+        let color = feature.style.stroke.unwrap_or(style.color);
+        let width = feature.style.stroke_width.unwrap_or(style.line_width);
+
+        match &feature.geometry {
+            GeoGeometry::Point(position) => {
+                let center = project(position);
+                let circle: Vec<Vec2> = (0..POINT_SEGMENTS)
+                    .map(|i| {
+                        let angle = i as f32 / POINT_SEGMENTS as f32 * std::f32::consts::TAU;
+                        center + Vec2::from_angle(angle) * style.point_radius
+                    })
+                    .collect();
+                fill(&mut buffers, &[circle], color);
+            }
+            GeoGeometry::LineString(points) => {
+                stroke(&mut buffers, &[project_all(points)], false, width, color);
+            }
+            GeoGeometry::Polygon(rings) => {
+                let rings: Vec<Vec<Vec2>> = rings.iter().map(|ring| project_all(ring)).collect();
+                let opacity = feature.style.fill_opacity.unwrap_or(style.fill_opacity);
+                let fill_color = feature.style.fill.unwrap_or(color);
+                fill(
+                    &mut buffers,
+                    &rings,
+                    fill_color.with_alpha(fill_color.alpha() * opacity),
+                );
+                stroke(&mut buffers, &rings, true, width, color);
+            }
+        }
+    }
+
+    if buffers.indices.is_empty() {
+        return None;
+    }
+
+    let mut mesh = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    );
+    mesh.insert_indices(Indices::U32(buffers.indices));
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_POSITION,
+        buffers
+            .vertices
+            .iter()
+            .map(|v| {
+                let position = Vec2::from_array(v.position);
+                let height = ground(position).unwrap_or(0.0) + style.height_offset;
+                [position.x, height, position.y]
+            })
+            .collect::<Vec<[f32; 3]>>(),
+    );
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_NORMAL,
+        vec![[0.0, 1.0, 0.0]; buffers.vertices.len()],
+    );
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_COLOR,
+        buffers
+            .vertices
+            .iter()
+            .map(|v| v.color)
+            .collect::<Vec<[f32; 4]>>(),
+    );
+    Some(mesh)
+}
+
+/// Builds the meshes of overlay layers when they are added, when their file or style changes and
+/// when the terrain below them changes.
+#[expect(clippy::too_many_arguments)]
+pub fn update_geo_overlays(
+    mut commands: Commands,
+    layers: Query<(Entity, Ref<GeoOverlayLayer>, Option<&Children>)>,
+    overlay_meshes: Query<(), With<GeoOverlayMesh>>,
+    mut overlay_events: MessageReader<AssetEvent<GeoOverlay>>,
+    overlays: Res<Assets<GeoOverlay>>,
+    chunks: Query<(&ChunkHeightMap, &GlobalTransform, &QuadTreeNodeComponent)>,
+    new_terrain: Query<(), Added<ChunkHeightMap>>,
+    config: Res<OSMConfig>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    time: Res<Time>,
+    mut terrain_rebuild: Local<Option<Timer>>,
+) {
+    let changed_overlays: Vec<AssetId<GeoOverlay>> = overlay_events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect();
+
+    // Re-drape at most every so often while the terrain is streaming in.
+    let timer = terrain_rebuild
+        .get_or_insert_with(|| Timer::new(TERRAIN_REBUILD_INTERVAL, TimerMode::Once));
+    timer.tick(time.delta());
+    if !new_terrain.is_empty() && timer.is_finished() {
+        timer.reset();
+    }
+    let terrain_changed = timer.just_finished();
+
+    for (entity, layer, children) in &layers {
+        if !(layer.is_changed()
+            || terrain_changed
+            || changed_overlays.contains(&layer.overlay.id()))
+        {
+            continue;
+        }
+        let Some(overlay) = overlays.get(&layer.overlay) else {
+            continue;
+        };
+
+        for child in children.into_iter().flatten() {
+            if overlay_meshes.contains(*child) {
+                commands.entity(*child).despawn();
+            }
+        }
+
+        let Some(mesh) = build_overlay_mesh(
+            overlay,
+            &layer.style,
+            config.location.get_world_center(),
+            |position| get_ground_height(&chunks, position),
+        ) else {
+            continue;
+        };
+        commands.entity(entity).with_child((
+            Mesh3d(meshes.add(mesh)),
+            MeshMaterial3d(materials.add(StandardMaterial {
+                base_color: Color::WHITE,
+                unlit: true,
+                alpha_mode: AlphaMode::Blend,
+                double_sided: true,
+                cull_mode: None,
+                ..default()
+            })),
+            GeoOverlayMesh,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_geojson() {
+        let overlay = parse_geojson(
+            br##"{
+                "type": "FeatureCollection",
+                "features": [
+                    {
+                        "type": "Feature",
+                        "properties": { "name": "Dam", "stroke": "#ff0000", "stroke-width": 3 },
+                        "geometry": { "type": "LineString", "coordinates": [[4.89, 52.37], [4.9, 52.38]] }
+                    },
+                    {
+                        "type": "Feature",
+                        "properties": null,
+                        "geometry": { "type": "MultiPoint", "coordinates": [[4.0, 52.0], [5.0, 53.0]] }
+                    }
+                ]
+            }"##,
+        )
+        .unwrap();
+
+        assert_eq!(overlay.features.len(), 3);
+        assert_eq!(overlay.features[0].name.as_deref(), Some("Dam"));
+        assert_eq!(
+            overlay.features[0].geometry,
+            GeoGeometry::LineString(vec![Vec2::new(52.37, 4.89), Vec2::new(52.38, 4.9)])
+        );
+        assert_eq!(overlay.features[0].style.stroke_width, Some(3.0));
+        assert_eq!(
+            overlay.features[2].geometry,
+            GeoGeometry::Point(Vec2::new(53.0, 5.0))
+        );
+    }
+
+    #[test]
+    fn test_parse_kml() {
+        let overlay = parse_kml(
+            r#"<kml xmlns="http://www.opengis.net/kml/2.2"><Document>
+                <Placemark>
+                    <name>Park</name>
+                    <Style><PolyStyle><color>800000ff</color></PolyStyle></Style>
+                    <Polygon><outerBoundaryIs><LinearRing>
+                        <coordinates>4.0,52.0,0 4.1,52.0,0 4.1,52.1,0 4.0,52.0,0</coordinates>
+                    </LinearRing></outerBoundaryIs></Polygon>
+                </Placemark>
+            </Document></kml>"#,
+        )
+        .unwrap();
+
+        assert_eq!(overlay.features.len(), 1);
+        let GeoGeometry::Polygon(rings) = &overlay.features[0].geometry else {
+            panic!("expected a polygon");
+        };
+        assert_eq!(rings[0][1], Vec2::new(52.0, 4.1));
+        assert_eq!(
+            overlay.features[0].style.fill,
+            Some(Color::srgba_u8(255, 0, 0, 128))
+        );
+    }
+
+    #[test]
+    fn test_parse_gpx() {
+        let overlay = parse_gpx(
+            r#"<gpx version="1.1" xmlns="http://www.topografix.com/GPX/1/1">
+                <wpt lat="52.0" lon="4.0"><name>Start</name></wpt>
+                <trk><trkseg>
+                    <trkpt lat="52.0" lon="4.0"/><trkpt lat="52.1" lon="4.1"/>
+                </trkseg></trk>
+            </gpx>"#,
+        )
+        .unwrap();
+
+        assert_eq!(overlay.features.len(), 2);
+        assert_eq!(overlay.features[0].name.as_deref(), Some("Start"));
+        assert_eq!(
+            overlay.features[1].geometry,
+            GeoGeometry::LineString(vec![Vec2::new(52.0, 4.0), Vec2::new(52.1, 4.1)])
+        );
+    }
+
+    #[test]
+    fn test_overlay_is_draped() {
+        let origin = Vec2::new(52.0, 4.0);
+        let overlay = GeoOverlay {
+            features: vec![GeoFeature {
+                geometry: GeoGeometry::Polygon(vec![vec![
+                    Vec2::new(52.0, 4.0),
+                    Vec2::new(52.0, 4.01),
+                    Vec2::new(52.01, 4.01),
+                ]]),
+                name: None,
+                style: FeatureStyle::default(),
+            }],
+        };
+        let style = OverlayStyle::default();
+        let mesh = build_overlay_mesh(&overlay, &style, origin, |position| Some(position.x * 0.1))
+            .unwrap();
+
+        let positions = mesh.attribute(Mesh::ATTRIBUTE_POSITION).unwrap();
+        let positions = positions.as_float3().unwrap();
+        // The polygon is about 680 by 1100 meters, so it has to be subdivided to follow the terrain.
+        assert!(positions.len() > 100);
+        for position in positions {
+            assert!((position[1] - (position[0] * 0.1 + style.height_offset)).abs() < 1e-3);
+        }
+    }
+}
@@ -1,26 +1,306 @@
-use std::collections::VecDeque;
+use std::{
+    collections::{HashSet, VecDeque},
+    io,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
 use bevy::{
+    asset::Assets,
     diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
     ecs::{
-        entity::Entity,
-        query::Without,
+        hierarchy::Children,
+        query::With,
         resource::Resource,
         system::{Query, Res, ResMut},
     },
+    image::Image,
+    mesh::{Indices, Mesh, Mesh3d},
+    time::Time,
 };
 use bevy_egui::egui::Color32;
 use bevy_terrain::quadtree::ChunkLoaded;
+use serde::Serialize;
 
-use crate::chunk::Chunk;
+use crate::{chunk::Chunk, scheduler::ChunkLoadState, storage::data_storage};
 
 const MAX_PERFORMANCE_HISTORY: usize = 128;
+/// Time between two estimates of the GPU memory, iterating all mesh and image assets is too slow
+/// to do every frame (seconds)
+const GPU_MEMORY_INTERVAL: f64 = 1.0;
+
+/// Directory in the [`data_storage`] that recorded sessions are written to
+pub const SESSION_DIRECTORY: &str = "sessions";
+
+/// Counters of the chunk loading pipeline.
+///
+/// These are updated from download callbacks and async tasks, so they are atomics instead of
+/// fields of [`OSMPerformance`].
+#[derive(Debug)]
+pub struct LoadingCounters {
+    download_bytes: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    tiles_parsed: AtomicU64,
+    parse_micros: AtomicU64,
+    heightmaps_decoded: AtomicU64,
+    heightmap_micros: AtomicU64,
+    meshes_built: AtomicU64,
+    mesh_build_micros: AtomicU64,
+}
+
+pub static LOADING_COUNTERS: LoadingCounters = LoadingCounters::new();
+
+impl LoadingCounters {
+    const fn new() -> Self {
+        Self {
+            download_bytes: AtomicU64::new(0),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+            tiles_parsed: AtomicU64::new(0),
+            parse_micros: AtomicU64::new(0),
+            heightmaps_decoded: AtomicU64::new(0),
+            heightmap_micros: AtomicU64::new(0),
+            meshes_built: AtomicU64::new(0),
+            mesh_build_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_download(&self, bytes: usize) {
+        self.download_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_cache_lookup(&self, hit: bool) {
+        if hit {
+            self.cache_hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.cache_misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records the time it took to decode a vector tile into features
+    pub fn record_parse(&self, duration: Duration) {
+        self.tiles_parsed.fetch_add(1, Ordering::Relaxed);
+        self.parse_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Records the time it took to sample the heights of an elevation tile
+    pub fn record_heightmap(&self, duration: Duration) {
+        self.heightmaps_decoded.fetch_add(1, Ordering::Relaxed);
+        self.heightmap_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Records the time it took to build the meshes of a tile
+    pub fn record_mesh_build(&self, duration: Duration) {
+        self.meshes_built.fetch_add(1, Ordering::Relaxed);
+        self.mesh_build_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn totals(&self) -> LoadingTotals {
+        LoadingTotals {
+            download_bytes: self.download_bytes.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+            tiles_parsed: self.tiles_parsed.load(Ordering::Relaxed),
+            parse_micros: self.parse_micros.load(Ordering::Relaxed),
+            heightmaps_decoded: self.heightmaps_decoded.load(Ordering::Relaxed),
+            heightmap_micros: self.heightmap_micros.load(Ordering::Relaxed),
+            meshes_built: self.meshes_built.load(Ordering::Relaxed),
+            mesh_build_micros: self.mesh_build_micros.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The values of [`LoadingCounters`] since the start of the application
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct LoadingTotals {
+    pub download_bytes: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub tiles_parsed: u64,
+    pub parse_micros: u64,
+    pub heightmaps_decoded: u64,
+    pub heightmap_micros: u64,
+    pub meshes_built: u64,
+    pub mesh_build_micros: u64,
+}
+
+impl LoadingTotals {
+    /// Average time to parse a tile (milliseconds)
+    pub fn average_parse_ms(&self) -> f64 {
+        self.parse_micros as f64 / 1000.0 / self.tiles_parsed.max(1) as f64
+    }
+
+    /// Average time to sample the heights of an elevation tile (milliseconds)
+    pub fn average_heightmap_ms(&self) -> f64 {
+        self.heightmap_micros as f64 / 1000.0 / self.heightmaps_decoded.max(1) as f64
+    }
+
+    /// Average time to build the meshes of a tile (milliseconds)
+    pub fn average_mesh_build_ms(&self) -> f64 {
+        self.mesh_build_micros as f64 / 1000.0 / self.meshes_built.max(1) as f64
+    }
+
+    pub fn cache_hit_ratio(&self) -> f64 {
+        self.cache_hits as f64 / (self.cache_hits + self.cache_misses).max(1) as f64
+    }
+}
+
+/// Estimated GPU memory of the mesh and image assets, based on their CPU side data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct GpuMemoryEstimate {
+    pub mesh_bytes: usize,
+    pub image_bytes: usize,
+}
+
+impl GpuMemoryEstimate {
+    pub fn total_bytes(&self) -> usize {
+        self.mesh_bytes + self.image_bytes
+    }
+}
+
+fn get_mesh_size(mesh: &Mesh) -> usize {
+    let indices = match mesh.indices() {
+        Some(Indices::U16(indices)) => indices.len() * 2,
+        Some(Indices::U32(indices)) => indices.len() * 4,
+        None => 0,
+    };
+    mesh.get_vertex_buffer_size() + indices
+}
+
+/// A single frame of a recorded session
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PerformanceSample {
+    /// Time since the start of the application (seconds)
+    pub time: f64,
+    pub fps: f64,
+    /// Frame time (milliseconds)
+    pub frametime: f64,
+    pub chunks_loading: usize,
+    pub chunks_loaded: usize,
+    pub entities: usize,
+    pub draw_calls: usize,
+    pub gpu_mesh_bytes: usize,
+    pub gpu_image_bytes: usize,
+    pub download_bytes: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// Average time to parse a tile so far (milliseconds)
+    pub parse_ms: f64,
+    /// Average time to sample the heights of an elevation tile so far (milliseconds)
+    pub heightmap_ms: f64,
+    /// Average time to build the meshes of a tile so far (milliseconds)
+    pub mesh_build_ms: f64,
+}
+
+/// Records a sample every frame while enabled, so sessions can be compared between commits.
+#[derive(Resource, Debug, Default)]
+pub struct SessionRecorder {
+    pub recording: bool,
+    /// Name of the session, for example the commit that is being measured
+    pub label: String,
+    samples: Vec<PerformanceSample>,
+}
+
+#[derive(Serialize)]
+struct SessionFile<'a> {
+    label: &'a str,
+    samples: &'a [PerformanceSample],
+}
+
+impl SessionRecorder {
+    pub fn start(&mut self) {
+        self.samples.clear();
+        self.recording = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.recording = false;
+    }
+
+    pub fn samples(&self) -> &[PerformanceSample] {
+        &self.samples
+    }
+
+    /// The samples as CSV, with one row per frame
+    pub fn to_csv(&self) -> Result<Vec<u8>, csv::Error> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for sample in &self.samples {
+            writer.serialize(sample)?;
+        }
+        writer.into_inner().map_err(|err| err.into_error().into())
+    }
+
+    /// The label and samples as JSON
+    pub fn to_json(&self) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec_pretty(&SessionFile {
+            label: &self.label,
+            samples: &self.samples,
+        })
+    }
+
+    /// Returns an unused key in [`SESSION_DIRECTORY`] named after the label
+    pub fn get_session_key(&self, extension: &str) -> String {
+        let label = match self.label.is_empty() {
+            true => "session".to_string(),
+            false => self
+                .label
+                .chars()
+                .map(|c| if c.is_alphanumeric() { c } else { '-' })
+                .collect(),
+        };
+        (1..)
+            .map(|i| format!("{SESSION_DIRECTORY}/{label}-{i}.{extension}"))
+            .find(|key| !data_storage().contains(key))
+            .expect("There should be an unused session key")
+    }
+
+    /// Writes the session to the [`data_storage`] as CSV or JSON, depending on the extension, and
+    /// returns its key
+    pub fn save(&self, extension: &str) -> io::Result<String> {
+        let bytes = match extension {
+            "json" => self.to_json()?,
+            _ => self.to_csv().map_err(io::Error::other)?,
+        };
+        let key = self.get_session_key(extension);
+        data_storage().write(&key, &bytes)?;
+        Ok(key)
+    }
+}
+
+/// The number of entities and draw calls of a loaded chunk
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkRenderStats {
+    pub x: i32,
+    pub y: i32,
+    pub z: i8,
+    /// The chunk entity and its children
+    pub entities: usize,
+    /// Estimated as the number of distinct meshes, entities that share a mesh and material are
+    /// drawn as instances in a single draw call
+    pub draw_calls: usize,
+}
 
 #[derive(Resource)]
 pub struct OSMPerformance {
     pub chunks_loading: VecDeque<usize>,
     pub fps: VecDeque<f64>,
     pub frametime: VecDeque<f64>,
+    pub entities: VecDeque<usize>,
+    pub draw_calls: VecDeque<usize>,
+    /// Downloaded bytes per frame
+    pub download_bytes: VecDeque<u64>,
+    /// Stats of the loaded chunks in the last frame
+    pub chunk_stats: Vec<ChunkRenderStats>,
+    /// Loading counters in the last frame
+    pub loading: LoadingTotals,
+    pub gpu_memory: GpuMemoryEstimate,
+    /// When [`Self::gpu_memory`] was last estimated (seconds)
+    gpu_memory_time: Option<f64>,
 }
 
 impl Default for OSMPerformance {
@@ -29,6 +309,13 @@ impl Default for OSMPerformance {
             chunks_loading: vec![0; MAX_PERFORMANCE_HISTORY].into(),
             fps: vec![0.0; MAX_PERFORMANCE_HISTORY].into(),
             frametime: vec![0.0; MAX_PERFORMANCE_HISTORY].into(),
+            entities: vec![0; MAX_PERFORMANCE_HISTORY].into(),
+            draw_calls: vec![0; MAX_PERFORMANCE_HISTORY].into(),
+            download_bytes: vec![0; MAX_PERFORMANCE_HISTORY].into(),
+            chunk_stats: Vec::new(),
+            loading: LoadingTotals::default(),
+            gpu_memory: GpuMemoryEstimate::default(),
+            gpu_memory_time: None,
         }
     }
 }
@@ -62,16 +349,71 @@ impl OSMPerformance {
             Color32::LIGHT_GREEN
         }
     }
+    pub fn get_frametime_plot_color(&self) -> Color32 {
+        let last = *self
+            .frametime
+            .iter()
+            .last()
+            .expect("History should always be filled");
+        if last > 33.3 {
+            Color32::RED
+        } else if last > 20.0 {
+            Color32::ORANGE
+        } else {
+            Color32::LIGHT_GREEN
+        }
+    }
 }
 
+#[expect(clippy::too_many_arguments)]
 pub fn update_performance(
     mut performance: ResMut<OSMPerformance>,
-    loading_chunks: Query<(Entity, &Chunk), Without<ChunkLoaded>>,
+    mut recorder: ResMut<SessionRecorder>,
+    chunk_states: Query<&ChunkLoadState>,
+    loaded_chunks: Query<(&Chunk, Option<&Children>), With<ChunkLoaded>>,
+    meshes: Query<&Mesh3d>,
+    mesh_assets: Res<Assets<Mesh>>,
+    image_assets: Res<Assets<Image>>,
     diagnostics: Res<DiagnosticsStore>,
+    time: Res<Time>,
 ) {
-    performance
-        .chunks_loading
-        .push_back(loading_chunks.iter().len());
+    performance.chunks_loading.push_back(
+        chunk_states
+            .iter()
+            .filter(|state| !matches!(state, ChunkLoadState::Loaded))
+            .count(),
+    );
+
+    performance.chunk_stats = loaded_chunks
+        .iter()
+        .map(|(chunk, children)| {
+            let children = children.map_or(&[][..], |children| &children[..]);
+            ChunkRenderStats {
+                x: chunk.x,
+                y: chunk.y,
+                z: chunk.z,
+                entities: 1 + children.len(),
+                draw_calls: children
+                    .iter()
+                    .filter_map(|child| meshes.get(*child).ok())
+                    .map(|mesh| mesh.id())
+                    .collect::<HashSet<_>>()
+                    .len(),
+            }
+        })
+        .collect();
+    let entities = performance
+        .chunk_stats
+        .iter()
+        .map(|stats| stats.entities)
+        .sum();
+    let draw_calls = performance
+        .chunk_stats
+        .iter()
+        .map(|stats| stats.draw_calls)
+        .sum();
+    performance.entities.push_back(entities);
+    performance.draw_calls.push_back(draw_calls);
 
     if let Some(fps) = diagnostics
         .get(&FrameTimeDiagnosticsPlugin::FPS)
@@ -86,6 +428,54 @@ pub fn update_performance(
         performance.frametime.push_back(frametime);
     }
 
+    let loading = LOADING_COUNTERS.totals();
+    let download_bytes = loading.download_bytes - performance.loading.download_bytes;
+    performance.download_bytes.push_back(download_bytes);
+    performance.loading = loading;
+
+    let now = time.elapsed_secs_f64();
+    if performance
+        .gpu_memory_time
+        .is_none_or(|last| now - last >= GPU_MEMORY_INTERVAL)
+    {
+        performance.gpu_memory = GpuMemoryEstimate {
+            mesh_bytes: mesh_assets
+                .iter()
+                .map(|(_, mesh)| get_mesh_size(mesh))
+                .sum(),
+            image_bytes: image_assets
+                .iter()
+                .map(|(_, image)| image.data.as_ref().map_or(0, Vec::len))
+                .sum(),
+        };
+        performance.gpu_memory_time = Some(now);
+    }
+
+    if recorder.recording {
+        let sample = PerformanceSample {
+            time: now,
+            fps: performance.fps.back().copied().unwrap_or_default(),
+            frametime: performance.frametime.back().copied().unwrap_or_default(),
+            chunks_loading: performance
+                .chunks_loading
+                .back()
+                .copied()
+                .unwrap_or_default(),
+            chunks_loaded: performance.chunk_stats.len(),
+            entities,
+            draw_calls,
+            gpu_mesh_bytes: performance.gpu_memory.mesh_bytes,
+            gpu_image_bytes: performance.gpu_memory.image_bytes,
+            download_bytes: loading.download_bytes,
+            cache_hits: loading.cache_hits,
+            cache_misses: loading.cache_misses,
+            parse_ms: loading.average_parse_ms(),
+            heightmap_ms: loading.average_heightmap_ms(),
+            mesh_build_ms: loading.average_mesh_build_ms(),
+        };
+        recorder.samples.push(sample);
+    }
+
     while performance.chunks_loading.len() > MAX_PERFORMANCE_HISTORY {
         performance.chunks_loading.pop_front();
     }
@@ -97,4 +487,16 @@ pub fn update_performance(
     while performance.frametime.len() > MAX_PERFORMANCE_HISTORY {
         performance.frametime.pop_front();
     }
+
+    while performance.entities.len() > MAX_PERFORMANCE_HISTORY {
+        performance.entities.pop_front();
+    }
+
+    while performance.draw_calls.len() > MAX_PERFORMANCE_HISTORY {
+        performance.draw_calls.pop_front();
+    }
+
+    while performance.download_bytes.len() > MAX_PERFORMANCE_HISTORY {
+        performance.download_bytes.pop_front();
+    }
 }
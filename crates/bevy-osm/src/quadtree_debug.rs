@@ -0,0 +1,327 @@
+//! A debug overlay of the terrain quadtree.
+//!
+//! The outline of every quadtree node is drawn over the terrain with gizmos, colored by the
+//! loading state of its chunk, and labeled with its lod and tile coordinates. Around the camera,
+//! a square of half size `k * width` shows for every lod how close the camera has to be for a
+//! node of that lod to be subdivided. Toggled with F3.
+
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use bevy_egui::{
+    EguiContexts,
+    egui::{self, Align2, Color32, FontId, Pos2, Rect as EguiRect},
+};
+use bevy_terrain::{
+    camera::get_ground_height,
+    mesh::ChunkHeightMap,
+    quadtree::{QuadTree, QuadTreeConfig, QuadTreeNodeComponent},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{chunk::Chunk, config::OSMConfig, scheduler::ChunkLoadState};
+
+/// Number of segments along each side of the draped outline of a node
+const EDGE_SEGMENTS: usize = 8;
+/// Height of the outlines above the terrain (meters)
+const OUTLINE_OFFSET: f32 = 2.0;
+
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct QuadTreeDebug {
+    pub enabled: bool,
+    /// Show the lod, tile coordinates and loading state of the nodes
+    pub labels: bool,
+    /// Show the distance at which the nodes of each lod are subdivided
+    pub subdivision_distance: bool,
+}
+
+impl Default for QuadTreeDebug {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            labels: true,
+            subdivision_distance: true,
+        }
+    }
+}
+
+/// The color of the outline of a node, nodes without a chunk are still being spawned
+fn get_state_color(state: Option<&ChunkLoadState>) -> Color {
+    match state {
+        None => Color::srgb(0.5, 0.5, 0.5),
+        Some(ChunkLoadState::Queued) => Color::srgb(0.8, 0.8, 0.8),
+        Some(ChunkLoadState::Downloading(_)) => Color::srgb(0.2, 0.5, 1.0),
+        Some(ChunkLoadState::Parsing) => Color::srgb(0.9, 0.7, 0.15),
+        Some(ChunkLoadState::Building) => Color::srgb(0.6, 0.85, 0.2),
+        Some(ChunkLoadState::Loaded) => Color::srgb(0.25, 0.8, 0.25),
+        Some(ChunkLoadState::Failed) => Color::srgb(0.9, 0.2, 0.2),
+    }
+}
+
+/// The color of the subdivision distance of a lod
+fn get_lod_color(lod: u8) -> Color {
+    Color::hsl(lod as f32 * 47.0 % 360.0, 0.9, 0.6)
+}
+
+/// Points along the draped outline of a node in chunk space
+fn get_outline(heights: Option<&ChunkHeightMap>) -> Vec<Vec3> {
+    let corners = [
+        Vec2::new(-0.5, -0.5),
+        Vec2::new(-0.5, 0.5),
+        Vec2::new(0.5, 0.5),
+        Vec2::new(0.5, -0.5),
+    ];
+    let mut points = Vec::with_capacity(4 * EDGE_SEGMENTS + 1);
+    for i in 0..4 {
+        let (start, end) = (corners[i], corners[(i + 1) % 4]);
+        for j in 0..EDGE_SEGMENTS {
+            let point = start.lerp(end, j as f32 / EDGE_SEGMENTS as f32);
+            // The border of the height map covers the edges of the chunk
+            let height = heights.map_or(0.0, |heights| heights.0.sample(point));
+            points.push(Vec3::new(point.x, height + OUTLINE_OFFSET, point.y));
+        }
+    }
+    points.push(points[0]);
+    points
+}
+
+/// The width of the nodes of every lod in the tree, and of the lod below the most detailed one,
+/// since that is where the next subdivision happens
+fn get_lod_widths<'a>(
+    rects: impl Iterator<Item = (u8, &'a Rect)>,
+    config: &QuadTreeConfig,
+) -> BTreeMap<u8, f32> {
+    let mut widths = BTreeMap::new();
+    for (lod, rect) in rects {
+        widths.insert(lod, rect.width());
+    }
+    if let Some((&lod, &width)) = widths.last_key_value()
+        && lod < config.max_lod
+    {
+        widths.insert(lod + 1, width / 2.0);
+    }
+    widths
+}
+
+/// Draws the outlines of the quadtree nodes and the subdivision distances around the camera
+pub fn draw_quadtree_debug(
+    mut gizmos: Gizmos,
+    mut debug: ResMut<QuadTreeDebug>,
+    keys: Res<ButtonInput<KeyCode>>,
+    camera: Single<&GlobalTransform, With<Camera>>,
+    quadtrees: Query<(&GlobalTransform, &QuadTreeConfig), With<QuadTree>>,
+    nodes: Query<(
+        &QuadTreeNodeComponent,
+        &GlobalTransform,
+        Option<&ChunkHeightMap>,
+        Option<&ChunkLoadState>,
+    )>,
+    chunks: Query<(&ChunkHeightMap, &GlobalTransform, &QuadTreeNodeComponent)>,
+) {
+    if keys.just_pressed(KeyCode::F3) {
+        debug.enabled = !debug.enabled;
+    }
+    if !debug.enabled {
+        return;
+    }
+
+    for (_, transform, heights, state) in &nodes {
+        let color = get_state_color(state);
+        gizmos.linestrip(
+            get_outline(heights)
+                .into_iter()
+                .map(|point| transform.transform_point(point)),
+            color,
+        );
+    }
+
+    if !debug.subdivision_distance {
+        return;
+    }
+    let position = camera.translation();
+    for (transform, config) in &quadtrees {
+        // With a screen-space error, `k` is not used
+        if config.max_screen_space_error.is_some() {
+            continue;
+        }
+        let offset = transform.translation();
+        let camera_height = (position - offset).y;
+        let ground = get_ground_height(&chunks, position.xz()).unwrap_or(offset.y) + OUTLINE_OFFSET;
+        let widths = get_lod_widths(
+            nodes.iter().map(|(node, ..)| (node.lod, &node.rect)),
+            config,
+        );
+        for (lod, width) in widths {
+            let distance = config.k * width;
+            // Above this distance, nodes of this lod are never subdivided
+            if camera_height >= distance {
+                continue;
+            }
+            gizmos.rect(
+                Isometry3d::new(
+                    Vec3::new(position.x, ground, position.z),
+                    Quat::from_rotation_x(std::f32::consts::FRAC_PI_2),
+                ),
+                Vec2::splat(2.0 * distance),
+                get_lod_color(lod),
+            );
+        }
+    }
+}
+
+/// Labels the nodes with their lod, tile and loading state, and lists the subdivision distances
+/// of every lod
+pub fn quadtree_debug_ui(
+    mut contexts: EguiContexts,
+    mut debug: ResMut<QuadTreeDebug>,
+    config: Res<OSMConfig>,
+    camera: Single<(&Camera, &GlobalTransform)>,
+    quadtrees: Query<(&GlobalTransform, &QuadTreeConfig), With<QuadTree>>,
+    nodes: Query<(
+        &QuadTreeNodeComponent,
+        &GlobalTransform,
+        Option<&ChunkHeightMap>,
+        Option<&Chunk>,
+        Option<&ChunkLoadState>,
+    )>,
+) {
+    if !debug.enabled {
+        return;
+    }
+    let Ok(ctx) = contexts.ctx_mut() else {
+        return;
+    };
+    let (camera, camera_transform) = *camera;
+    let position = camera_transform.translation();
+
+    if debug.labels {
+        let mut visible: Vec<(String, Color, Vec2, f32)> = nodes
+            .iter()
+            .filter_map(|(node, transform, heights, chunk, state)| {
+                let height = heights.map_or(0.0, |heights| heights.0.sample(Vec2::ZERO));
+                let center = transform.transform_point(Vec3::new(0.0, height, 0.0));
+                let screen = camera.world_to_viewport(camera_transform, center).ok()?;
+                let tile = match chunk {
+                    Some(chunk) => format!("{}/{}/{}", chunk.z, chunk.x, chunk.y),
+                    None => format!("{}/{}", node.x, node.y),
+                };
+                let name = state.map_or("spawned", ChunkLoadState::get_name);
+                Some((
+                    format!("lod {}\n{tile}\n{name}", node.lod),
+                    get_state_color(state),
+                    screen,
+                    center.distance(position),
+                ))
+            })
+            .collect();
+        // Nearby nodes are the most interesting ones
+        visible.sort_by(|a, b| a.3.total_cmp(&b.3));
+
+        let painter = ctx.layer_painter(egui::LayerId::background());
+        let mut occupied: Vec<EguiRect> = Vec::new();
+        for (text, color, screen, _) in visible {
+            let [r, g, b, _] = color.to_srgba().to_u8_array();
+            let galley = painter.layout(
+                text,
+                FontId::monospace(11.0),
+                Color32::from_rgb(r, g, b),
+                f32::INFINITY,
+            );
+            let rect =
+                Align2::CENTER_CENTER.anchor_size(Pos2::new(screen.x, screen.y), galley.size());
+            if occupied.iter().any(|other| other.intersects(rect)) {
+                continue;
+            }
+            occupied.push(rect);
+            painter.rect_filled(rect.expand(2.0), 3.0, Color32::from_black_alpha(160));
+            painter.galley(rect.min, galley, Color32::WHITE);
+        }
+    }
+
+    if !config.ui_visible {
+        return;
+    }
+    egui::Window::new("Quadtree").show(ctx, |ui| {
+        ui.checkbox(&mut debug.labels, "labels");
+        ui.checkbox(&mut debug.subdivision_distance, "subdivision distance");
+
+        let mut states: BTreeMap<&str, usize> = BTreeMap::new();
+        for (.., state) in &nodes {
+            *states
+                .entry(state.map_or("spawned", ChunkLoadState::get_name))
+                .or_default() += 1;
+        }
+        ui.label(
+            states
+                .iter()
+                .map(|(state, count)| format!("{state}: {count}"))
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+
+        for (i, (transform, quadtree_config)) in quadtrees.iter().enumerate() {
+            let camera_height = (position - transform.translation()).y;
+            if quadtree_config.max_screen_space_error.is_some() {
+                ui.label("refined by screen-space error, k is not used");
+                continue;
+            }
+            let widths = get_lod_widths(
+                nodes.iter().map(|(node, ..)| (node.lod, &node.rect)),
+                quadtree_config,
+            );
+            egui::Grid::new(("quadtree_lods", i))
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("lod");
+                    ui.label("nodes");
+                    ui.label("width");
+                    ui.label("k * width");
+                    ui.end_row();
+                    for (lod, width) in widths {
+                        let distance = quadtree_config.k * width;
+                        let count = nodes.iter().filter(|(node, ..)| node.lod == lod).count();
+                        let color = if camera_height < distance {
+                            let [r, g, b, _] = get_lod_color(lod).to_srgba().to_u8_array();
+                            Color32::from_rgb(r, g, b)
+                        } else {
+                            Color32::GRAY
+                        };
+                        ui.label(lod.to_string());
+                        ui.label(count.to_string());
+                        ui.label(format!("{width:.0} m"));
+                        ui.colored_label(color, format!("{distance:.0} m"));
+                        ui.end_row();
+                    }
+                });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lod_widths() {
+        let config = QuadTreeConfig {
+            max_lod: 3,
+            ..default()
+        };
+        let rects = [
+            (1, Rect::from_center_size(Vec2::ZERO, Vec2::splat(500.0))),
+            (2, Rect::from_center_size(Vec2::ZERO, Vec2::splat(250.0))),
+            (1, Rect::from_center_size(Vec2::ONE, Vec2::splat(500.0))),
+        ];
+        let widths = get_lod_widths(rects.iter().map(|(lod, rect)| (*lod, rect)), &config);
+        // The next lod is added, but never beyond the most detailed one
+        assert_eq!(
+            widths.into_iter().collect::<Vec<_>>(),
+            vec![(1, 500.0), (2, 250.0), (3, 125.0)]
+        );
+
+        let rects = [(3, Rect::from_center_size(Vec2::ZERO, Vec2::splat(125.0)))];
+        let widths = get_lod_widths(rects.iter().map(|(lod, rect)| (*lod, rect)), &config);
+        assert_eq!(widths.len(), 1);
+    }
+}
@@ -0,0 +1,955 @@
+//! A routable road network from the `transportation` layer of the vector tiles.
+//!
+//! The roads of every loaded vector tile are collected in the [`RoadNetwork`], until the chunk
+//! of the tile is despawned. It builds a [`RoadGraph`] with nodes at the intersections and dead
+//! ends in a task, in which routes are found with A*. Spawn a [`RouteRequest`] to draw a route
+//! on the terrain:
+//!
+//! ```ignore
+//! commands.spawn(RouteRequest::new(Vec2::new(52.373, 4.892), Vec2::new(52.358, 4.881)));
+//! ```
+
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+    error::Error,
+    f64::consts::PI,
+    fmt,
+    sync::Arc,
+};
+
+use bevy::{
+    math::DVec2,
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task, block_on, futures_lite::future},
+};
+use geo_types::{Geometry, LineString};
+use mvt_reader::{Reader, error::ParserError, feature::Value};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chunk::Chunk,
+    overlay::{GeoFeature, GeoGeometry, GeoOverlay, GeoOverlayLayer, OverlayStyle},
+    schema::{LayerClass, layer::OMTLayer, parse_class, transportation::Transportation},
+};
+
+/// Extent of the coordinates in a vector tile
+const TILE_EXTENT: f64 = 4096.0;
+/// Vertices closer than this are merged into one, so roads of neighbouring tiles connect
+/// (degrees)
+const SNAP_DISTANCE: f64 = 1e-5;
+pub const METERS_PER_DEGREE: f64 = 111_320.0;
+/// Size of the cells of the [`NodeGrid`] (degrees)
+const NODE_CELL_SIZE: f64 = 0.005;
+
+/// The (x, y, z) of a tile
+type TileKey = (i32, i32, i8);
+
+/// The classes of roads that can be routed over
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RoadClass {
+    Motorway,
+    Trunk,
+    Primary,
+    Secondary,
+    Tertiary,
+    Minor,
+    Service,
+    Track,
+    Path,
+}
+
+impl RoadClass {
+    /// Returns the class of a `transportation` feature, or `None` if it can't be driven or
+    /// walked on, such as railways and ferries
+    pub fn from_transportation(class: &Transportation) -> Option<Self> {
+        match class {
+            Transportation::Motorway => Some(Self::Motorway),
+            Transportation::Trunk => Some(Self::Trunk),
+            Transportation::Primary => Some(Self::Primary),
+            Transportation::Secondary => Some(Self::Secondary),
+            Transportation::Tertiary => Some(Self::Tertiary),
+            Transportation::Minor => Some(Self::Minor),
+            Transportation::Service => Some(Self::Service),
+            Transportation::Track => Some(Self::Track),
+            Transportation::Path | Transportation::Pier => Some(Self::Path),
+            _ => None,
+        }
+    }
+
+    /// Typical speed on a road of this class, which the edges are weighted by (m/s)
+    pub fn get_speed(&self) -> f64 {
+        match self {
+            Self::Motorway => 30.0,
+            Self::Trunk => 25.0,
+            Self::Primary => 20.0,
+            Self::Secondary => 16.0,
+            Self::Tertiary => 14.0,
+            Self::Minor => 10.0,
+            Self::Service => 6.0,
+            Self::Track => 4.0,
+            Self::Path => 1.4,
+        }
+    }
+
+    /// The highest speed of all classes, used by the A* heuristic (m/s)
+    const MAX_SPEED: f64 = 30.0;
+}
+
+/// A road of a vector tile, clipped to the tile
+#[derive(Debug, Clone, PartialEq)]
+pub struct Road {
+    pub class: RoadClass,
+    /// Whether the road can only be used from its first point to its last
+    pub oneway: bool,
+    /// (lat, lon) in degrees, or tile coordinates for [`get_tile_roads`]
+    pub points: Vec<DVec2>,
+}
+
+/// Returns the distance between two (lat, lon) coordinates, which is accurate for the short
+/// distances between the vertices of a road (meters)
+pub fn get_distance(a: DVec2, b: DVec2) -> f64 {
+    let latitude = ((a.x + b.x) / 2.0).to_radians();
+    let north = (b.x - a.x) * METERS_PER_DEGREE;
+    let east = (b.y - a.y) * METERS_PER_DEGREE * latitude.cos();
+    north.hypot(east)
+}
+
+/// Like [`crate::chunk::get_lat_lon`], with the precision that is needed to connect roads
+fn get_lat_lon(x: f64, y: f64, zoom: i8) -> DVec2 {
+    let n = 2f64.powi(zoom as i32);
+    DVec2::new(
+        (PI - y / n * 2.0 * PI).sinh().atan().to_degrees(),
+        x / n * 360.0 - 180.0,
+    )
+}
+
+/// Clips a segment to the square from `0` to `extent` (Liang-Barsky)
+fn clip_segment(a: DVec2, b: DVec2, extent: f64) -> Option<(DVec2, DVec2)> {
+    let delta = b - a;
+    let (mut start, mut end) = (0.0f64, 1.0f64);
+    for (p, q) in [
+        (-delta.x, a.x),
+        (delta.x, extent - a.x),
+        (-delta.y, a.y),
+        (delta.y, extent - a.y),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+            continue;
+        }
+        let t = q / p;
+        if p < 0.0 {
+            start = start.max(t);
+        } else {
+            end = end.min(t);
+        }
+    }
+    (start < end).then(|| (a + delta * start, a + delta * end))
+}
+
+/// Clips a line in tile coordinates to the tile. Roads that cross the edge are cut exactly on
+/// it, so they connect to the same road in the neighbouring tile.
+fn clip_line(line: &LineString<i32>) -> Vec<Vec<DVec2>> {
+    let mut pieces: Vec<Vec<DVec2>> = Vec::new();
+    for segment in line.lines() {
+        let (a, b) = (
+            DVec2::new(segment.start.x as f64, segment.start.y as f64),
+            DVec2::new(segment.end.x as f64, segment.end.y as f64),
+        );
+        let Some((start, end)) = clip_segment(a, b, TILE_EXTENT) else {
+            continue;
+        };
+        match pieces.last_mut() {
+            Some(piece) if piece.last() == Some(&start) => piece.push(end),
+            _ => pieces.push(vec![start, end]),
+        }
+    }
+    pieces
+}
+
+fn get_oneway(feature: &mvt_reader::feature::Feature<i32>) -> i64 {
+    match feature
+        .properties
+        .as_ref()
+        .and_then(|properties| properties.get("oneway"))
+    {
+        Some(Value::Int(value) | Value::SInt(value)) => *value,
+        Some(Value::UInt(value)) => *value as i64,
+        _ => 0,
+    }
+}
+
+/// Reads the roads from a vector tile, with their points in tile coordinates from 0 to 4096
+pub fn get_tile_roads(bytes: Vec<u8>) -> Result<Vec<Road>, ParserError> {
+    let reader = Reader::new(bytes)?;
+    let mut roads = Vec::new();
+
+    for layer in reader.get_layer_metadata()? {
+        if OMTLayer::from_name(&layer.name) != OMTLayer::Transportation {
+            continue;
+        }
+
+        for feature in reader.get_features_as::<i32>(layer.layer_index)? {
+            let class = match feature
+                .properties
+                .as_ref()
+                .and_then(|properties| properties.get("class"))
+            {
+                Some(Value::String(class)) => parse_class(&OMTLayer::Transportation, class),
+                _ => continue,
+            };
+            let LayerClass::Transportation(class) = class else {
+                continue;
+            };
+            let Some(class) = RoadClass::from_transportation(&class) else {
+                continue;
+            };
+            let lines = match &feature.geometry {
+                Geometry::LineString(line) => vec![line.clone()],
+                Geometry::MultiLineString(lines) => lines.0.clone(),
+                _ => continue,
+            };
+
+            let oneway = get_oneway(&feature);
+            for line in &lines {
+                for mut points in clip_line(line) {
+                    // Roads that are oneway against the direction they are drawn in
+                    if oneway < 0 {
+                        points.reverse();
+                    }
+                    roads.push(Road {
+                        class,
+                        oneway: oneway != 0,
+                        points,
+                    });
+                }
+            }
+        }
+    }
+    Ok(roads)
+}
+
+/// Converts the points of roads from [`get_tile_roads`] to (lat, lon) in degrees
+pub fn get_lat_lon_roads(mut roads: Vec<Road>, chunk: &Chunk) -> Vec<Road> {
+    for road in &mut roads {
+        for point in &mut road.points {
+            *point = get_lat_lon(
+                chunk.x as f64 + point.x / TILE_EXTENT,
+                chunk.y as f64 + point.y / TILE_EXTENT,
+                chunk.z,
+            );
+        }
+    }
+    roads
+}
+
+/// Reads the roads from a vector tile, with their points as (lat, lon) in degrees
+pub fn get_roads(bytes: Vec<u8>, chunk: &Chunk) -> Result<Vec<Road>, ParserError> {
+    Ok(get_lat_lon_roads(get_tile_roads(bytes)?, chunk))
+}
+
+#[derive(Debug)]
+pub enum RoutingError {
+    /// No roads have been loaded
+    NoRoads,
+    /// The start and destination are not connected by the loaded roads
+    NoRoute,
+}
+
+impl fmt::Display for RoutingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoutingError::NoRoads => write!(f, "no roads have been loaded"),
+            RoutingError::NoRoute => write!(f, "no route was found between the locations"),
+        }
+    }
+}
+
+impl Error for RoutingError {}
+
+/// A road between two nodes of the [`RoadGraph`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoadEdge {
+    pub from: usize,
+    pub to: usize,
+    pub class: RoadClass,
+    /// Whether the edge can only be used from `from` to `to`
+    pub oneway: bool,
+    /// (meters)
+    pub length: f64,
+    /// The (lat, lon) of the road, from `from` to `to`
+    pub points: Vec<DVec2>,
+}
+
+impl RoadEdge {
+    /// The time it takes to travel over the edge, which routes are optimized for (seconds)
+    pub fn get_cost(&self) -> f64 {
+        self.length / self.class.get_speed()
+    }
+}
+
+/// A route from [`RoadGraph::find_route`]
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct Route {
+    /// (lat, lon) in degrees
+    pub points: Vec<DVec2>,
+    /// (meters)
+    pub length: f64,
+    /// (seconds)
+    pub duration: f64,
+}
+
+impl Route {
+    /// Returns the route as an overlay, to draw it with a [`GeoOverlayLayer`]
+    pub fn to_overlay(&self) -> GeoOverlay {
+        GeoOverlay {
+            features: vec![GeoFeature {
+                geometry: GeoGeometry::LineString(
+                    self.points.iter().map(|point| point.as_vec2()).collect(),
+                ),
+                name: None,
+                style: default(),
+            }],
+        }
+    }
+}
+
+/// Merges vertices that are within `snap_distance` of each other
+struct VertexIndex {
+    snap_distance: f64,
+    vertices: Vec<DVec2>,
+    cells: HashMap<(i64, i64), Vec<usize>>,
+}
+
+impl VertexIndex {
+    fn new(snap_distance: f64) -> Self {
+        Self {
+            snap_distance,
+            vertices: Vec::new(),
+            cells: HashMap::new(),
+        }
+    }
+
+    fn get_cell(&self, point: DVec2) -> (i64, i64) {
+        let cell = (point / self.snap_distance).floor();
+        (cell.x as i64, cell.y as i64)
+    }
+
+    fn insert(&mut self, point: DVec2) -> usize {
+        let (x, y) = self.get_cell(point);
+        for cell in (x - 1..=x + 1).flat_map(|x| (y - 1..=y + 1).map(move |y| (x, y))) {
+            if let Some(vertex) = self.cells.get(&cell).and_then(|vertices| {
+                vertices
+                    .iter()
+                    .find(|&&vertex| self.vertices[vertex].distance(point) <= self.snap_distance)
+            }) {
+                return *vertex;
+            }
+        }
+        let vertex = self.vertices.len();
+        self.vertices.push(point);
+        self.cells.entry((x, y)).or_default().push(vertex);
+        vertex
+    }
+}
+
+/// The nodes of a graph in cells of (lat, lon), to find the nearest node without measuring the
+/// distance to every node
+#[derive(Debug, Default)]
+struct NodeGrid {
+    cells: HashMap<(i64, i64), Vec<usize>>,
+    /// The bounds of the cells that contain nodes
+    min: (i64, i64),
+    max: (i64, i64),
+}
+
+impl NodeGrid {
+    fn get_cell(point: DVec2) -> (i64, i64) {
+        let cell = (point / NODE_CELL_SIZE).floor();
+        (cell.x as i64, cell.y as i64)
+    }
+
+    fn new(nodes: &[DVec2]) -> Self {
+        let mut grid = Self {
+            min: (i64::MAX, i64::MAX),
+            max: (i64::MIN, i64::MIN),
+            ..default()
+        };
+        for (node, &point) in nodes.iter().enumerate() {
+            let cell = Self::get_cell(point);
+            grid.cells.entry(cell).or_default().push(node);
+            grid.min = (grid.min.0.min(cell.0), grid.min.1.min(cell.1));
+            grid.max = (grid.max.0.max(cell.0), grid.max.1.max(cell.1));
+        }
+        grid
+    }
+
+    /// The cells at a ring around `center` that lie within the bounds
+    fn get_ring(&self, (x, y): (i64, i64), ring: i64) -> Vec<(i64, i64)> {
+        let (min, max) = (self.min, self.max);
+        let columns = (x - ring).max(min.0)..=(x + ring).min(max.0);
+        let rows = (y - ring + 1).max(min.1)..=(y + ring - 1).min(max.1);
+        let mut cells = Vec::new();
+        for row in [y - ring, y + ring] {
+            if (min.1..=max.1).contains(&row) {
+                cells.extend(columns.clone().map(|column| (column, row)));
+            }
+            if ring == 0 {
+                return cells;
+            }
+        }
+        for column in [x - ring, x + ring] {
+            if (min.0..=max.0).contains(&column) {
+                cells.extend(rows.clone().map(|row| (column, row)));
+            }
+        }
+        cells
+    }
+
+    /// Searches the cells in rings around the cell of `lat_lon`, until the rings are further
+    /// away than the nearest node found so far
+    fn get_nearest(&self, nodes: &[DVec2], lat_lon: DVec2) -> Option<usize> {
+        if self.cells.is_empty() {
+            return None;
+        }
+        let center = Self::get_cell(lat_lon);
+        let last_ring = [
+            center.0 - self.min.0,
+            self.max.0 - center.0,
+            center.1 - self.min.1,
+            self.max.1 - center.1,
+        ]
+        .into_iter()
+        .map(i64::abs)
+        .max()
+        .unwrap_or(0);
+        // About the smallest width of a nearby cell, since degrees of longitude get shorter
+        // towards the poles
+        let cell_width = NODE_CELL_SIZE
+            * METERS_PER_DEGREE
+            * (lat_lon.x.abs() + NODE_CELL_SIZE)
+                .min(89.0)
+                .to_radians()
+                .cos();
+
+        let mut nearest: Option<(usize, f64)> = None;
+        for ring in 0..=last_ring {
+            for cell in self.get_ring(center, ring) {
+                for &node in self.cells.get(&cell).into_iter().flatten() {
+                    let distance = get_distance(nodes[node], lat_lon);
+                    if nearest.is_none_or(|(_, nearest)| distance < nearest) {
+                        nearest = Some((node, distance));
+                    }
+                }
+            }
+            // Nodes in the next rings are at least this far away
+            if nearest.is_some_and(|(_, distance)| distance <= ring as f64 * cell_width) {
+                break;
+            }
+        }
+        nearest.map(|(node, _)| node)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct QueueEntry {
+    /// The cost so far plus the estimate of the remaining cost
+    estimate: f64,
+    node: usize,
+}
+
+impl Eq for QueueEntry {}
+
+impl Ord for QueueEntry {
+    /// Reversed, so the [`BinaryHeap`] pops the lowest estimate first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+impl PartialOrd for QueueEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A graph of roads with nodes at intersections and dead ends
+#[derive(Debug, Default)]
+pub struct RoadGraph {
+    /// (lat, lon) in degrees, unless the graph was built [`RoadGraph::with_metric`]
+    pub nodes: Vec<DVec2>,
+    pub edges: Vec<RoadEdge>,
+    /// The edges that start or end at each node
+    adjacency: Vec<Vec<usize>>,
+    /// Only for graphs with (lat, lon) coordinates
+    node_grid: Option<NodeGrid>,
+}
+
+impl RoadGraph {
+    /// Builds the graph of roads with (lat, lon) coordinates
+    pub fn new<'a>(roads: impl IntoIterator<Item = &'a Road>) -> Self {
+        let mut graph = Self::with_metric(roads, SNAP_DISTANCE, get_distance);
+        graph.node_grid = Some(NodeGrid::new(&graph.nodes));
+        graph
+    }
+
+    /// Builds the graph of roads in other coordinates, such as those of [`get_tile_roads`].
+    /// Vertices within `snap_distance` are merged, `distance` returns the length of a segment in
+    /// meters.
+    pub fn with_metric<'a>(
+        roads: impl IntoIterator<Item = &'a Road>,
+        snap_distance: f64,
+        distance: impl Fn(DVec2, DVec2) -> f64,
+    ) -> Self {
+        let mut index = VertexIndex::new(snap_distance);
+        let mut segments = HashSet::new();
+        let mut incidence: HashMap<usize, usize> = HashMap::new();
+        let mut endpoints = HashSet::new();
+
+        // Split the roads into runs of segments that haven't been seen before, roads in the
+        // overlapping parts of tiles would otherwise be added twice.
+        let mut runs: Vec<(RoadClass, bool, Vec<usize>)> = Vec::new();
+        for road in roads {
+            let mut vertices: Vec<usize> = road
+                .points
+                .iter()
+                .map(|&point| index.insert(point))
+                .collect();
+            vertices.dedup();
+
+            let mut run: Vec<usize> = Vec::new();
+            for pair in vertices.windows(2) {
+                let (a, b) = (pair[0], pair[1]);
+                if !segments.insert((a.min(b), a.max(b))) {
+                    if run.len() >= 2 {
+                        runs.push((road.class, road.oneway, std::mem::take(&mut run)));
+                    }
+                    run.clear();
+                    continue;
+                }
+                if run.is_empty() {
+                    run.push(a);
+                }
+                run.push(b);
+                *incidence.entry(a).or_default() += 1;
+                *incidence.entry(b).or_default() += 1;
+            }
+            if run.len() >= 2 {
+                runs.push((road.class, road.oneway, run));
+            }
+        }
+        for (_, _, run) in &runs {
+            endpoints.insert(run[0]);
+            endpoints.insert(run[run.len() - 1]);
+        }
+
+        let mut graph = RoadGraph::default();
+        let mut nodes: HashMap<usize, usize> = HashMap::new();
+        let mut get_node = |graph: &mut RoadGraph, vertex: usize| {
+            *nodes.entry(vertex).or_insert_with(|| {
+                graph.nodes.push(index.vertices[vertex]);
+                graph.adjacency.push(Vec::new());
+                graph.nodes.len() - 1
+            })
+        };
+
+        for (class, oneway, run) in runs {
+            let mut start = 0;
+            for (i, &vertex) in run.iter().enumerate().skip(1) {
+                let is_node = i == run.len() - 1
+                    || endpoints.contains(&vertex)
+                    || incidence.get(&vertex) != Some(&2);
+                if !is_node {
+                    continue;
+                }
+
+                let points: Vec<DVec2> = run[start..=i]
+                    .iter()
+                    .map(|&vertex| index.vertices[vertex])
+                    .collect();
+                let length = points
+                    .windows(2)
+                    .map(|pair| distance(pair[0], pair[1]))
+                    .sum();
+                let from = get_node(&mut graph, run[start]);
+                let to = get_node(&mut graph, vertex);
+
+                let edge = graph.edges.len();
+                graph.edges.push(RoadEdge {
+                    from,
+                    to,
+                    class,
+                    oneway,
+                    length,
+                    points,
+                });
+                graph.adjacency[from].push(edge);
+                if to != from {
+                    graph.adjacency[to].push(edge);
+                }
+                start = i;
+            }
+        }
+        graph
+    }
+
+    pub fn get_nearest_node(&self, lat_lon: DVec2) -> Option<usize> {
+        if let Some(grid) = &self.node_grid {
+            return grid.get_nearest(&self.nodes, lat_lon);
+        }
+        (0..self.nodes.len()).min_by(|&a, &b| {
+            get_distance(self.nodes[a], lat_lon).total_cmp(&get_distance(self.nodes[b], lat_lon))
+        })
+    }
+
+    /// Finds the fastest route between the nodes that are closest to `from` and `to`, which are
+    /// (lat, lon) in degrees
+    pub fn find_route(&self, from: DVec2, to: DVec2) -> Result<Route, RoutingError> {
+        let (Some(start), Some(goal)) = (self.get_nearest_node(from), self.get_nearest_node(to))
+        else {
+            return Err(RoutingError::NoRoads);
+        };
+        let heuristic =
+            |node: usize| get_distance(self.nodes[node], self.nodes[goal]) / RoadClass::MAX_SPEED;
+
+        let mut costs = vec![f64::INFINITY; self.nodes.len()];
+        // The edge each node was reached through, and whether it was traversed forwards
+        let mut previous: Vec<Option<(usize, bool)>> = vec![None; self.nodes.len()];
+        let mut queue = BinaryHeap::new();
+        costs[start] = 0.0;
+        queue.push(QueueEntry {
+            estimate: heuristic(start),
+            node: start,
+        });
+
+        while let Some(QueueEntry { estimate, node }) = queue.pop() {
+            if node == goal {
+                break;
+            }
+            if estimate - heuristic(node) > costs[node] {
+                // A cheaper way to this node was found after it was queued
+                continue;
+            }
+            for &edge_index in &self.adjacency[node] {
+                let edge = &self.edges[edge_index];
+                let (next, forward) = if edge.from == node {
+                    (edge.to, true)
+                } else if !edge.oneway {
+                    (edge.from, false)
+                } else {
+                    continue;
+                };
+                let cost = costs[node] + edge.get_cost();
+                if cost < costs[next] {
+                    costs[next] = cost;
+                    previous[next] = Some((edge_index, forward));
+                    queue.push(QueueEntry {
+                        estimate: cost + heuristic(next),
+                        node: next,
+                    });
+                }
+            }
+        }
+
+        if costs[goal].is_infinite() {
+            return Err(RoutingError::NoRoute);
+        }
+
+        let mut path = Vec::new();
+        let mut node = goal;
+        while let Some((edge_index, forward)) = previous[node] {
+            let edge = &self.edges[edge_index];
+            path.push((edge, forward));
+            node = if forward { edge.from } else { edge.to };
+        }
+        path.reverse();
+
+        let mut points = vec![self.nodes[start]];
+        for (edge, forward) in &path {
+            if *forward {
+                points.extend(&edge.points[1..]);
+            } else {
+                points.extend(edge.points.iter().rev().skip(1));
+            }
+        }
+        Ok(Route {
+            points,
+            length: path.iter().map(|(edge, _)| edge.length).sum(),
+            duration: costs[goal],
+        })
+    }
+}
+
+/// The roads of the vector tiles of the chunks that are loaded
+#[derive(Resource, Default)]
+pub struct RoadNetwork {
+    /// The roads of each tile
+    tiles: HashMap<TileKey, Arc<Vec<Road>>>,
+    /// The tile of each chunk entity, to remove the roads when the chunk is despawned
+    chunks: HashMap<Entity, TileKey>,
+    graph: Option<Arc<RoadGraph>>,
+    /// Whether tiles were added or removed since the graph was built
+    stale: bool,
+    task: Option<Task<RoadGraph>>,
+}
+
+impl RoadNetwork {
+    pub fn contains_tile(&self, chunk: &Chunk) -> bool {
+        self.tiles.contains_key(&(chunk.x, chunk.y, chunk.z))
+    }
+
+    /// Adds the roads of the tile of a chunk, unless the tile was added before
+    pub fn insert_tile(&mut self, entity: Entity, chunk: &Chunk, roads: Vec<Road>) {
+        let tile = (chunk.x, chunk.y, chunk.z);
+        self.chunks.insert(entity, tile);
+        if !self.tiles.contains_key(&tile) {
+            self.tiles.insert(tile, Arc::new(roads));
+            self.stale = true;
+        }
+    }
+
+    /// Removes the roads of the tile of a chunk, unless another chunk has the same tile
+    pub fn remove_chunk(&mut self, entity: Entity) {
+        let Some(tile) = self.chunks.remove(&entity) else {
+            return;
+        };
+        if !self.chunks.values().any(|other| *other == tile) {
+            self.tiles.remove(&tile);
+            self.stale = true;
+        }
+    }
+
+    /// Whether the area of a tile is covered by tiles of a higher zoom level, which have more
+    /// detailed roads
+    fn is_covered(&self, (x, y, z): TileKey, max_zoom: i8) -> bool {
+        z < max_zoom
+            && [(0, 0), (1, 0), (0, 1), (1, 1)]
+                .into_iter()
+                .all(|(dx, dy)| {
+                    let child = (x * 2 + dx, y * 2 + dy, z + 1);
+                    self.tiles.contains_key(&child) || self.is_covered(child, max_zoom)
+                })
+    }
+
+    /// The roads of the most detailed tiles that have been loaded
+    fn get_detailed_roads(&self) -> Vec<Arc<Vec<Road>>> {
+        let max_zoom = self.tiles.keys().map(|(_, _, z)| *z).max().unwrap_or(0);
+        self.tiles
+            .iter()
+            .filter(|(tile, _)| !self.is_covered(**tile, max_zoom))
+            .map(|(_, roads)| roads.clone())
+            .collect()
+    }
+
+    /// Starts building the graph when tiles have changed, and receives it when the task is done.
+    /// Returns whether a new graph is available.
+    pub fn update_graph(&mut self) -> bool {
+        if let Some(task) = &mut self.task
+            && let Some(graph) = block_on(future::poll_once(task))
+        {
+            self.graph = Some(Arc::new(graph));
+            self.task = None;
+            return true;
+        }
+        if self.stale && self.task.is_none() {
+            let roads = self.get_detailed_roads();
+            self.task =
+                Some(AsyncComputeTaskPool::get().spawn(async move {
+                    RoadGraph::new(roads.iter().flat_map(|roads| roads.iter()))
+                }));
+            self.stale = false;
+        }
+        false
+    }
+
+    /// The graph of the most detailed roads, as it was last built
+    pub fn get_graph(&self) -> Option<&RoadGraph> {
+        self.graph.as_deref()
+    }
+
+    /// Finds the fastest route between two (lat, lon) coordinates in degrees
+    pub fn find_route(&self, from: Vec2, to: Vec2) -> Result<Route, RoutingError> {
+        self.get_graph()
+            .ok_or(RoutingError::NoRoads)?
+            .find_route(from.as_dvec2(), to.as_dvec2())
+    }
+}
+
+/// Requests a route between two (lat, lon) coordinates in degrees. Once the roads are loaded
+/// the [`Route`] is inserted and drawn on the terrain as a [`GeoOverlayLayer`].
+#[derive(Component, Debug, Clone, PartialEq)]
+#[require(Transform, Visibility)]
+pub struct RouteRequest {
+    pub from: Vec2,
+    pub to: Vec2,
+    pub style: OverlayStyle,
+}
+
+impl RouteRequest {
+    pub fn new(from: Vec2, to: Vec2) -> Self {
+        Self {
+            from,
+            to,
+            style: OverlayStyle {
+                color: Color::srgb(0.1, 0.5, 1.0),
+                line_width: 8.0,
+                ..default()
+            },
+        }
+    }
+}
+
+/// Removes the roads of despawned chunks from the network
+pub fn remove_unloaded_roads(
+    mut removed: RemovedComponents<Chunk>,
+    mut network: ResMut<RoadNetwork>,
+) {
+    for entity in removed.read() {
+        network.remove_chunk(entity);
+    }
+}
+
+/// Finds the routes of new requests, and of requests without a route when a new graph has been
+/// built
+pub fn update_routes(
+    mut commands: Commands,
+    mut network: ResMut<RoadNetwork>,
+    mut overlays: ResMut<Assets<GeoOverlay>>,
+    requests: Query<(Entity, Ref<RouteRequest>, Has<Route>)>,
+) {
+    // The graph is only built while routes are requested
+    if requests.is_empty() {
+        return;
+    }
+    let graph_updated = network.update_graph();
+    for (entity, request, has_route) in &requests {
+        if !request.is_changed() && (has_route || !graph_updated) {
+            continue;
+        }
+
+        match network.find_route(request.from, request.to) {
+            Ok(route) => {
+                let overlay = overlays.add(route.to_overlay());
+                commands.entity(entity).insert((
+                    GeoOverlayLayer::new(overlay).with_style(request.style.clone()),
+                    route,
+                ));
+            }
+            Err(err) => debug!("No route yet: {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn road(class: RoadClass, oneway: bool, points: &[(f64, f64)]) -> Road {
+        Road {
+            class,
+            oneway,
+            points: points
+                .iter()
+                .map(|&(lat, lon)| DVec2::new(lat, lon))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_clip_segment() {
+        let (start, end) =
+            clip_segment(DVec2::new(-100.0, 50.0), DVec2::new(100.0, 50.0), 4096.0).unwrap();
+        assert_eq!(start, DVec2::new(0.0, 50.0));
+        assert_eq!(end, DVec2::new(100.0, 50.0));
+        assert!(clip_segment(DVec2::new(-100.0, 5.0), DVec2::new(-1.0, 5.0), 4096.0).is_none());
+    }
+
+    #[test]
+    fn test_graph_has_nodes_at_intersections() {
+        // A crossroads, with the east-west road split across two tiles
+        let roads = [
+            road(
+                RoadClass::Primary,
+                false,
+                &[(0.0, 0.0), (0.0, 0.001), (0.0, 0.002)],
+            ),
+            road(RoadClass::Primary, false, &[(0.0, 0.002), (0.0, 0.003)]),
+            road(
+                RoadClass::Minor,
+                false,
+                &[(-0.001, 0.001), (0.0, 0.001), (0.001, 0.001)],
+            ),
+        ];
+        let graph = RoadGraph::new(&roads);
+        // The crossing, the ends of the roads and the tile edge at which both halves meet
+        assert_eq!(graph.nodes.len(), 6);
+        assert_eq!(graph.edges.len(), 5);
+    }
+
+    #[test]
+    fn test_route_prefers_fast_roads_and_respects_oneway() {
+        let roads = [
+            // A short path and a longer detour over a primary road
+            road(RoadClass::Path, false, &[(0.0, 0.0), (0.0, 0.01)]),
+            road(
+                RoadClass::Primary,
+                false,
+                &[(0.0, 0.0), (0.002, 0.0), (0.002, 0.01), (0.0, 0.01)],
+            ),
+            // A oneway shortcut in the other direction
+            road(
+                RoadClass::Motorway,
+                true,
+                &[(0.0, 0.01), (-0.001, 0.005), (0.0, 0.0)],
+            ),
+        ];
+        let graph = RoadGraph::new(&roads);
+
+        let route = graph
+            .find_route(DVec2::new(0.0, 0.0), DVec2::new(0.0, 0.01))
+            .unwrap();
+        assert_eq!(route.points.len(), 4);
+        assert_eq!(route.points[1], DVec2::new(0.002, 0.0));
+        let expected = (0.002 + 0.01 + 0.002) * METERS_PER_DEGREE;
+        assert!((route.length - expected).abs() < 1.0);
+
+        let back = graph
+            .find_route(DVec2::new(0.0, 0.01), DVec2::new(0.0, 0.0))
+            .unwrap();
+        assert_eq!(back.points.len(), 3);
+
+        assert!(matches!(
+            RoadGraph::default().find_route(DVec2::ZERO, DVec2::ONE),
+            Err(RoutingError::NoRoads)
+        ));
+    }
+
+    #[test]
+    fn test_nearest_node() {
+        let roads: Vec<Road> = (0..20)
+            .map(|i| {
+                let lat = 52.0 + i as f64 * 0.003;
+                road(RoadClass::Minor, false, &[(lat, 4.0), (lat, 4.0 + 0.004)])
+            })
+            .collect();
+        let graph = RoadGraph::new(&roads);
+        let linear = |lat_lon: DVec2| {
+            (0..graph.nodes.len()).min_by(|&a, &b| {
+                get_distance(graph.nodes[a], lat_lon)
+                    .total_cmp(&get_distance(graph.nodes[b], lat_lon))
+            })
+        };
+
+        for lat_lon in [
+            DVec2::new(52.01, 4.001),
+            DVec2::new(52.0571, 4.0039),
+            DVec2::new(51.9, 3.9),
+            DVec2::new(53.0, 5.0),
+        ] {
+            assert_eq!(graph.get_nearest_node(lat_lon), linear(lat_lon));
+        }
+    }
+}
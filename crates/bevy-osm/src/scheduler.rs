@@ -0,0 +1,219 @@
+use bevy::{camera::primitives::Frustum, ecs::system::SystemParam, prelude::*};
+use bevy_terrain::quadtree::{QuadTree, QuadTreeConfig, QuadTreeNodeComponent, QuadTreeView};
+
+use crate::{
+    cache::{
+        DownloadProgress, cache_elevation_for_chunk, cache_raster_tile_for_chunk,
+        cache_vector_tile_for_chunk,
+    },
+    chunk::Chunk,
+    config::OSMConfig,
+    layers::{ChunkOverlays, MAX_RASTER_OVERLAYS},
+};
+
+/// The loading state of a chunk, from the moment its quadtree node is spawned until its meshes
+/// have been built.
+#[derive(Component, Debug, Clone)]
+pub enum ChunkLoadState {
+    /// Waiting for a download slot
+    Queued,
+    /// Tiles are being downloaded to the cache
+    Downloading(DownloadProgress),
+    /// Tiles are cached, waiting for the images to decode
+    Parsing,
+    /// The terrain mesh is being built, the state becomes `Loaded` when it is added
+    Building,
+    Loaded,
+    /// Loading failed, the chunk is queued again after a delay, see [`retry_failed_chunks`]
+    Failed,
+}
+
+/// How often loading a chunk has failed, and when it is tried again
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct ChunkRetry {
+    pub attempts: u32,
+    /// Seconds since startup
+    pub retry_at: f32,
+}
+
+impl ChunkLoadState {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            ChunkLoadState::Queued => "queued",
+            ChunkLoadState::Downloading(_) => "downloading",
+            ChunkLoadState::Parsing => "parsing",
+            ChunkLoadState::Building => "building",
+            ChunkLoadState::Loaded => "loaded",
+            ChunkLoadState::Failed => "failed",
+        }
+    }
+}
+
+/// Limits how much chunk loading work is started per frame.
+#[derive(Resource, Debug, Clone)]
+pub struct LoadingBudget {
+    /// Maximum number of chunks that are downloading tiles at the same time
+    pub max_concurrent_downloads: usize,
+    /// Maximum number of chunks that start loading their cached tiles from disk per frame
+    pub max_disk_reads_per_frame: usize,
+    /// Maximum number of chunks for which meshes are built per frame
+    pub max_mesh_builds_per_frame: usize,
+    /// Maximum number of finished vector tiles that add their meshes to the world per frame
+    pub max_gpu_uploads_per_frame: usize,
+    /// Maximum number of chunks for which a terrain mesh is built from the tiles of an ancestor
+    /// per frame, while their own tiles are downloading
+    pub max_fallback_builds_per_frame: usize,
+    /// Delay before a failed chunk is queued again, doubled after every failure (seconds)
+    pub retry_delay: f32,
+    pub max_retry_delay: f32,
+}
+
+impl LoadingBudget {
+    /// The delay before the next attempt after `attempts` failures
+    pub fn get_retry_delay(&self, attempts: u32) -> f32 {
+        let exponent = attempts.saturating_sub(1).min(16) as i32;
+        (self.retry_delay * 2f32.powi(exponent)).min(self.max_retry_delay)
+    }
+}
+
+impl Default for LoadingBudget {
+    fn default() -> Self {
+        Self {
+            max_concurrent_downloads: 6,
+            max_disk_reads_per_frame: 8,
+            max_mesh_builds_per_frame: 2,
+            max_gpu_uploads_per_frame: 4,
+            max_fallback_builds_per_frame: 4,
+            retry_delay: 1.0,
+            max_retry_delay: 60.0,
+        }
+    }
+}
+
+/// Orders chunks by importance, based on their distance to the camera and their LOD.
+#[derive(SystemParam)]
+pub struct ChunkPriority<'w, 's> {
+    camera: Single<'w, 's, (&'static Transform, Option<&'static Frustum>), With<Camera>>,
+    quadtrees: Query<'w, 's, (&'static QuadTreeConfig, &'static Transform), With<QuadTree>>,
+}
+
+impl ChunkPriority<'_, '_> {
+    /// Returns the items sorted by priority, most important first.
+    pub fn sort<'a, T>(
+        &self,
+        items: impl Iterator<Item = (T, &'a QuadTreeNodeComponent)>,
+    ) -> Vec<T> {
+        let mut items = items.collect::<Vec<_>>();
+
+        if let Ok((config, transform)) = self.quadtrees.single() {
+            let (camera, frustum) = *self.camera;
+            let view = QuadTreeView {
+                position: camera.translation - transform.translation,
+                frustum: frustum
+                    .filter(|_| config.frustum_culling)
+                    .map(|frustum| (*frustum, transform.compute_affine())),
+                projection_scale: 1.0,
+            };
+            items.sort_by(|(_, a), (_, b)| {
+                view.get_priority(a.rect, config)
+                    .total_cmp(&view.get_priority(b.rect, config))
+            });
+        }
+
+        items.into_iter().map(|(item, _)| item).collect()
+    }
+}
+
+/// Starts downloading the tiles of the most important queued chunks.
+pub fn schedule_chunk_downloads(
+    mut chunks: Query<(Entity, &Chunk, &QuadTreeNodeComponent, &mut ChunkLoadState)>,
+    priority: ChunkPriority,
+    budget: Res<LoadingBudget>,
+    config: Res<OSMConfig>,
+) {
+    let downloading = chunks
+        .iter()
+        .filter(|(.., state)| {
+            matches!(state, ChunkLoadState::Downloading(progress) if !progress.is_finished())
+        })
+        .count();
+    let slots = budget.max_concurrent_downloads.saturating_sub(downloading);
+    if slots == 0 {
+        return;
+    }
+
+    let queued = priority.sort(
+        chunks
+            .iter()
+            .filter(|(.., state)| matches!(state, ChunkLoadState::Queued))
+            .map(|(entity, _, node, _)| (entity, node)),
+    );
+
+    for entity in queued.into_iter().take(slots) {
+        let (_, chunk, _, mut state) = chunks.get_mut(entity).unwrap();
+        let progress = DownloadProgress::default();
+
+        cache_elevation_for_chunk(chunk, &progress);
+        cache_raster_tile_for_chunk(chunk, &config.raster_tile_source, &progress);
+        // An overlay that fails to download is left out of the terrain material, instead of
+        // failing the whole chunk
+        let overlay_progress = progress.optional();
+        for layer in config.raster_overlays.iter().take(MAX_RASTER_OVERLAYS) {
+            cache_raster_tile_for_chunk(chunk, &layer.source, &overlay_progress);
+        }
+        cache_vector_tile_for_chunk(chunk, &progress);
+
+        *state = ChunkLoadState::Downloading(progress);
+    }
+}
+
+/// Queues failed chunks again with an exponential backoff, so a transient network error does not
+/// leave a permanent hole in the terrain.
+pub fn retry_failed_chunks(
+    mut commands: Commands,
+    mut chunks: Query<(Entity, &mut Chunk, &mut ChunkLoadState, Option<&ChunkRetry>)>,
+    budget: Res<LoadingBudget>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_secs();
+    for (entity, mut chunk, mut state, retry) in &mut chunks {
+        if !matches!(*state, ChunkLoadState::Failed) {
+            continue;
+        }
+        // The chunk failed since the last time this system ran
+        if state.is_changed() {
+            let attempts = retry.map_or(0, |retry| retry.attempts) + 1;
+            let delay = budget.get_retry_delay(attempts);
+            warn!(
+                "Loading chunk {}/{}/{} failed, retrying in {delay:.0} s",
+                chunk.z, chunk.x, chunk.y
+            );
+            commands.entity(entity).insert(ChunkRetry {
+                attempts,
+                retry_at: now + delay,
+            });
+            continue;
+        }
+        if retry.is_some_and(|retry| now >= retry.retry_at) {
+            // Drop the failed images, so they are loaded again instead of reusing the failed
+            // assets
+            chunk.elevation = Handle::default();
+            chunk.raster = Handle::default();
+            commands.entity(entity).remove::<ChunkOverlays>();
+            *state = ChunkLoadState::Queued;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        let budget = LoadingBudget::default();
+        assert_eq!(budget.get_retry_delay(1), budget.retry_delay);
+        assert_eq!(budget.get_retry_delay(3), budget.retry_delay * 4.0);
+        assert_eq!(budget.get_retry_delay(100), budget.max_retry_delay);
+    }
+}
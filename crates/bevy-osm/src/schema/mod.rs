@@ -1,6 +1,6 @@
 use crate::schema::{
-    aeroway::Aeroway, landcover::Landcover, landuse::Landuse, layer::OMTLayer, poi::Poi,
-    transportation::Transportation, water::Water, waterway::Waterway,
+    aeroway::Aeroway, landcover::Landcover, landuse::Landuse, layer::OMTLayer, place::Place,
+    poi::Poi, transportation::Transportation, water::Water, waterway::Waterway,
 };
 use serde::{Deserialize, Serialize};
 
@@ -8,6 +8,7 @@ pub mod aeroway;
 pub mod landcover;
 pub mod landuse;
 pub mod layer;
+pub mod place;
 pub mod poi;
 pub mod transportation;
 pub mod water;
@@ -25,7 +26,7 @@ pub enum LayerClass {
     Landuse(Landuse),
     MountainPeak,
     Park,
-    Place,
+    Place(Place),
     Poi(Poi),
     Transportation(Transportation),
     TransportationName,
@@ -204,7 +205,22 @@ pub fn parse_class(layer: &OMTLayer, class: &str) -> LayerClass {
             "stream" => LayerClass::Waterway(Waterway::Stream),
             _ => LayerClass::Unknown,
         },
-        OMTLayer::Place => LayerClass::Place,
+        OMTLayer::Place => match class {
+            "continent" => LayerClass::Place(Place::Continent),
+            "country" => LayerClass::Place(Place::Country),
+            "state" => LayerClass::Place(Place::State),
+            "province" => LayerClass::Place(Place::Province),
+            "city" => LayerClass::Place(Place::City),
+            "town" => LayerClass::Place(Place::Town),
+            "village" => LayerClass::Place(Place::Village),
+            "hamlet" => LayerClass::Place(Place::Hamlet),
+            "suburb" => LayerClass::Place(Place::Suburb),
+            "quarter" => LayerClass::Place(Place::Quarter),
+            "neighbourhood" => LayerClass::Place(Place::Neighbourhood),
+            "isolated_dwelling" => LayerClass::Place(Place::IsolatedDwelling),
+            "island" => LayerClass::Place(Place::Island),
+            _ => LayerClass::Unknown,
+        },
         OMTLayer::MountainPeak => LayerClass::MountainPeak,
         OMTLayer::TransportationName => LayerClass::TransportationName,
         OMTLayer::Boundary => LayerClass::Boundary,
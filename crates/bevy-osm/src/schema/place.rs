@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Place {
+    Continent,
+    Country,
+    State,
+    Province,
+    City,
+    Town,
+    Village,
+    Hamlet,
+    Suburb,
+    Quarter,
+    Neighbourhood,
+    IsolatedDwelling,
+    Island,
+}
@@ -0,0 +1,314 @@
+//! Persists the settings of the viewer between sessions.
+//!
+//! The [`OSMConfig`], the [`TrafficConfig`], the settings of the minimap and the quadtree overlay
+//! and the vector overlays that were loaded from files are read from [`SETTINGS_KEY`] in the
+//! [`data_storage`] at startup and written back when they change. The camera itself is restored
+//! by `bevy-where-was-i`. [`SettingsOverrides`], usually parsed from the command line, take
+//! precedence over the file and are saved along with the next change.
+
+use std::io;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{OSMConfig, RasterTileSource},
+    location::Location,
+    minimap::Minimap,
+    overlay::{GeoOverlayLayer, OverlayStyle},
+    quadtree_debug::QuadTreeDebug,
+    storage::data_storage,
+    traffic::TrafficConfig,
+};
+
+pub const SETTINGS_KEY: &str = "osm_settings.json";
+/// Minimum time between two writes of the settings file, so dragging a slider does not write it
+/// every frame (seconds)
+const SAVE_INTERVAL: f32 = 1.0;
+
+pub const USAGE: &str = "Options:
+  --location <name>  Amsterdam, London, Monaco or New York
+  --source <name>    A raster tile source, such as osm-default or cesium-google-satellite, or a
+                     tile url with {z}, {x} and {y} placeholders";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct MinimapSettings {
+    pub visible: bool,
+    pub zoom: i8,
+    pub source: RasterTileSource,
+}
+
+impl Default for MinimapSettings {
+    fn default() -> Self {
+        Self::from(&Minimap::default())
+    }
+}
+
+impl From<&Minimap> for MinimapSettings {
+    fn from(minimap: &Minimap) -> Self {
+        Self {
+            visible: minimap.visible,
+            zoom: minimap.zoom,
+            source: minimap.source.clone(),
+        }
+    }
+}
+
+/// A [`GeoOverlayLayer`] that was loaded from a file. Overlays without a file, such as routes,
+/// are not saved.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VectorOverlaySettings {
+    /// The asset path of the GeoJSON, KML or GPX file
+    pub path: String,
+    #[serde(default)]
+    pub style: OverlayStyle,
+}
+
+impl VectorOverlaySettings {
+    pub fn from_layer(layer: &GeoOverlayLayer) -> Option<Self> {
+        Some(Self {
+            path: layer.overlay.path()?.to_string(),
+            style: layer.style.clone(),
+        })
+    }
+}
+
+/// Everything that is saved to the settings file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct OSMSettings {
+    pub config: OSMConfig,
+    pub minimap: MinimapSettings,
+    pub quadtree_debug: QuadTreeDebug,
+    pub traffic: TrafficConfig,
+    pub vector_overlays: Vec<VectorOverlaySettings>,
+}
+
+impl OSMSettings {
+    pub fn new<'a>(
+        config: &OSMConfig,
+        minimap: &Minimap,
+        quadtree_debug: &QuadTreeDebug,
+        traffic: &TrafficConfig,
+        overlays: impl IntoIterator<Item = &'a GeoOverlayLayer>,
+    ) -> Self {
+        Self {
+            config: config.clone(),
+            minimap: MinimapSettings::from(minimap),
+            quadtree_debug: quadtree_debug.clone(),
+            traffic: traffic.clone(),
+            vector_overlays: overlays
+                .into_iter()
+                .filter_map(VectorOverlaySettings::from_layer)
+                .collect(),
+        }
+    }
+
+    /// Applies the settings to the resources, the vector overlays are spawned separately
+    pub fn apply(
+        self,
+        config: &mut OSMConfig,
+        minimap: &mut Minimap,
+        quadtree_debug: &mut QuadTreeDebug,
+        traffic: &mut TrafficConfig,
+    ) {
+        *config = self.config;
+        minimap.visible = self.minimap.visible;
+        minimap.zoom = self.minimap.zoom;
+        minimap.source = self.minimap.source;
+        *quadtree_debug = self.quadtree_debug;
+        *traffic = self.traffic;
+    }
+}
+
+pub fn load_settings() -> io::Result<OSMSettings> {
+    Ok(serde_json::from_slice(&data_storage().read(SETTINGS_KEY)?)?)
+}
+
+pub fn save_settings(settings: &OSMSettings) -> io::Result<()> {
+    data_storage().write(SETTINGS_KEY, &serde_json::to_vec_pretty(settings)?)
+}
+
+/// Settings that take precedence over the settings file
+#[derive(Resource, Debug, Clone, PartialEq, Default)]
+pub struct SettingsOverrides {
+    pub location: Option<Location>,
+    pub raster_tile_source: Option<RasterTileSource>,
+}
+
+impl SettingsOverrides {
+    /// Parses the options in [`USAGE`], without the name of the executable
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut overrides = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for `{arg}`"));
+            match arg.as_str() {
+                "--location" => {
+                    let name = value()?;
+                    overrides.location = Some(
+                        Location::from_name(&name).ok_or(format!("Unknown location `{name}`"))?,
+                    );
+                }
+                "--source" => {
+                    let name = value()?;
+                    overrides.raster_tile_source = Some(
+                        RasterTileSource::from_name(&name)
+                            .ok_or(format!("Unknown raster tile source `{name}`"))?,
+                    );
+                }
+                _ => return Err(format!("Unknown argument `{arg}`")),
+            }
+        }
+        Ok(overrides)
+    }
+
+    pub fn apply(&self, config: &mut OSMConfig) {
+        if let Some(location) = &self.location {
+            config.location = location.clone();
+        }
+        if let Some(source) = &self.raster_tile_source {
+            config.raster_tile_source = source.clone();
+        }
+    }
+}
+
+/// The settings as they were last written to the file
+#[derive(Resource, Debug, Default)]
+pub struct SavedSettings {
+    settings: Option<OSMSettings>,
+    last_save: f32,
+}
+
+/// Loads the settings file, spawns the saved vector overlays and applies the overrides, before the
+/// terrain is spawned
+#[expect(clippy::too_many_arguments)]
+pub fn setup_settings(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut config: ResMut<OSMConfig>,
+    mut minimap: ResMut<Minimap>,
+    mut quadtree_debug: ResMut<QuadTreeDebug>,
+    mut traffic: ResMut<TrafficConfig>,
+    mut saved: ResMut<SavedSettings>,
+    overrides: Res<SettingsOverrides>,
+) {
+    let mut vector_overlays = Vec::new();
+    if data_storage().contains(SETTINGS_KEY) {
+        match load_settings() {
+            Ok(settings) => {
+                info!("Loaded settings from `{SETTINGS_KEY}`");
+                for overlay in &settings.vector_overlays {
+                    commands.spawn(
+                        GeoOverlayLayer::new(asset_server.load(&overlay.path))
+                            .with_style(overlay.style.clone()),
+                    );
+                }
+                vector_overlays = settings.vector_overlays.clone();
+                settings.apply(&mut config, &mut minimap, &mut quadtree_debug, &mut traffic);
+            }
+            Err(err) => error!("Could not load settings: {err}"),
+        }
+    }
+    overrides.apply(&mut config);
+    saved.settings = Some(OSMSettings {
+        vector_overlays,
+        ..OSMSettings::new(&config, &minimap, &quadtree_debug, &traffic, [])
+    });
+}
+
+/// Writes the settings file when the settings differ from what was last written
+pub fn update_settings(
+    time: Res<Time>,
+    config: Res<OSMConfig>,
+    minimap: Res<Minimap>,
+    quadtree_debug: Res<QuadTreeDebug>,
+    traffic: Res<TrafficConfig>,
+    overlays: Query<&GeoOverlayLayer>,
+    mut saved: ResMut<SavedSettings>,
+) {
+    if time.elapsed_secs() - saved.last_save < SAVE_INTERVAL {
+        return;
+    }
+    let settings = OSMSettings::new(&config, &minimap, &quadtree_debug, &traffic, &overlays);
+    if saved.settings.as_ref() == Some(&settings) {
+        return;
+    }
+    if let Err(err) = save_settings(&settings) {
+        error!("Could not save settings: {err}");
+    }
+    // Also after an error, so it is not retried every frame
+    saved.settings = Some(settings);
+    saved.last_save = time.elapsed_secs();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layers::RasterLayer;
+
+    use super::*;
+
+    #[test]
+    fn test_settings_round_trip() {
+        let mut settings = OSMSettings::default();
+        settings.config.location = Location::Monaco;
+        settings
+            .config
+            .raster_overlays
+            .push(RasterLayer::new(RasterTileSource::Custom {
+                name: "hiking".into(),
+                url: "https://tiles.example.com/{z}/{x}/{y}.png".into(),
+            }));
+        settings.config.terrain_analysis.contours = true;
+        settings.minimap.zoom = 9;
+        settings.quadtree_debug.enabled = true;
+        settings.traffic.max_vehicles = 500;
+        settings.vector_overlays.push(VectorOverlaySettings {
+            path: "overlays/route.gpx".into(),
+            style: OverlayStyle {
+                line_width: 10.0,
+                ..default()
+            },
+        });
+
+        let json = serde_json::to_string(&settings).unwrap();
+        assert_eq!(
+            serde_json::from_str::<OSMSettings>(&json).unwrap(),
+            settings
+        );
+
+        // Settings that are missing from the file keep their default
+        let partial: OSMSettings =
+            serde_json::from_str(r#"{"config": {"location": "London"}}"#).unwrap();
+        assert_eq!(partial.config.location, Location::London);
+        assert_eq!(
+            partial.config.raster_tile_source,
+            OSMConfig::default().raster_tile_source
+        );
+        assert_eq!(partial.minimap, MinimapSettings::default());
+        assert_eq!(partial.traffic, TrafficConfig::default());
+        assert!(partial.vector_overlays.is_empty());
+    }
+
+    #[test]
+    fn test_overrides_from_args() {
+        let args = ["--location", "new-york", "--source", "osm-default"].map(String::from);
+        let overrides = SettingsOverrides::from_args(args).unwrap();
+        assert_eq!(overrides.location, Some(Location::NewYork));
+        assert_eq!(
+            overrides.raster_tile_source,
+            Some(RasterTileSource::OSMDefault)
+        );
+
+        let url = "https://tiles.example.com/{z}/{x}/{y}.webp";
+        let overrides = SettingsOverrides::from_args(["--source".into(), url.into()]).unwrap();
+        assert!(matches!(
+            overrides.raster_tile_source,
+            Some(RasterTileSource::Custom { .. })
+        ));
+
+        assert!(SettingsOverrides::from_args(["--location".into(), "Atlantis".into()]).is_err());
+        assert!(SettingsOverrides::from_args(["--source".into()]).is_err());
+    }
+}
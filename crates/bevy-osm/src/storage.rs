@@ -0,0 +1,203 @@
+//! Storage of downloaded tiles that works both natively and in the browser.
+//!
+//! Tiles are stored under keys such as `elevation/12/2101/1346.webp`. Natively they are files
+//! in [`CACHE_DIRECTORY`], on `wasm32` they are kept in memory because there is no filesystem.
+//! The asset server reads them through the `tiles://` asset source of [`TileStoragePlugin`].
+//!
+//! Files that the user creates, such as recorded sessions, are kept in a separate
+//! [`data_storage`], which is [`DATA_DIRECTORY`] on native platforms.
+
+use std::{
+    collections::HashMap,
+    io,
+    path::Path,
+    sync::{OnceLock, RwLock},
+};
+
+use bevy::{
+    asset::io::{AssetReader, AssetReaderError, AssetSourceBuilder, PathStream, Reader, VecReader},
+    prelude::*,
+};
+
+/// Name of the asset source that reads from the tile storage
+pub const TILE_ASSET_SOURCE: &str = "tiles";
+/// Directory of the [`FileStorage`] that is used by default on native platforms
+pub const CACHE_DIRECTORY: &str = "assets/cache";
+/// Directory of the [`FileStorage`] for files created by the user on native platforms
+pub const DATA_DIRECTORY: &str = "assets/data";
+
+/// A key-value store for tiles, session tokens and files created by the user
+pub trait TileStorage: Send + Sync + 'static {
+    fn contains(&self, key: &str) -> bool;
+    fn read(&self, key: &str) -> io::Result<Vec<u8>>;
+    fn write(&self, key: &str, bytes: &[u8]) -> io::Result<()>;
+}
+
+/// Stores tiles as files in a directory
+#[cfg(not(target_arch = "wasm32"))]
+pub struct FileStorage {
+    root: std::path::PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileStorage {
+    pub fn new(root: impl Into<std::path::PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl TileStorage for FileStorage {
+    fn contains(&self, key: &str) -> bool {
+        self.root.join(key).exists()
+    }
+
+    fn read(&self, key: &str) -> io::Result<Vec<u8>> {
+        std::fs::read(self.root.join(key))
+    }
+
+    fn write(&self, key: &str, bytes: &[u8]) -> io::Result<()> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, bytes)
+    }
+}
+
+/// Keeps tiles in memory for the lifetime of the application
+#[derive(Default)]
+pub struct MemoryStorage {
+    tiles: RwLock<HashMap<String, Vec<u8>>>,
+}
+
+impl TileStorage for MemoryStorage {
+    fn contains(&self, key: &str) -> bool {
+        self.tiles.read().unwrap().contains_key(key)
+    }
+
+    fn read(&self, key: &str) -> io::Result<Vec<u8>> {
+        self.tiles
+            .read()
+            .unwrap()
+            .get(key)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, key.to_string()))
+    }
+
+    fn write(&self, key: &str, bytes: &[u8]) -> io::Result<()> {
+        self.tiles
+            .write()
+            .unwrap()
+            .insert(key.to_string(), bytes.to_vec());
+        Ok(())
+    }
+}
+
+static TILE_STORAGE: OnceLock<Box<dyn TileStorage>> = OnceLock::new();
+static DATA_STORAGE: OnceLock<Box<dyn TileStorage>> = OnceLock::new();
+
+#[cfg(not(target_arch = "wasm32"))]
+fn get_default_storage() -> Box<dyn TileStorage> {
+    Box::new(FileStorage::new(CACHE_DIRECTORY))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn get_default_storage() -> Box<dyn TileStorage> {
+    Box::new(MemoryStorage::default())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn get_default_data_storage() -> Box<dyn TileStorage> {
+    Box::new(FileStorage::new(DATA_DIRECTORY))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn get_default_data_storage() -> Box<dyn TileStorage> {
+    Box::new(MemoryStorage::default())
+}
+
+/// The storage that tiles are downloaded to
+pub fn tile_storage() -> &'static dyn TileStorage {
+    TILE_STORAGE.get_or_init(get_default_storage).as_ref()
+}
+
+/// Replaces the default storage. This has to happen before the first tile is requested, returns
+/// `false` if the storage was already in use.
+pub fn set_tile_storage(storage: impl TileStorage) -> bool {
+    TILE_STORAGE.set(Box::new(storage)).is_ok()
+}
+
+/// The storage of files created by the user, such as sessions, annotations and settings
+pub fn data_storage() -> &'static dyn TileStorage {
+    DATA_STORAGE.get_or_init(get_default_data_storage).as_ref()
+}
+
+/// Replaces the default data storage, returns `false` if the storage was already in use.
+pub fn set_data_storage(storage: impl TileStorage) -> bool {
+    DATA_STORAGE.set(Box::new(storage)).is_ok()
+}
+
+/// Reads assets from the [`tile_storage`]
+pub struct TileStorageReader;
+
+impl AssetReader for TileStorageReader {
+    async fn read<'a>(&'a self, path: &'a Path) -> Result<impl Reader + 'a, AssetReaderError> {
+        let key = path.to_string_lossy();
+        match tile_storage().read(&key) {
+            Ok(bytes) => Ok(VecReader::new(bytes)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Err(AssetReaderError::NotFound(path.to_path_buf()))
+            }
+            Err(err) => Err(AssetReaderError::Io(err.into())),
+        }
+    }
+
+    async fn read_meta<'a>(&'a self, path: &'a Path) -> Result<impl Reader + 'a, AssetReaderError> {
+        // Tiles have no meta files, so the default loader settings are used.
+        Err::<VecReader, _>(AssetReaderError::NotFound(path.to_path_buf()))
+    }
+
+    async fn read_directory<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> Result<Box<PathStream>, AssetReaderError> {
+        Err(AssetReaderError::NotFound(path.to_path_buf()))
+    }
+
+    async fn is_directory<'a>(&'a self, _path: &'a Path) -> Result<bool, AssetReaderError> {
+        Ok(false)
+    }
+}
+
+/// Registers the `tiles://` asset source. Asset sources can only be registered before the
+/// `AssetPlugin`, so this has to be added before `DefaultPlugins`.
+pub struct TileStoragePlugin;
+
+impl Plugin for TileStoragePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_asset_source(
+            TILE_ASSET_SOURCE,
+            AssetSourceBuilder::new(|| Box::new(TileStorageReader)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_storage() {
+        let storage = MemoryStorage::default();
+        assert!(!storage.contains("elevation/9/1/2.webp"));
+        assert_eq!(
+            storage.read("elevation/9/1/2.webp").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        storage.write("elevation/9/1/2.webp", &[1, 2, 3]).unwrap();
+        assert!(storage.contains("elevation/9/1/2.webp"));
+        assert_eq!(storage.read("elevation/9/1/2.webp").unwrap(), [1, 2, 3]);
+    }
+}
@@ -3,16 +3,34 @@ use std::collections::HashMap;
 use bevy::{color::Color, log::warn};
 
 use crate::{
+    airport::{AerowayInstruction, AerowayKind},
+    boundary::BoundaryInstruction,
+    labels::PlaceInstruction,
     mesh::{BuildingInstruction, Layer},
     osm_types::BuildingClass,
     schema::{
         LayerClass, aeroway::Aeroway, landcover::Landcover, layer::OMTLayer, parse_class,
-        transportation::Transportation, waterway::Waterway,
+        transportation::Transportation, water::Water, waterway::Waterway,
     },
     tag::Tag,
 };
 
-use super::mesh::{BuildInstruction, FillInstruction, StrokeInstruction};
+use super::mesh::{BuildInstruction, FillInstruction, StrokeInstruction, WaterInstruction};
+
+/// Decides how the features of a vector tile are rendered
+pub trait Theme {
+    fn get_build_instruction(&self, tags: Vec<Tag>, layer_name: OMTLayer) -> BuildInstruction;
+}
+
+/// The default theme for OpenFreeMap vector tiles
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenFreeMapTheme;
+
+impl Theme for OpenFreeMapTheme {
+    fn get_build_instruction(&self, tags: Vec<Tag>, layer_name: OMTLayer) -> BuildInstruction {
+        get_way_build_instruction_openfreemap(tags, layer_name)
+    }
+}
 
 pub fn get_way_build_instruction_openfreemap(
     tags: Vec<Tag>,
@@ -44,10 +62,9 @@ pub fn get_way_build_instruction_openfreemap(
                     layer: Layer::Foreground,
                 });
             }
-            LayerClass::Water(_) => {
-                return BuildInstruction::Fill(FillInstruction {
-                    color: Color::linear_rgb(0.0, 0.2, 0.9),
-                    layer: Layer::Foreground,
+            LayerClass::Water(water) => {
+                return BuildInstruction::Water(WaterInstruction {
+                    level: !matches!(water, Water::River),
                 });
             }
             LayerClass::Landcover(Landcover::Ice) => {
@@ -151,15 +168,21 @@ pub fn get_way_build_instruction_openfreemap(
                 });
             }
             LayerClass::Aeroway(Aeroway::Runway) => {
-                return BuildInstruction::Fill(FillInstruction {
-                    color: Color::BLACK,
-                    layer: Layer::OnTop,
+                return BuildInstruction::Aeroway(AerowayInstruction {
+                    kind: AerowayKind::Runway,
                 });
             }
-            LayerClass::Aeroway(
-                Aeroway::Taxiway | Aeroway::Apron | Aeroway::Helipad | Aeroway::Gate,
-            )
-            | LayerClass::Transportation(Transportation::Aerialway) => {
+            LayerClass::Aeroway(Aeroway::Taxiway) => {
+                return BuildInstruction::Aeroway(AerowayInstruction {
+                    kind: AerowayKind::Taxiway,
+                });
+            }
+            LayerClass::Aeroway(Aeroway::Apron | Aeroway::Helipad | Aeroway::Gate) => {
+                return BuildInstruction::Aeroway(AerowayInstruction {
+                    kind: AerowayKind::Apron,
+                });
+            }
+            LayerClass::Transportation(Transportation::Aerialway) => {
                 return BuildInstruction::Fill(FillInstruction {
                     color: Color::linear_rgb(0.3, 0.3, 0.3),
                     layer: Layer::Foreground,
@@ -171,11 +194,11 @@ pub fn get_way_build_instruction_openfreemap(
                     layer: Layer::Background,
                 });
             }
-            LayerClass::Place => {
-                return BuildInstruction::Fill(FillInstruction {
-                    color: Color::linear_rgb(1.0, 0.2, 0.2),
-                    layer: Layer::Foreground,
-                });
+            LayerClass::Place(class) => {
+                return BuildInstruction::Place(PlaceInstruction { class });
+            }
+            LayerClass::Boundary => {
+                return get_boundary_build_instruction(&tags);
             }
             LayerClass::Landuse(_) => {
                 return BuildInstruction::Fill(FillInstruction {
@@ -186,7 +209,6 @@ pub fn get_way_build_instruction_openfreemap(
             LayerClass::MountainPeak
             | LayerClass::TransportationName
             | LayerClass::WaterName
-            | LayerClass::Boundary
             | LayerClass::Building
             | LayerClass::AerodromeLabel
             | LayerClass::Housenumber
@@ -216,6 +238,12 @@ pub fn get_way_build_instruction_openfreemap(
                     .and_then(|x| x.parse::<i32>().ok().map(|x| x.max(5) as f32)),
             })
         }
+        OMTLayer::Boundary => get_boundary_build_instruction(&tags),
         _ => BuildInstruction::None,
     }
 }
+
+/// Boundaries have no `class`, they are styled by their `admin_level`
+fn get_boundary_build_instruction(tags: &[Tag]) -> BuildInstruction {
+    BoundaryInstruction::from_tags(tags).map_or(BuildInstruction::None, BuildInstruction::Boundary)
+}
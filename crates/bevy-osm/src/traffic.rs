@@ -0,0 +1,641 @@
+//! Ambient traffic on the roads of the vector tiles.
+//!
+//! Every chunk from [`MIN_TRAFFIC_ZOOM`] on gets a [`TrafficNetwork`] of lanes, built from the
+//! `transportation` layer. Vehicles drive on the right, follow the direction of oneway roads and
+//! wait for each other at intersections. At the edge of a chunk they continue on the lane of the
+//! neighbouring chunk, and when their chunk is despawned they move on to the chunks that replaced
+//! it. They share a mesh and a few materials, so they are drawn instanced.
+
+use std::collections::HashMap;
+
+use bevy::{math::DVec2, prelude::*};
+use bevy_terrain::mesh::HeightMap;
+use rand::RngExt;
+use serde::{Deserialize, Serialize};
+
+use crate::routing::{Road, RoadClass, RoadGraph};
+
+/// Chunks of lower zoom levels are too large to see individual vehicles
+pub const MIN_TRAFFIC_ZOOM: i8 = 14;
+/// Extent of the coordinates in a vector tile
+const TILE_EXTENT: f32 = 4096.0;
+/// Width of a lane, two-way roads have one in each direction (meters)
+const LANE_WIDTH: f32 = 3.5;
+/// Distance from the end of a lane at which vehicles ask for the right of way at an intersection
+/// (meters)
+const YIELD_DISTANCE: f32 = 12.0;
+/// Distance into the next lane after which a vehicle has cleared the intersection (meters)
+const CLEARANCE_DISTANCE: f32 = 8.0;
+/// Distance that is kept to the vehicle ahead (meters)
+const VEHICLE_GAP: f32 = 9.0;
+/// Maximum distance between the end of a lane on the edge of a chunk and the start of the lane
+/// of the neighbouring chunk it continues on (meters)
+const STITCH_DISTANCE: f32 = 3.0;
+/// Maximum distance from a vehicle of a despawned chunk to the lane it moves to (meters)
+const HANDOVER_DISTANCE: f32 = 10.0;
+/// (m/s²)
+const ACCELERATION: f32 = 3.0;
+const DECELERATION: f32 = 8.0;
+/// Vehicles drive slower than the speeds the routes are planned with, which are for empty roads
+const SPEED_FACTOR: f32 = 0.7;
+const VEHICLE_COLORS: [Color; 6] = [
+    Color::srgb(0.9, 0.9, 0.9),
+    Color::srgb(0.05, 0.05, 0.05),
+    Color::srgb(0.5, 0.5, 0.55),
+    Color::srgb(0.6, 0.05, 0.05),
+    Color::srgb(0.05, 0.15, 0.5),
+    Color::srgb(0.8, 0.6, 0.1),
+];
+
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct TrafficConfig {
+    pub enabled: bool,
+    /// Vehicles per kilometer of lane, road classes without an entry have no traffic
+    pub density: HashMap<RoadClass, f32>,
+    /// Upper bound on the vehicles of all chunks together
+    pub max_vehicles: usize,
+}
+
+impl Default for TrafficConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            density: HashMap::from([
+                (RoadClass::Motorway, 12.0),
+                (RoadClass::Trunk, 10.0),
+                (RoadClass::Primary, 10.0),
+                (RoadClass::Secondary, 8.0),
+                (RoadClass::Tertiary, 6.0),
+                (RoadClass::Minor, 3.0),
+                (RoadClass::Service, 1.0),
+            ]),
+            max_vehicles: 2000,
+        }
+    }
+}
+
+/// A lane in the local space of its chunk
+#[derive(Debug, Clone)]
+pub struct Lane {
+    pub class: RoadClass,
+    pub points: Vec<Vec3>,
+    /// The distance along the lane of each point (meters)
+    pub distances: Vec<f32>,
+    /// The junctions at the start and end of the lane
+    pub start: usize,
+    pub end: usize,
+    /// The lane of the same road in the opposite direction
+    pub reverse: Option<usize>,
+}
+
+impl Lane {
+    pub fn get_length(&self) -> f32 {
+        self.distances.last().copied().unwrap_or(0.0)
+    }
+
+    /// Returns the position and direction at a distance along the lane
+    pub fn sample(&self, distance: f32) -> (Vec3, Vec3) {
+        let segment = self
+            .distances
+            .partition_point(|&d| d <= distance)
+            .clamp(1, self.points.len() - 1);
+        let (start, end) = (self.points[segment - 1], self.points[segment]);
+        let (d0, d1) = (self.distances[segment - 1], self.distances[segment]);
+        let t = ((distance - d0) / (d1 - d0).max(f32::EPSILON)).clamp(0.0, 1.0);
+        (start.lerp(end, t), end - start)
+    }
+}
+
+/// A place where lanes meet
+#[derive(Debug, Clone, Default)]
+pub struct Junction {
+    /// The lanes that start here
+    pub outgoing: Vec<usize>,
+    /// The number of roads that meet here, vehicles yield at intersections of three or more
+    pub roads: usize,
+    /// Whether the junction lies on the edge of the chunk, where the roads are clipped
+    pub on_edge: bool,
+}
+
+/// The lanes of a chunk
+#[derive(Component, Debug, Clone, Default)]
+pub struct TrafficNetwork {
+    pub lanes: Vec<Lane>,
+    pub junctions: Vec<Junction>,
+    /// The lanes that start on the edge of the chunk, where vehicles come in from the
+    /// neighbouring chunks
+    pub edge_lanes: Vec<usize>,
+}
+
+/// Offsets a polyline to the right (meters)
+fn offset_line(points: &[Vec2], offset: f32) -> Vec<Vec2> {
+    (0..points.len())
+        .map(|i| {
+            let before = points[i.saturating_sub(1)];
+            let after = points[(i + 1).min(points.len() - 1)];
+            let right = (after - before).normalize_or_zero().perp();
+            points[i] + right * offset
+        })
+        .collect()
+}
+
+impl TrafficNetwork {
+    /// Builds the lanes of roads from [`crate::routing::get_tile_roads`], where `size` is the
+    /// size of the chunk in meters
+    pub fn new(roads: &[Road], heights: &HeightMap, size: Vec2) -> Self {
+        let scale = size.as_dvec2() / TILE_EXTENT as f64;
+        let graph = RoadGraph::with_metric(roads, 0.5, |a, b| ((b - a) * scale).length());
+
+        let extent = TILE_EXTENT as f64;
+        let mut network = TrafficNetwork {
+            lanes: Vec::new(),
+            junctions: graph
+                .nodes
+                .iter()
+                .map(|node| Junction {
+                    on_edge: node.min_element() <= 0.5 || node.max_element() >= extent - 0.5,
+                    ..default()
+                })
+                .collect(),
+            edge_lanes: Vec::new(),
+        };
+        // Points in meters relative to the chunk center
+        let to_meters = |point: DVec2| (point.as_vec2() / TILE_EXTENT - 0.5) * size;
+
+        for edge in &graph.edges {
+            let points: Vec<Vec2> = edge.points.iter().map(|&p| to_meters(p)).collect();
+            network.junctions[edge.from].roads += 1;
+            network.junctions[edge.to].roads += 1;
+
+            if edge.oneway {
+                network.add_lane(edge.class, &points, (edge.from, edge.to), heights, size);
+                continue;
+            }
+            let reversed: Vec<Vec2> = points.iter().rev().copied().collect();
+            let forward = network.add_lane(
+                edge.class,
+                &offset_line(&points, LANE_WIDTH / 2.0),
+                (edge.from, edge.to),
+                heights,
+                size,
+            );
+            let backward = network.add_lane(
+                edge.class,
+                &offset_line(&reversed, LANE_WIDTH / 2.0),
+                (edge.to, edge.from),
+                heights,
+                size,
+            );
+            network.lanes[forward].reverse = Some(backward);
+            network.lanes[backward].reverse = Some(forward);
+        }
+        network.edge_lanes = (0..network.lanes.len())
+            .filter(|&lane| network.junctions[network.lanes[lane].start].on_edge)
+            .collect();
+        network
+    }
+
+    fn add_lane(
+        &mut self,
+        class: RoadClass,
+        points: &[Vec2],
+        (start, end): (usize, usize),
+        heights: &HeightMap,
+        size: Vec2,
+    ) -> usize {
+        let mut distance = 0.0;
+        let distances = points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| {
+                if i > 0 {
+                    distance += point.distance(points[i - 1]);
+                }
+                distance
+            })
+            .collect();
+        let points = points
+            .iter()
+            .map(|point| {
+                let local = *point / size;
+                Vec3::new(local.x, heights.sample(local), local.y)
+            })
+            .collect();
+
+        let lane = self.lanes.len();
+        self.lanes.push(Lane {
+            class,
+            points,
+            distances,
+            start,
+            end,
+            reverse: None,
+        });
+        self.junctions[start].outgoing.push(lane);
+        lane
+    }
+
+    /// Picks the lane a vehicle continues on at the end of `lane`, turning around at dead ends.
+    /// On the edge of the chunk, [`find_connecting_lane`] is tried first.
+    fn get_next_lane(&self, lane: usize, rng: &mut impl RngExt) -> Option<usize> {
+        let current = &self.lanes[lane];
+        let options: Vec<usize> = self.junctions[current.end]
+            .outgoing
+            .iter()
+            .copied()
+            .filter(|&next| Some(next) != current.reverse)
+            .collect();
+        if options.is_empty() {
+            return current.reverse;
+        }
+        Some(options[rng.random_range(0..options.len())])
+    }
+}
+
+#[derive(Component, Debug, Clone)]
+pub struct TrafficVehicle {
+    /// The chunk with the [`TrafficNetwork`] the vehicle drives on
+    pub chunk: Entity,
+    pub lane: usize,
+    /// Distance along the lane (meters)
+    pub distance: f32,
+    /// (m/s)
+    pub speed: f32,
+    /// The junction the vehicle has the right of way on
+    pub junction: Option<usize>,
+}
+
+/// The shared mesh and materials of the vehicles
+#[derive(Resource)]
+pub struct TrafficAssets {
+    pub mesh: Handle<Mesh>,
+    pub materials: Vec<Handle<StandardMaterial>>,
+}
+
+impl FromWorld for TrafficAssets {
+    fn from_world(world: &mut World) -> Self {
+        let mesh = world.resource_mut::<Assets<Mesh>>().add(
+            Cuboid::new(1.8, 1.5, 4.5)
+                .mesh()
+                .build()
+                .translated_by(Vec3::Y * 0.75),
+        );
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        let materials = VEHICLE_COLORS
+            .into_iter()
+            .map(|color| {
+                materials.add(StandardMaterial {
+                    base_color: color,
+                    perceptual_roughness: 0.3,
+                    ..default()
+                })
+            })
+            .collect();
+        Self { mesh, materials }
+    }
+}
+
+type NetworkQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static TrafficNetwork,
+        &'static GlobalTransform,
+        &'static InheritedVisibility,
+    ),
+>;
+
+/// Finds the lane of a neighbouring chunk that starts where a lane of `chunk` ends on its edge.
+/// `end` and `direction` are the end of that lane in world space.
+fn find_connecting_lane(
+    networks: &NetworkQuery,
+    chunk: Entity,
+    end: Vec3,
+    direction: Vec3,
+) -> Option<(Entity, usize)> {
+    let mut closest: Option<(Entity, usize, f32)> = None;
+    for (entity, network, transform, _) in networks {
+        if entity == chunk {
+            continue;
+        }
+        for &index in &network.edge_lanes {
+            let (start, start_direction) = network.lanes[index].sample(0.0);
+            let world_start = transform.transform_point(start);
+            let start_direction = transform.transform_point(start + start_direction) - world_start;
+            let distance = world_start.xz().distance(end.xz());
+            if distance < STITCH_DISTANCE
+                && start_direction.xz().dot(direction.xz()) > 0.0
+                && closest.is_none_or(|(.., closest)| distance < closest)
+            {
+                closest = Some((entity, index, distance));
+            }
+        }
+    }
+    closest.map(|(entity, index, _)| (entity, index))
+}
+
+/// Finds the lane closest to a vehicle whose chunk was despawned that goes in about the same
+/// direction, and the distance along it. `position` and `forward` are in world space.
+fn find_lane_at(
+    networks: &NetworkQuery,
+    position: Vec3,
+    forward: Vec3,
+) -> Option<(Entity, usize, f32)> {
+    let position = position.xz();
+    let mut closest: Option<((Entity, usize, f32), f32)> = None;
+    for (entity, network, transform, _) in networks {
+        for (index, lane) in network.lanes.iter().enumerate() {
+            for segment in 1..lane.points.len() {
+                let start = transform.transform_point(lane.points[segment - 1]).xz();
+                let end = transform.transform_point(lane.points[segment]).xz();
+                let direction = end - start;
+                if direction.dot(forward.xz()) <= 0.0 {
+                    continue;
+                }
+                let t = ((position - start).dot(direction)
+                    / direction.length_squared().max(f32::EPSILON))
+                .clamp(0.0, 1.0);
+                let distance = position.distance(start + direction * t);
+                if distance < HANDOVER_DISTANCE
+                    && closest.is_none_or(|(_, closest)| distance < closest)
+                {
+                    let (d0, d1) = (lane.distances[segment - 1], lane.distances[segment]);
+                    closest = Some(((entity, index, d0 + (d1 - d0) * t), distance));
+                }
+            }
+        }
+    }
+    closest.map(|(lane, _)| lane)
+}
+
+/// Spawns vehicles on new traffic networks, or on all of them when the config changes
+pub fn spawn_traffic(
+    mut commands: Commands,
+    config: Res<TrafficConfig>,
+    assets: Res<TrafficAssets>,
+    networks: Query<(Entity, Ref<TrafficNetwork>)>,
+    vehicles: Query<Entity, With<TrafficVehicle>>,
+) {
+    let mut count = vehicles.iter().count();
+    if config.is_changed() {
+        for vehicle in &vehicles {
+            commands.entity(vehicle).despawn();
+        }
+        count = 0;
+    }
+    if !config.enabled {
+        return;
+    }
+
+    let mut rng = rand::rng();
+    for (chunk, network) in &networks {
+        if !config.is_changed() && !network.is_added() {
+            continue;
+        }
+        for (index, lane) in network.lanes.iter().enumerate() {
+            let density = config.density.get(&lane.class).copied().unwrap_or(0.0);
+            let expected = density * lane.get_length() / 1000.0;
+            // Rounded randomly, so short lanes get vehicles as well
+            let lane_count = expected.floor() as usize
+                + usize::from(rng.random_range(0.0..1.0) < expected.fract());
+
+            for _ in 0..lane_count {
+                if count >= config.max_vehicles {
+                    return;
+                }
+                count += 1;
+                let material = &assets.materials[rng.random_range(0..assets.materials.len())];
+                commands.spawn((
+                    TrafficVehicle {
+                        chunk,
+                        lane: index,
+                        distance: rng.random_range(0.0..=lane.get_length()),
+                        speed: 0.0,
+                        junction: None,
+                    },
+                    Mesh3d(assets.mesh.clone()),
+                    MeshMaterial3d(material.clone()),
+                    Transform::default(),
+                    Visibility::Hidden,
+                ));
+            }
+        }
+    }
+}
+
+/// Moves the vehicles along their lanes, from chunk to chunk, and those of chunks that were
+/// despawned on to the lanes of the chunks that replaced them
+pub fn update_traffic(
+    mut commands: Commands,
+    time: Res<Time>,
+    networks: NetworkQuery,
+    mut vehicles: Query<(Entity, &mut TrafficVehicle, &mut Transform, &mut Visibility)>,
+    // The vehicle that has the right of way on each (chunk, junction)
+    mut reservations: Local<HashMap<(Entity, usize), Entity>>,
+) {
+    let dt = time.delta_secs();
+    let mut rng = rand::rng();
+
+    // The distances of the vehicles on each lane, to keep a gap to the one ahead
+    let mut lanes: HashMap<(Entity, usize), Vec<f32>> = HashMap::new();
+    for (_, vehicle, _, _) in &vehicles {
+        lanes
+            .entry((vehicle.chunk, vehicle.lane))
+            .or_default()
+            .push(vehicle.distance);
+    }
+    reservations.retain(|&(_, junction), holder| {
+        vehicles
+            .get(*holder)
+            .is_ok_and(|(_, vehicle, _, _)| vehicle.junction == Some(junction))
+    });
+
+    for (entity, mut vehicle, mut transform, mut visibility) in &mut vehicles {
+        if !networks.contains(vehicle.chunk) {
+            let Some((chunk, lane, distance)) =
+                find_lane_at(&networks, transform.translation, *transform.forward())
+            else {
+                commands.entity(entity).despawn();
+                continue;
+            };
+            // The reservations of the despawned chunk are gone with it
+            vehicle.junction = None;
+            vehicle.chunk = chunk;
+            vehicle.lane = lane;
+            vehicle.distance = distance;
+        }
+        let Ok((_, network, chunk_transform, _)) = networks.get(vehicle.chunk) else {
+            continue;
+        };
+        let Some(lane) = network.lanes.get(vehicle.lane) else {
+            commands.entity(entity).despawn();
+            continue;
+        };
+        let length = lane.get_length();
+        let remaining = length - vehicle.distance;
+
+        let mut target_speed = lane.class.get_speed() as f32 * SPEED_FACTOR;
+        let braking_distance = vehicle.speed.powi(2) / (2.0 * DECELERATION) + 1.0;
+        // The furthest the vehicle may drive
+        let mut stop_at = f32::INFINITY;
+        if let Some(ahead) = lanes
+            .get(&(vehicle.chunk, vehicle.lane))
+            .into_iter()
+            .flatten()
+            .filter(|&&distance| distance > vehicle.distance)
+            .min_by(|a, b| a.total_cmp(b))
+        {
+            stop_at = ahead - VEHICLE_GAP;
+        }
+
+        // Wait for the right of way at intersections, first come first served
+        let junction = &network.junctions[lane.end];
+        if junction.roads >= 3
+            && remaining < YIELD_DISTANCE.max(braking_distance)
+            && vehicle.junction != Some(lane.end)
+        {
+            let holder = reservations
+                .entry((vehicle.chunk, lane.end))
+                .or_insert(entity);
+            if *holder == entity {
+                if let Some(previous) = vehicle.junction.replace(lane.end) {
+                    reservations.remove(&(vehicle.chunk, previous));
+                }
+            } else {
+                stop_at = stop_at.min(length - 1.0);
+            }
+        }
+
+        if stop_at - vehicle.distance < braking_distance {
+            target_speed = 0.0;
+        }
+        vehicle.speed = if target_speed > vehicle.speed {
+            (vehicle.speed + ACCELERATION * dt).min(target_speed)
+        } else {
+            (vehicle.speed - DECELERATION * dt).max(target_speed)
+        };
+        vehicle.distance =
+            (vehicle.distance + vehicle.speed * dt).min(stop_at.max(vehicle.distance));
+
+        if vehicle.distance >= length {
+            let connecting = if network.junctions[lane.end].on_edge {
+                let (end, direction) = lane.sample(length);
+                let world_end = chunk_transform.transform_point(end);
+                let direction = chunk_transform.transform_point(end + direction) - world_end;
+                find_connecting_lane(&networks, vehicle.chunk, world_end, direction)
+            } else {
+                None
+            };
+            if let Some((chunk, next)) = connecting {
+                if let Some(junction) = vehicle.junction.take() {
+                    reservations.remove(&(vehicle.chunk, junction));
+                }
+                vehicle.distance -= length;
+                vehicle.chunk = chunk;
+                vehicle.lane = next;
+            } else if let Some(next) = network.get_next_lane(vehicle.lane, &mut rng) {
+                vehicle.distance -= length;
+                vehicle.lane = next;
+            } else {
+                commands.entity(entity).despawn();
+                continue;
+            }
+        }
+        let Ok((_, network, chunk_transform, chunk_visibility)) = networks.get(vehicle.chunk)
+        else {
+            continue;
+        };
+
+        // Give up the right of way once the intersection is cleared
+        if let Some(junction) = vehicle.junction {
+            let lane = &network.lanes[vehicle.lane];
+            if lane.start == junction && vehicle.distance > CLEARANCE_DISTANCE {
+                reservations.remove(&(vehicle.chunk, junction));
+                vehicle.junction = None;
+            }
+        }
+
+        let (position, direction) = network.lanes[vehicle.lane].sample(vehicle.distance);
+        let translation = chunk_transform.transform_point(position);
+        let forward = chunk_transform.transform_point(position + direction) - translation;
+        *transform = Transform::from_translation(translation);
+        if let Ok(forward) = Dir3::new(forward) {
+            transform.look_to(forward, Vec3::Y);
+        }
+        *visibility = if chunk_visibility.get() {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn road(oneway: bool, points: &[(f64, f64)]) -> Road {
+        Road {
+            class: RoadClass::Primary,
+            oneway,
+            points: points.iter().map(|&(x, y)| DVec2::new(x, y)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_lanes_follow_direction() {
+        let heights = HeightMap::new(IVec2::splat(8));
+        // A two-way road to the east with a oneway road going north from its middle
+        let roads = [
+            road(false, &[(0.0, 2048.0), (2048.0, 2048.0), (4096.0, 2048.0)]),
+            road(true, &[(2048.0, 2048.0), (2048.0, 0.0)]),
+        ];
+        let network = TrafficNetwork::new(&roads, &heights, Vec2::splat(4096.0));
+
+        // Two lanes for each half of the two-way road, one for the oneway road
+        assert_eq!(network.lanes.len(), 5);
+        let oneway = network
+            .lanes
+            .iter()
+            .find(|lane| lane.reverse.is_none())
+            .unwrap();
+        assert_eq!(oneway.points[0], Vec3::ZERO);
+        assert_eq!(oneway.get_length(), 2048.0);
+        assert_eq!(network.junctions[oneway.start].roads, 3);
+
+        // Vehicles drive on the right, which is south of a road to the east
+        let east = network
+            .lanes
+            .iter()
+            .find(|lane| lane.reverse.is_some() && lane.points[1].x > lane.points[0].x)
+            .unwrap();
+        assert!(east.points[0].z > 0.0);
+        assert_eq!(east.sample(east.get_length() / 2.0).1.normalize(), Vec3::X);
+    }
+
+    #[test]
+    fn test_edge_lanes() {
+        let heights = HeightMap::new(IVec2::splat(8));
+        // A two-way road across the chunk with a oneway road leaving it to the north
+        let roads = [
+            road(false, &[(0.0, 2048.0), (2048.0, 2048.0), (4096.0, 2048.0)]),
+            road(true, &[(2048.0, 2048.0), (2048.0, 0.0)]),
+        ];
+        let network = TrafficNetwork::new(&roads, &heights, Vec2::splat(4096.0));
+
+        // Vehicles come in from the west and the east, and leave through the north as well
+        assert_eq!(network.edge_lanes.len(), 2);
+        for &lane in &network.edge_lanes {
+            assert!(network.lanes[lane].points[0].x.abs() > 0.49);
+        }
+        let oneway = network
+            .lanes
+            .iter()
+            .find(|lane| lane.reverse.is_none())
+            .unwrap();
+        assert!(!network.junctions[oneway.start].on_edge);
+        assert!(network.junctions[oneway.end].on_edge);
+    }
+}
@@ -13,10 +13,21 @@ use crate::{
     cache::ensure_session_is_valid,
     chunk::{get_root_chunk_for_location, world_to_lat_lon},
     config::{OSMConfig, RasterTileSource},
+    flight_path::CameraFlyTo,
+    geocoding::{Geocoding, PlaceIndex},
+    layers::{AnalysisShading, LayerBlendMode, MAX_RASTER_OVERLAYS, RasterLayer, TerrainAnalysis},
     location::Location,
-    performance::OSMPerformance,
+    performance::{OSMPerformance, SessionRecorder},
 };
 
+const OVERLAY_SOURCES: [RasterTileSource; 5] = [
+    RasterTileSource::Transport,
+    RasterTileSource::OSMDefault,
+    RasterTileSource::CesiumGoogleRoadmaps,
+    RasterTileSource::CesiumGoogleContour,
+    RasterTileSource::CesiumGoogleSatellite,
+];
+
 fn show_chunks_loading_plot(ui: &mut egui::Ui, performance: &OSMPerformance) -> Response {
     Plot::new("Chunks loading")
         .legend(Legend::default())
@@ -72,6 +83,271 @@ fn show_fps_plot(ui: &mut egui::Ui, performance: &OSMPerformance) -> Response {
         .response
 }
 
+fn show_frametime_plot(ui: &mut egui::Ui, performance: &OSMPerformance) -> Response {
+    Plot::new("Frame time")
+        .legend(Legend::default())
+        .default_y_bounds(0.0, 50.0)
+        .show(ui, |plot_ui| {
+            plot_ui.points(
+                Points::new(
+                    "Frame time",
+                    PlotPoints::Owned(
+                        performance
+                            .frametime
+                            .iter()
+                            .enumerate()
+                            .map(|(i, el)| PlotPoint::new(i as f64, *el))
+                            .collect(),
+                    ),
+                )
+                .stems(-1.5)
+                .radius(1.0)
+                .color(performance.get_frametime_plot_color())
+                .name(format!(
+                    "Frame time (ms): {:.1}",
+                    performance.frametime.iter().sum::<f64>()
+                        / (performance.frametime.len() as f64)
+                )),
+            );
+        })
+        .response
+}
+
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..1_000_000 => format!("{:.1} kB", bytes as f64 / 1e3),
+        1_000_000..1_000_000_000 => format!("{:.1} MB", bytes as f64 / 1e6),
+        _ => format!("{:.2} GB", bytes as f64 / 1e9),
+    }
+}
+
+fn show_loading_stats(ui: &mut egui::Ui, performance: &OSMPerformance) {
+    let loading = &performance.loading;
+    let recent_bytes: u64 = performance.download_bytes.iter().sum();
+
+    ui.add(Label::new("downloaded (recent):"));
+    ui.add(Label::new(format!(
+        "{} ({})",
+        format_bytes(loading.download_bytes),
+        format_bytes(recent_bytes)
+    )));
+    ui.end_row();
+    ui.add(Label::new("cache hits / misses:"));
+    ui.add(Label::new(format!(
+        "{} / {} ({:.0}%)",
+        loading.cache_hits,
+        loading.cache_misses,
+        loading.cache_hit_ratio() * 100.0
+    )));
+    ui.end_row();
+    ui.add(Label::new("tile parse / heightmap / mesh build:"));
+    ui.add(Label::new(format!(
+        "{:.2} ms / {:.2} ms / {:.2} ms",
+        loading.average_parse_ms(),
+        loading.average_heightmap_ms(),
+        loading.average_mesh_build_ms()
+    )));
+    ui.end_row();
+    ui.add(Label::new("GPU memory (meshes, images):"));
+    ui.add(Label::new(format!(
+        "{} ({}, {})",
+        format_bytes(performance.gpu_memory.total_bytes() as u64),
+        format_bytes(performance.gpu_memory.mesh_bytes as u64),
+        format_bytes(performance.gpu_memory.image_bytes as u64)
+    )));
+    ui.end_row();
+}
+
+fn recorder_ui(ui: &mut egui::Ui, recorder: &mut SessionRecorder) {
+    ui.add(Label::new("session label:"));
+    ui.text_edit_singleline(&mut recorder.label);
+    ui.end_row();
+
+    ui.add(Label::new(format!(
+        "recorded frames: {}",
+        recorder.samples().len()
+    )));
+    ui.horizontal(|ui| {
+        if recorder.recording {
+            if ui.button("Stop").clicked() {
+                recorder.stop();
+            }
+        } else if ui.button("Record").clicked() {
+            recorder.start();
+        }
+
+        for extension in ["csv", "json"] {
+            if ui
+                .button(format!("Save {}", extension.to_uppercase()))
+                .clicked()
+            {
+                match recorder.save(extension) {
+                    Ok(key) => info!("Saved session to {key}"),
+                    Err(err) => error!("Could not save session: {err}"),
+                }
+            }
+        }
+    });
+    ui.end_row();
+}
+
+fn show_render_stats(ui: &mut egui::Ui, performance: &OSMPerformance) {
+    let chunks = performance.chunk_stats.len().max(1) as f64;
+    let entities = performance.entities.back().copied().unwrap_or_default();
+    let draw_calls = performance.draw_calls.back().copied().unwrap_or_default();
+
+    ui.add(Label::new("entities (per chunk):"));
+    ui.add(Label::new(format!(
+        "{entities} ({:.1})",
+        entities as f64 / chunks
+    )));
+    ui.end_row();
+    ui.add(Label::new("draw calls (per chunk):"));
+    ui.add(Label::new(format!(
+        "{draw_calls} ({:.1})",
+        draw_calls as f64 / chunks
+    )));
+    ui.end_row();
+}
+
+/// Shows the overlay stack, returns whether the chunks need to be reloaded because overlays were
+/// added, removed or got a different source
+fn raster_overlays_ui(ui: &mut Ui, overlays: &mut Vec<RasterLayer>) -> bool {
+    let mut reload = false;
+    let mut removed = None;
+
+    for (i, layer) in overlays.iter_mut().enumerate() {
+        ui.checkbox(&mut layer.visible, format!("overlay {i}"));
+        ui.horizontal(|ui| {
+            let mut source = layer.source.clone();
+            ComboBox::from_id_salt(("overlay_source", i))
+                .selected_text(source.get_name())
+                .show_ui(ui, |ui| {
+                    for option in OVERLAY_SOURCES {
+                        ui.selectable_value(&mut source, option.clone(), option.get_name());
+                    }
+                });
+            if source != layer.source {
+                layer.source = source;
+                reload = true;
+            }
+
+            ComboBox::from_id_salt(("overlay_blend_mode", i))
+                .selected_text(layer.blend_mode.get_name())
+                .show_ui(ui, |ui| {
+                    for mode in LayerBlendMode::ALL {
+                        ui.selectable_value(&mut layer.blend_mode, mode, mode.get_name());
+                    }
+                });
+            ui.add(egui::Slider::new(&mut layer.opacity, 0.0..=1.0).text("opacity"));
+
+            if ui.button("Remove").clicked() {
+                removed = Some(i);
+            }
+        });
+        ui.end_row();
+    }
+
+    if let Some(i) = removed {
+        overlays.remove(i);
+        reload = true;
+    }
+    if overlays.len() < MAX_RASTER_OVERLAYS && ui.button("Add overlay").clicked() {
+        overlays.push(RasterLayer::new(RasterTileSource::Transport));
+        reload = true;
+    }
+    ui.end_row();
+
+    reload
+}
+
+fn terrain_analysis_ui(ui: &mut Ui, analysis: &mut TerrainAnalysis) {
+    ui.checkbox(&mut analysis.hillshade, "hillshade");
+    ui.horizontal(|ui| {
+        ui.add(egui::Slider::new(&mut analysis.sun_azimuth, 0.0..=360.0).text("azimuth"));
+        ui.add(egui::Slider::new(&mut analysis.sun_altitude, 0.0..=90.0).text("altitude"));
+    });
+    ui.end_row();
+
+    ui.add(Label::new("shading:"));
+    ui.horizontal(|ui| {
+        ComboBox::from_id_salt("analysis_shading")
+            .selected_text(analysis.shading.get_name())
+            .show_ui(ui, |ui| {
+                for shading in AnalysisShading::ALL {
+                    ui.selectable_value(&mut analysis.shading, shading, shading.get_name());
+                }
+            });
+        if analysis.shading == AnalysisShading::Hypsometric {
+            ui.add(egui::DragValue::new(&mut analysis.hypsometric_range.x).suffix(" m"));
+            ui.add(egui::DragValue::new(&mut analysis.hypsometric_range.y).suffix(" m"));
+        }
+    });
+    ui.end_row();
+
+    ui.checkbox(&mut analysis.contours, "contours");
+    ui.add(
+        egui::DragValue::new(&mut analysis.contour_interval)
+            .range(1.0..=1000.0)
+            .suffix(" m"),
+    );
+    ui.end_row();
+
+    ui.add(Label::new("analysis opacity:"));
+    ui.add(egui::Slider::new(&mut analysis.opacity, 0.0..=1.0));
+    ui.end_row();
+}
+
+/// Shows the search box and its results, selecting a result flies the camera there
+fn search_ui(
+    commands: &mut Commands,
+    ui: &mut Ui,
+    geocoding: &mut Geocoding,
+    index: &PlaceIndex,
+    camera: Entity,
+) {
+    ui.add(Label::new("search:"));
+    let response = ui.add(
+        egui::TextEdit::singleline(&mut geocoding.query)
+            .hint_text(format!("{} names indexed", index.len())),
+    );
+    if response.changed() {
+        geocoding.search(index);
+    }
+    ui.end_row();
+
+    if geocoding.is_searching() && geocoding.results.is_empty() {
+        ui.add(Label::new(""));
+        ui.add(Label::new("searching..."));
+        ui.end_row();
+    }
+
+    let mut selected = None;
+    for (i, result) in geocoding.results.iter().enumerate() {
+        ui.add(Label::new(""));
+        if ui
+            .selectable_label(false, result.get_description())
+            .clicked()
+        {
+            selected = Some(i);
+        }
+        ui.end_row();
+    }
+
+    if let Some(i) = selected {
+        let result = geocoding.results.remove(i);
+        info!(
+            "Flying to `{}` at {:.5}, {:.5}",
+            result.name, result.lat_lon.x, result.lat_lon.y
+        );
+        commands
+            .entity(camera)
+            .insert(CameraFlyTo::new(result.lat_lon));
+        geocoding.query = result.name;
+        geocoding.results.clear();
+    }
+}
+
 fn osm_ui(
     commands: &mut Commands,
     config: &mut OSMConfig,
@@ -83,12 +359,7 @@ fn osm_ui(
     ComboBox::from_label("Location")
         .selected_text(selected.get_name())
         .show_ui(ui, |ui| {
-            for source in [
-                Location::Amsterdam,
-                Location::London,
-                Location::Monaco,
-                Location::NewYork,
-            ] {
+            for source in Location::ALL {
                 ui.selectable_value(&mut selected, source.clone(), source.get_name());
             }
         });
@@ -109,14 +380,7 @@ fn osm_ui(
     ComboBox::from_label("Raster tile source")
         .selected_text(selected.get_name())
         .show_ui(ui, |ui| {
-            for source in [
-                RasterTileSource::Debug,
-                RasterTileSource::CesiumGoogleSatellite,
-                RasterTileSource::CesiumGoogleRoadmaps,
-                RasterTileSource::CesiumGoogleContour,
-                RasterTileSource::OSMDefault,
-                RasterTileSource::Transport,
-            ] {
+            for source in RasterTileSource::BUILT_IN {
                 ui.selectable_value(&mut selected, source.clone(), source.get_name());
             }
         });
@@ -130,6 +394,19 @@ fn osm_ui(
         ensure_session_is_valid(&config.raster_tile_source);
     }
 
+    if raster_overlays_ui(ui, &mut config.raster_overlays) {
+        for (entity, mut quadtree) in quadtrees.iter_mut() {
+            quadtree.root.destruct(&entity, commands);
+        }
+        for layer in &config.raster_overlays {
+            ensure_session_is_valid(&layer.source);
+        }
+    }
+    terrain_analysis_ui(ui, &mut config.terrain_analysis);
+
+    ui.checkbox(&mut config.place_labels, "place labels");
+    ui.end_row();
+
     ui.add(Label::new("translation:"));
     ui.add(Label::new(format!(
         "{:.0}, {:.0}, {:.0}",
@@ -142,15 +419,20 @@ fn osm_ui(
     ui.end_row();
 }
 
+#[expect(clippy::too_many_arguments)]
 pub fn setup_osm_ui(
     mut commands: Commands,
     mut osm_config: ResMut<OSMConfig>,
-    camera: Single<&Transform, With<Camera>>,
+    camera: Single<(Entity, &Transform), With<Camera>>,
     mut contexts: EguiContexts,
     keys: Res<ButtonInput<KeyCode>>,
     mut quadtrees: Query<(Entity, &mut QuadTree)>,
     performance: Res<OSMPerformance>,
+    mut recorder: ResMut<SessionRecorder>,
+    mut geocoding: ResMut<Geocoding>,
+    place_index: Res<PlaceIndex>,
 ) {
+    let (camera_entity, camera) = *camera;
     if keys.just_pressed(KeyCode::KeyY) {
         osm_config.ui_visible = !osm_config.ui_visible;
     }
@@ -168,9 +450,19 @@ pub fn setup_osm_ui(
                             &mut commands,
                             osm_config.as_mut(),
                             ui,
-                            &camera,
+                            camera,
                             &mut quadtrees,
                         );
+                        search_ui(
+                            &mut commands,
+                            ui,
+                            &mut geocoding,
+                            &place_index,
+                            camera_entity,
+                        );
+                        show_render_stats(ui, &performance);
+                        show_loading_stats(ui, &performance);
+                        recorder_ui(ui, &mut recorder);
                     });
                 egui::Grid::new("plot_grid")
                     .num_columns(1)
@@ -182,6 +474,8 @@ pub fn setup_osm_ui(
                         ui.end_row();
                         show_fps_plot(ui, &performance);
                         ui.end_row();
+                        show_frametime_plot(ui, &performance);
+                        ui.end_row();
                     });
             });
     }
@@ -1,12 +1,10 @@
-use std::fs::File;
-use std::io::Read;
-
 use crate::building::{polygon_building, spawn_building};
-use crate::cache::{cache_vector_tile_for_chunk, get_openfreemap_cache_path};
+use crate::cache::{DownloadProgress, cache_vector_tile_for_chunk, get_openfreemap_cache_path};
 use crate::chunk::Chunk;
 use crate::material::MapMaterialHandle;
 use crate::mesh::{BuildInstruction, spawn_stroke_mesh};
 use crate::schema::layer::OMTLayer;
+use crate::storage::tile_storage;
 use crate::tag::Tag;
 use crate::theme::get_way_build_instruction_openfreemap;
 use bevy::prelude::*;
@@ -75,6 +73,10 @@ pub fn spawn_pbf(
                 child_ids.push(mesh.id());
             }
             BuildInstruction::Light(_light) => {}
+            BuildInstruction::Water(_water) => {}
+            BuildInstruction::Aeroway(_aeroway) => {}
+            BuildInstruction::Boundary(_boundary) => {}
+            BuildInstruction::Place(_place) => {}
             BuildInstruction::None => {}
         }
     }
@@ -91,11 +93,9 @@ pub fn spawn_chunk(
     chunk: &Chunk,
     chunk_entity: Entity,
 ) {
-    cache_vector_tile_for_chunk(chunk);
-    let mut bytes = Vec::new();
-    File::open(get_openfreemap_cache_path(chunk))
-        .unwrap()
-        .read_to_end(&mut bytes)
+    cache_vector_tile_for_chunk(chunk, &DownloadProgress::default());
+    let bytes = tile_storage()
+        .read(&get_openfreemap_cache_path(chunk))
         .unwrap();
     spawn_pbf(
         parse_pbf(bytes).unwrap(),
@@ -187,9 +187,20 @@ fn process_layer(reader: &Reader, layer: &Layer) -> Result<Vec<PolygonInstructio
                     ));
                 }
             }
-            Geometry::MultiPoint(_) => {
-                // println!("Not implemented multipoint: {layer:?} {feature:?}");
+            // Only places are drawn as points, as labels
+            Geometry::Point(point) if layer_name == OMTLayer::Place => {
+                polygons.push((tags, layer_name.clone(), vec![transform_coord(&point.0)]));
+            }
+            Geometry::MultiPoint(multi_point) if layer_name == OMTLayer::Place => {
+                for point in multi_point {
+                    polygons.push((
+                        tags.clone(),
+                        layer_name.clone(),
+                        vec![transform_coord(&point.0)],
+                    ));
+                }
             }
+            Geometry::Point(_) | Geometry::MultiPoint(_) => {}
             _ => {
                 panic!("Not implemented: {feature:?}");
             }
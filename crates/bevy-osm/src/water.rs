@@ -0,0 +1,354 @@
+//! Meshes for the water areas of the vector tiles.
+//!
+//! Water is drawn with the animated [`WaterMaterial`](bevy_terrain::water::WaterMaterial). The
+//! distance from each vertex to the shore is stored in `ATTRIBUTE_UV_1`, which the shader uses
+//! for the foam along the shore and to fade from shallow to deep water.
+
+use bevy::{
+    asset::RenderAssetUsages,
+    mesh::{Indices, PrimitiveTopology},
+    prelude::*,
+};
+use bevy_terrain::mesh::HeightMap;
+use lyon::{math::Point, path::Path};
+use lyon_tessellation::{BuffersBuilder, FillOptions, FillTessellator};
+
+use crate::mesh::{VertexBuffers, VertexConstructor, WaterInstruction, subdivide};
+
+/// Maximum distance between the vertices of a water surface (meters)
+const WATER_SPACING: f32 = 20.0;
+/// Lower bound on the spacing as a fraction of the chunk, so large chunks stay cheap
+const MIN_WATER_SPACING: f32 = 1.0 / 32.0;
+/// Upper bound on the triangles of a single water area
+const MAX_WATER_TRIANGLES: usize = 50_000;
+/// Height of the water above the terrain at the shore, to avoid z-fighting (meters)
+const WATER_HEIGHT_OFFSET: f32 = 0.3;
+/// Distance to the shore of water areas without a shore in the tile, such as the open sea
+/// (meters)
+const OPEN_WATER_DISTANCE: f32 = 1e4;
+
+/// Whether a segment lies on the edge of the tile, where polygons are clipped. These segments
+/// are not a shore.
+fn is_tile_edge(a: Vec2, b: Vec2) -> bool {
+    const EDGE: f32 = 0.5 - 1e-3;
+    let on_edge = |a: f32, b: f32| a.abs() >= EDGE && b.abs() >= EDGE && a.signum() == b.signum();
+    on_edge(a.x, b.x) || on_edge(a.y, b.y)
+}
+
+/// A part of the shore, in meters relative to the chunk center, with the ground height at both
+/// ends
+struct ShoreSegment {
+    start: Vec2,
+    end: Vec2,
+    heights: (f32, f32),
+}
+
+impl ShoreSegment {
+    /// Returns the distance from a point to the segment and the ground height at the closest
+    /// point
+    fn get_closest(&self, point: Vec2) -> (f32, f32) {
+        let direction = self.end - self.start;
+        let t = ((point - self.start).dot(direction)
+            / direction.length_squared().max(f32::EPSILON))
+        .clamp(0.0, 1.0);
+        let closest = self.start + direction * t;
+        (
+            point.distance(closest),
+            self.heights.0.lerp(self.heights.1, t),
+        )
+    }
+}
+
+/// The shore segments bucketed in a grid, so the closest segment to a vertex is found in the
+/// nearby cells instead of by measuring the distance to the whole shore
+struct ShoreGrid {
+    segments: Vec<ShoreSegment>,
+    /// Size of a cell (meters)
+    cell_size: f32,
+    min: Vec2,
+    size: IVec2,
+    /// Indices of the segments that overlap each cell, row by row
+    cells: Vec<Vec<usize>>,
+}
+
+impl ShoreGrid {
+    fn new(segments: Vec<ShoreSegment>, cell_size: f32) -> Self {
+        let (min, max) = segments
+            .iter()
+            .flat_map(|segment| [segment.start, segment.end])
+            .fold((Vec2::INFINITY, Vec2::NEG_INFINITY), |(min, max), p| {
+                (min.min(p), max.max(p))
+            });
+        let mut grid = Self {
+            segments: Vec::new(),
+            cell_size,
+            min,
+            size: IVec2::ZERO,
+            cells: Vec::new(),
+        };
+        if segments.is_empty() {
+            return grid;
+        }
+        grid.size = grid.get_cell(max) + 1;
+        grid.cells = vec![Vec::new(); (grid.size.x * grid.size.y) as usize];
+        for (i, segment) in segments.iter().enumerate() {
+            let from = grid.get_cell(segment.start.min(segment.end));
+            let to = grid.get_cell(segment.start.max(segment.end));
+            for y in from.y..=to.y {
+                for x in from.x..=to.x {
+                    grid.cells[(y * grid.size.x + x) as usize].push(i);
+                }
+            }
+        }
+        grid.segments = segments;
+        grid
+    }
+
+    fn get_cell(&self, point: Vec2) -> IVec2 {
+        ((point - self.min) / self.cell_size).floor().as_ivec2()
+    }
+
+    /// Returns the distance to the closest segment and the ground height at the closest point,
+    /// searching the cells in rings around the cell of the point
+    fn get_closest(&self, point: Vec2) -> Option<(f32, f32)> {
+        if self.segments.is_empty() {
+            return None;
+        }
+        let center = self.get_cell(point);
+        // The ring at which every cell of the grid has been searched
+        let last_ring = center
+            .abs()
+            .max((center - (self.size - 1)).abs())
+            .max_element();
+
+        let mut closest: Option<(f32, f32)> = None;
+        for ring in 0..=last_ring {
+            let from = (center - ring).max(IVec2::ZERO);
+            let to = (center + ring).min(self.size - 1);
+            for y in from.y..=to.y {
+                for x in from.x..=to.x {
+                    if (x - center.x).abs() != ring && (y - center.y).abs() != ring {
+                        continue;
+                    }
+                    for &i in &self.cells[(y * self.size.x + x) as usize] {
+                        let candidate = self.segments[i].get_closest(point);
+                        if closest.is_none_or(|closest| candidate.0 < closest.0) {
+                            closest = Some(candidate);
+                        }
+                    }
+                }
+            }
+            // Segments in the next rings are at least this far away
+            if closest.is_some_and(|closest| closest.0 <= ring as f32 * self.cell_size) {
+                break;
+            }
+        }
+        closest
+    }
+}
+
+/// Builds the surface of a water polygon in chunk space, where `size` is the size of the chunk
+/// in meters
+pub fn build_water_mesh(
+    polygon: &[Point],
+    instruction: &WaterInstruction,
+    heights: &HeightMap,
+    size: Vec2,
+) -> Option<Mesh> {
+    if polygon.len() < 3 {
+        return None;
+    }
+    let ring: Vec<Vec2> = polygon.iter().map(|p| Vec2::new(p.x, p.y)).collect();
+    let spacing = (WATER_SPACING / size.max_element()).max(MIN_WATER_SPACING);
+
+    let mut path_builder = Path::builder();
+    path_builder.begin(polygon[0]);
+    for p in &polygon[1..] {
+        path_builder.line_to(*p);
+    }
+    path_builder.end(true);
+
+    let mut buffers = VertexBuffers::new();
+    let constructor = VertexConstructor {
+        color: Color::WHITE,
+    };
+    if let Err(e) = FillTessellator::new().tessellate_path(
+        &path_builder.build(),
+        &FillOptions::default(),
+        &mut BuffersBuilder::new(&mut buffers, constructor),
+    ) {
+        error!("FillTessellator error: {:?}", e);
+        return None;
+    }
+    if buffers.indices.is_empty() {
+        return None;
+    }
+    subdivide(&mut buffers, 0, spacing, MAX_WATER_TRIANGLES);
+
+    let shore = ShoreGrid::new(
+        ring.iter()
+            .zip(ring.iter().cycle().skip(1))
+            .filter(|&(a, b)| a != b && !is_tile_edge(*a, *b))
+            .flat_map(|(&a, &b)| {
+                let steps = (a.distance(b) / spacing).ceil().max(1.0) as usize;
+                (0..steps).map(move |i| {
+                    (
+                        a.lerp(b, i as f32 / steps as f32),
+                        a.lerp(b, (i + 1) as f32 / steps as f32),
+                    )
+                })
+            })
+            .map(|(start, end)| ShoreSegment {
+                start: start * size,
+                end: end * size,
+                heights: (heights.sample(start), heights.sample(end)),
+            })
+            .collect(),
+        spacing * size.max_element(),
+    );
+
+    // Level water lies at the lowest point of its shore, so it doesn't climb up the banks.
+    let level = shore
+        .segments
+        .iter()
+        .map(|segment| segment.heights.0.min(segment.heights.1))
+        .reduce(f32::min)
+        .unwrap_or_else(|| {
+            ring.iter()
+                .map(|p| heights.sample(*p))
+                .fold(f32::INFINITY, f32::min)
+        });
+
+    let (positions, distances): (Vec<[f32; 3]>, Vec<[f32; 2]>) = buffers
+        .vertices
+        .iter()
+        .map(|vertex| {
+            let p = Vec2::from_array(vertex.position);
+            let (distance, shore_height) = shore
+                .get_closest(p * size)
+                .unwrap_or((OPEN_WATER_DISTANCE, level));
+            let height = if instruction.level {
+                level
+            } else {
+                shore_height
+            };
+            ([p.x, height + WATER_HEIGHT_OFFSET, p.y], [distance, 0.0])
+        })
+        .unzip();
+
+    let mut mesh = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    );
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_UV_0,
+        positions
+            .iter()
+            .map(|p| [p[0] + 0.5, p[2] + 0.5])
+            .collect::<Vec<[f32; 2]>>(),
+    );
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_NORMAL,
+        vec![[0.0, 1.0, 0.0]; positions.len()],
+    );
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_1, distances);
+    mesh.insert_indices(Indices::U32(buffers.indices));
+    Some(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::mesh::VertexAttributeValues;
+    use lyon::math::point;
+
+    use super::*;
+
+    fn sloped_heightmap() -> HeightMap {
+        let mut heights = HeightMap::new(IVec2::splat(8));
+        for x in -1..=10 {
+            for z in -1..=10 {
+                heights.set(x, z, x as f32 * 10.0);
+            }
+        }
+        heights
+    }
+
+    #[test]
+    fn test_water_is_level_and_measures_the_shore() {
+        let heights = sloped_heightmap();
+        // A lake that touches the eastern edge of the tile, which is not a shore
+        let polygon = [
+            point(-0.25, -0.25),
+            point(0.5, -0.25),
+            point(0.5, 0.25),
+            point(-0.25, 0.25),
+        ];
+        let mesh = build_water_mesh(
+            &polygon,
+            &WaterInstruction { level: true },
+            &heights,
+            Vec2::splat(1000.0),
+        )
+        .expect("polygon should be tessellated");
+
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("mesh should have positions");
+        };
+        let Some(VertexAttributeValues::Float32x2(distances)) =
+            mesh.attribute(Mesh::ATTRIBUTE_UV_1)
+        else {
+            panic!("mesh should have shore distances");
+        };
+
+        let level = positions[0][1];
+        assert!(positions.iter().all(|p| (p[1] - level).abs() < 1e-4));
+        let shore_height = heights.sample(Vec2::new(-0.25, 0.0));
+        assert!((level - WATER_HEIGHT_OFFSET - shore_height).abs() < 1e-3);
+
+        for (position, distance) in positions.iter().zip(distances) {
+            // The closest shore is the western, northern or southern side
+            let expected = (position[0] + 0.25)
+                .min(position[2] + 0.25)
+                .min(0.25 - position[2])
+                * 1000.0;
+            assert!((distance[0] - expected).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn test_shore_grid_finds_the_closest_segment() {
+        let segment = |start: Vec2, end: Vec2| ShoreSegment {
+            start,
+            end,
+            heights: (start.x, end.x),
+        };
+        let segments = || {
+            (0..40).map(|i| {
+                let angle = i as f32 * 0.3;
+                let start = Vec2::new(angle.cos(), angle.sin()) * (100.0 + i as f32 * 7.0);
+                segment(start, start + Vec2::new(15.0, -10.0))
+            })
+        };
+        let grid = ShoreGrid::new(segments().collect(), 20.0);
+
+        for x in -30..30 {
+            for y in -30..30 {
+                let point = Vec2::new(x as f32, y as f32) * 13.0;
+                let expected = segments()
+                    .map(|segment| segment.get_closest(point).0)
+                    .reduce(f32::min)
+                    .unwrap();
+                let (distance, _) = grid.get_closest(point).unwrap();
+                assert!((distance - expected).abs() < 1e-3);
+            }
+        }
+        assert!(
+            ShoreGrid::new(Vec::new(), 20.0)
+                .get_closest(Vec2::ZERO)
+                .is_none()
+        );
+    }
+}
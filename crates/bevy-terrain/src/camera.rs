@@ -1,10 +1,11 @@
-use core::f32::consts::PI;
+use core::f32::consts::{FRAC_PI_2, PI};
 
 use bevy::anti_alias::taa::TemporalAntiAliasing;
 use bevy::camera::Exposure;
 use bevy::camera::Hdr;
 use bevy::core_pipeline::tonemapping::Tonemapping;
 use bevy::dev_tools::infinite_grid::InfiniteGrid;
+use bevy::input::mouse::{AccumulatedMouseMotion, AccumulatedMouseScroll, MouseScrollUnit};
 use bevy::light::Atmosphere;
 use bevy::light::atmosphere::ScatteringMedium;
 use bevy::light::light_consts::lux;
@@ -18,6 +19,10 @@ use bevy::prelude::*;
 use bevy::pbr::ScreenSpaceReflections;
 use bevy_where_was_i::WhereWasI;
 
+use crate::{mesh::ChunkHeightMap, quadtree::QuadTreeNodeComponent};
+
+const EARTH_RADIUS_METERS: f32 = 6.371e6;
+
 pub fn setup_lighting_for_open_world(
     mut commands: Commands,
     mut scattering_mediums: ResMut<Assets<ScatteringMedium>>,
@@ -99,3 +104,277 @@ pub fn rotate_sun(
         tf.rotate_y(time.delta_secs() * PI * sun_hor_rot_factor)
     });
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TerrainCameraMode {
+    /// First person at eye height. WASD to walk, hold shift to run.
+    Walk,
+    /// First person at the height of a car. W/S to accelerate and brake, A/D to steer.
+    Drive,
+    /// Orbits around a point on the ground. WASD to pan, scroll or Q/E to zoom.
+    #[default]
+    Orbit,
+}
+
+impl TerrainCameraMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Walk => Self::Drive,
+            Self::Drive => Self::Orbit,
+            Self::Orbit => Self::Walk,
+        }
+    }
+}
+
+/// A camera controller that stays above the terrain.
+///
+/// Hold the right mouse button to look around and press V to switch between modes.
+#[derive(Component, Debug, Clone)]
+pub struct TerrainCamera {
+    pub mode: TerrainCameraMode,
+    /// Height of the camera above the ground in walk mode (meters)
+    pub eye_height: f32,
+    /// Height of the camera above the ground in drive mode (meters)
+    pub vehicle_height: f32,
+    /// Walking speed (m/s), tripled when running
+    pub walk_speed: f32,
+    /// Maximum speed in drive mode (m/s)
+    pub max_drive_speed: f32,
+    /// Minimum height of the camera above the ground in orbit mode (meters)
+    pub min_clearance: f32,
+    /// Maximum distance between the camera and its focus point in orbit mode (meters)
+    pub max_altitude: f32,
+    /// Rotation per pixel of mouse movement (radians)
+    pub sensitivity: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+    /// The point on the ground the camera orbits around
+    pub focus: Vec3,
+    /// Distance between the camera and its focus point in orbit mode
+    pub distance: f32,
+    /// Current speed in drive mode (m/s)
+    pub speed: f32,
+    /// The last known ground height below the camera, used while no terrain is loaded there
+    pub ground_height: f32,
+}
+
+impl Default for TerrainCamera {
+    fn default() -> Self {
+        Self {
+            mode: TerrainCameraMode::default(),
+            eye_height: 1.7,
+            vehicle_height: 1.3,
+            walk_speed: 1.4,
+            max_drive_speed: 40.0,
+            min_clearance: 2.0,
+            max_altitude: 1e5,
+            sensitivity: 0.003,
+            yaw: 0.0,
+            pitch: -0.5,
+            focus: Vec3::ZERO,
+            distance: 500.0,
+            speed: 0.0,
+            ground_height: 0.0,
+        }
+    }
+}
+
+impl TerrainCamera {
+    fn rotation(&self) -> Quat {
+        Quat::from_euler(EulerRot::YXZ, self.yaw, self.pitch, 0.0)
+    }
+
+    /// Takes over the position and orientation of the camera
+    fn sync_with_transform(&mut self, transform: &Transform) {
+        let (yaw, pitch, _) = transform.rotation.to_euler(EulerRot::YXZ);
+        self.yaw = yaw;
+        self.pitch = pitch;
+
+        if self.mode == TerrainCameraMode::Orbit {
+            self.pitch = self.pitch.min(-0.1);
+            self.focus = transform.translation - self.rotation() * Vec3::Z * self.distance;
+        }
+    }
+
+    fn switch_mode(&mut self, mode: TerrainCameraMode, transform: &mut Transform) {
+        match mode {
+            TerrainCameraMode::Orbit => {
+                self.distance = (transform.translation.y - self.ground_height)
+                    .clamp(self.min_clearance, self.max_altitude)
+                    * 2.0;
+                self.pitch = -0.5;
+                self.focus = transform.translation - self.rotation() * Vec3::Z * self.distance;
+            }
+            TerrainCameraMode::Walk | TerrainCameraMode::Drive => {
+                if self.mode == TerrainCameraMode::Orbit {
+                    transform.translation = self.focus;
+                }
+                self.pitch = 0.0;
+            }
+        }
+        self.speed = 0.0;
+        self.mode = mode;
+    }
+}
+
+/// Returns the height of the most detailed loaded terrain chunk at a world position on the
+/// XZ-plane.
+pub fn get_ground_height(
+    chunks: &Query<(&ChunkHeightMap, &GlobalTransform, &QuadTreeNodeComponent)>,
+    position: Vec2,
+) -> Option<f32> {
+    chunks
+        .iter()
+        .filter_map(|(heights, transform, node)| {
+            let local = transform
+                .affine()
+                .inverse()
+                .transform_point3(Vec3::new(position.x, 0.0, position.y));
+            if !Rect::from_center_size(Vec2::ZERO, Vec2::ONE).contains(local.xz()) {
+                return None;
+            }
+            let height = heights.0.sample(local.xz());
+            Some((
+                node.lod,
+                transform
+                    .transform_point(Vec3::new(local.x, height, local.z))
+                    .y,
+            ))
+        })
+        .max_by_key(|(lod, _)| *lod)
+        .map(|(_, height)| height)
+}
+
+#[expect(clippy::too_many_arguments)]
+pub fn update_terrain_camera(
+    mut cameras: Query<(&mut Transform, &mut TerrainCamera, Option<&mut Projection>)>,
+    chunks: Query<(&ChunkHeightMap, &GlobalTransform, &QuadTreeNodeComponent)>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    mouse_motion: Res<AccumulatedMouseMotion>,
+    mouse_scroll: Res<AccumulatedMouseScroll>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
+    let ground_at = |position: Vec3| get_ground_height(&chunks, position.xz());
+
+    let mut input = Vec2::ZERO;
+    if keys.pressed(KeyCode::KeyW) {
+        input.y += 1.0;
+    }
+    if keys.pressed(KeyCode::KeyS) {
+        input.y -= 1.0;
+    }
+    if keys.pressed(KeyCode::KeyD) {
+        input.x += 1.0;
+    }
+    if keys.pressed(KeyCode::KeyA) {
+        input.x -= 1.0;
+    }
+
+    let mut zoom = match mouse_scroll.unit {
+        MouseScrollUnit::Line => -mouse_scroll.delta.y * 0.1,
+        MouseScrollUnit::Pixel => -mouse_scroll.delta.y * 0.001,
+    };
+    if keys.pressed(KeyCode::KeyQ) {
+        zoom -= dt;
+    }
+    if keys.pressed(KeyCode::KeyE) {
+        zoom += dt;
+    }
+
+    for (mut transform, mut controller, projection) in &mut cameras {
+        if controller.is_added() {
+            controller.sync_with_transform(&transform);
+        }
+        let controller = controller.as_mut();
+        if keys.just_pressed(KeyCode::KeyV) {
+            controller.switch_mode(controller.mode.next(), &mut transform);
+        }
+
+        if mouse_buttons.pressed(MouseButton::Right) {
+            let max_pitch = match controller.mode {
+                TerrainCameraMode::Orbit => -0.01,
+                _ => FRAC_PI_2 - 0.01,
+            };
+            controller.yaw -= mouse_motion.delta.x * controller.sensitivity;
+            controller.pitch = (controller.pitch - mouse_motion.delta.y * controller.sensitivity)
+                .clamp(-FRAC_PI_2 + 0.01, max_pitch);
+        }
+
+        let heading = Quat::from_rotation_y(controller.yaw);
+        let forward = heading * Vec3::NEG_Z;
+        let right = heading * Vec3::X;
+
+        match controller.mode {
+            TerrainCameraMode::Walk => {
+                let speed = match keys.pressed(KeyCode::ShiftLeft) {
+                    true => controller.walk_speed * 3.0,
+                    false => controller.walk_speed,
+                };
+                let mut position = transform.translation
+                    + (forward * input.y + right * input.x).normalize_or_zero() * speed * dt;
+                position.y =
+                    ground_at(position).unwrap_or(controller.ground_height) + controller.eye_height;
+
+                transform.translation = position;
+                transform.rotation = controller.rotation();
+            }
+            TerrainCameraMode::Drive => {
+                let acceleration = controller.max_drive_speed / 4.0;
+                controller.speed = match input.y == 0.0 {
+                    true => controller.speed * (1.0 - 0.5 * dt),
+                    false => controller.speed + input.y * acceleration * dt,
+                }
+                .clamp(
+                    -controller.max_drive_speed / 4.0,
+                    controller.max_drive_speed,
+                );
+
+                // Steer like a car with a turning radius of 10 meters.
+                controller.yaw -= (input.x * controller.speed / 10.0).clamp(-1.0, 1.0) * dt;
+
+                let mut position = transform.translation + forward * controller.speed * dt;
+                position.y = ground_at(position).unwrap_or(controller.ground_height)
+                    + controller.vehicle_height;
+
+                transform.translation = position;
+                transform.rotation = controller.rotation();
+            }
+            TerrainCameraMode::Orbit => {
+                controller.distance = (controller.distance * zoom.exp())
+                    .clamp(controller.min_clearance, controller.max_altitude);
+
+                // Panning speed scales with the distance, so it is usable from street level up
+                // to the edge of the atmosphere.
+                controller.focus += (forward * input.y + right * input.x).normalize_or_zero()
+                    * controller.distance
+                    * dt;
+                controller.focus.y = ground_at(controller.focus).unwrap_or(controller.focus.y);
+
+                let mut position =
+                    controller.focus + controller.rotation() * Vec3::Z * controller.distance;
+                position.y = position.y.max(
+                    ground_at(position).unwrap_or(controller.ground_height)
+                        + controller.min_clearance,
+                );
+
+                *transform =
+                    Transform::from_translation(position).looking_at(controller.focus, Vec3::Y);
+            }
+        }
+
+        controller.ground_height =
+            ground_at(transform.translation).unwrap_or(controller.ground_height);
+        let height_above_ground = (transform.translation.y - controller.ground_height).max(0.0);
+
+        if let Some(mut projection) = projection
+            && let Projection::Perspective(perspective) = projection.as_mut()
+        {
+            let horizon_distance =
+                (height_above_ground * (2.0 * EARTH_RADIUS_METERS + height_above_ground)).sqrt();
+            perspective.near = (height_above_ground * 0.01).clamp(0.05, 100.0);
+            perspective.far = horizon_distance.max(1e4) + 1e4;
+        }
+    }
+}
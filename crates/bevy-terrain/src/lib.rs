@@ -5,24 +5,38 @@ pub mod quadtree;
 pub mod system;
 pub mod water;
 
-use bevy::{pbr::ExtendedMaterial, prelude::*};
+use bevy::prelude::*;
 
 use quadtree::{QuadTree, QuadTreeConfig, QuadTreeNode};
 
 use crate::{
+    camera::update_terrain_camera,
     mesh::build_mesh_cache,
     system::update_terrain_quadtree,
-    water::{Water, spawn_water},
+    water::{WaterMaterial, spawn_water},
 };
 
 pub struct WaterPlugin;
 
 impl Plugin for WaterPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(MaterialPlugin::<ExtendedMaterial<StandardMaterial, Water>>::default())
-            .add_systems(Startup, spawn_water);
+        // The material may have been registered already, by a plugin that draws water meshes of
+        // its own.
+        if !app.is_plugin_added::<MaterialPlugin<WaterMaterial>>() {
+            app.add_plugins(MaterialPlugin::<WaterMaterial>::default());
+        }
+        app.add_systems(Startup, spawn_water);
     }
 }
+/// Adds the [`camera::TerrainCamera`] controller
+pub struct TerrainCameraPlugin;
+
+impl Plugin for TerrainCameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, update_terrain_camera);
+    }
+}
+
 pub struct TerrainPlugin;
 
 impl Plugin for TerrainPlugin {
@@ -40,6 +54,7 @@ pub fn build_terrain_tile(mut commands: Commands) {
         max_lod: 24,
         min_lod: 0,
         size,
+        ..default()
     };
     let quadtree = QuadTree {
         root: QuadTreeNode::new(Vec2::ZERO, Vec2::splat(size), 0, 0),
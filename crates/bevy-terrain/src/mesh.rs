@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::sync::Arc;
 
 use bevy::{
     asset::RenderAssetUsages,
@@ -14,7 +14,79 @@ pub struct MeshCache {
     pub material: MeshMaterial3d<StandardMaterial>,
 }
 
-pub type HeightMap = HashMap<(i32, i32), f32>;
+/// The heightmap a terrain chunk was built from, in the local space of the chunk.
+#[derive(Component, Debug, Clone)]
+pub struct ChunkHeightMap(pub Arc<HeightMap>);
+
+/// Heights of a grid of vertices, stored as a dense row-major array.
+///
+/// Besides the vertices of the mesh itself, it contains a border of one vertex on the low side
+/// and two vertices on the high side, so the range of valid coordinates is -1..vertex_count+2
+/// (inclusive) in both dimensions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeightMap {
+    vertex_count: IVec2,
+    heights: Vec<f32>,
+}
+
+impl HeightMap {
+    pub fn new(vertex_count: IVec2) -> Self {
+        let size = (vertex_count + 4).max(IVec2::ZERO);
+        Self {
+            vertex_count,
+            heights: vec![0.0; (size.x * size.y) as usize],
+        }
+    }
+
+    pub fn vertex_count(&self) -> IVec2 {
+        self.vertex_count
+    }
+
+    fn index(&self, x: i32, z: i32) -> usize {
+        assert!(
+            (-1..=self.vertex_count.x + 2).contains(&x)
+                && (-1..=self.vertex_count.y + 2).contains(&z),
+            "Coordinate ({x}, {z}) is outside of the heightmap"
+        );
+        ((x + 1) + (z + 1) * (self.vertex_count.x + 4)) as usize
+    }
+
+    pub fn get(&self, x: i32, z: i32) -> f32 {
+        self.heights[self.index(x, z)]
+    }
+
+    pub fn set(&mut self, x: i32, z: i32, height: f32) {
+        let index = self.index(x, z);
+        self.heights[index] = height;
+    }
+
+    /// Interpolates the height at a position in mesh space, in which the mesh spans -0.5..0.5.
+    ///
+    /// Follows the triangles of [`build_mesh_data`], so the result matches the rendered surface.
+    pub fn sample(&self, position: Vec2) -> f32 {
+        let grid = (position + 0.5).clamp(Vec2::ZERO, Vec2::ONE) * self.vertex_count.as_vec2();
+        let cell = grid.floor().as_ivec2().min(self.vertex_count - 1);
+        let fraction = grid - cell.as_vec2();
+
+        let (x, z) = (cell.x, cell.y);
+        if fraction.x + fraction.y <= 1.0 {
+            let h00 = self.get(x, z);
+            h00 + fraction.x * (self.get(x + 1, z) - h00) + fraction.y * (self.get(x, z + 1) - h00)
+        } else {
+            let h11 = self.get(x + 1, z + 1);
+            h11 + (1.0 - fraction.x) * (self.get(x, z + 1) - h11)
+                + (1.0 - fraction.y) * (self.get(x + 1, z) - h11)
+        }
+    }
+}
+
+impl Extend<((i32, i32), f32)> for HeightMap {
+    fn extend<T: IntoIterator<Item = ((i32, i32), f32)>>(&mut self, iter: T) {
+        for ((x, z), height) in iter {
+            self.set(x, z, height);
+        }
+    }
+}
 
 /// Builds a mesh of size 1.0 x 1.0, with vertex_count number of cells within in both
 /// dimensions.
@@ -25,7 +97,7 @@ pub type HeightMap = HashMap<(i32, i32), f32>;
 ///
 /// [`heights`] must include values in a range of -1..vertex_count+2 (inclusive) in both
 /// dimensions.
-pub fn build_mesh_data(heights: HeightMap, vertex_count: IVec2) -> Mesh {
+pub fn build_mesh_data(heights: &HeightMap, vertex_count: IVec2) -> Mesh {
     let mut mesh = Mesh::new(
         PrimitiveTopology::TriangleList,
         RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
@@ -51,7 +123,7 @@ pub fn build_mesh_data(heights: HeightMap, vertex_count: IVec2) -> Mesh {
     let get_vertex = |x: i32, z: i32| {
         let x_pos = (x as f32) * vertex_spacing_x - 0.5;
         let z_pos = (z as f32) * vertex_spacing_z - 0.5;
-        [x_pos, heights[&(x, z)], z_pos]
+        [x_pos, heights.get(x, z), z_pos]
     };
     let get_normal = |x: i32, z: i32| {
         triangle_normal(get_vertex(x, z), get_vertex(x, z + 1), get_vertex(x + 1, z))
@@ -164,17 +236,18 @@ pub fn spawn_mesh(
     let perlin = Perlin::new(1);
     let vert_scale = 0.3;
 
-    let heights = iterate_mesh_vertices(vertex_count, world_rect)
-        .map(|(x_local, z_local, x_world, z_world)| {
+    let mut heights = HeightMap::new(vertex_count);
+    heights.extend(iterate_mesh_vertices(vertex_count, world_rect).map(
+        |(x_local, z_local, x_world, z_world)| {
             (
                 (x_local, z_local),
                 vert_scale * perlin.get([x_world, 0.0, z_world]) as f32 - 0.9,
             )
-        })
-        .collect();
+        },
+    ));
 
     let entity = commands.spawn((
-        Mesh3d(meshes.add(build_mesh_data(heights, vertex_count))),
+        Mesh3d(meshes.add(build_mesh_data(&heights, vertex_count))),
         rect_to_transform(world_rect),
         mesh_cache.material.clone(),
     ));
@@ -1,4 +1,8 @@
-use bevy::prelude::*;
+use bevy::{
+    camera::primitives::{Aabb, Frustum},
+    math::Affine3A,
+    prelude::*,
+};
 
 use crate::mesh::rect_to_transform;
 
@@ -18,6 +22,23 @@ pub struct QuadTreeConfig {
     /// Then: 2^18 * 4 / 40_000_000 * 256 == 6.7
     /// So, we'll have 6.7 vertices per meter, or 1 vertex per 15 centimeter at max LOD (LOD 20).
     pub size: f32,
+    /// Only refine nodes that intersect the camera frustum
+    pub frustum_culling: bool,
+    /// Radius of the planet, used to skip refinement of nodes that are below the horizon.
+    /// Horizon culling is disabled when `None`.
+    pub horizon_radius: Option<f32>,
+    /// Lowest and highest possible terrain height, used to build bounding boxes of nodes
+    pub min_height: f32,
+    pub max_height: f32,
+    /// Maximum screen-space error in pixels. When set, nodes are refined until a mesh cell
+    /// projects to fewer pixels than this, instead of using the distance criterion of `k`.
+    pub max_screen_space_error: Option<f32>,
+    /// Number of mesh cells along one side of a node, used to estimate its geometric error
+    pub node_resolution: u32,
+    /// Maximum number of node entities that are spawned per frame
+    pub max_nodes_per_frame: Option<usize>,
+    /// Maximum number of nodes that can exist at a single LOD level
+    pub max_nodes_per_level: Option<usize>,
 }
 
 #[derive(Component, Debug, Default, Clone)]
@@ -47,6 +68,134 @@ pub struct QuadTreeNodeComponent {
 #[derive(Component)]
 pub struct ChunkLoaded;
 
+/// The state of the camera that drives refinement, in the local space of a quadtree.
+#[derive(Debug, Clone)]
+pub struct QuadTreeView {
+    pub position: Vec3,
+    /// The camera frustum and the transform from the local space of the quadtree to world space
+    pub frustum: Option<(Frustum, Affine3A)>,
+    /// Viewport height in pixels divided by `2 * tan(fov / 2)`, projects world-space errors
+    /// at a distance of 1 meter to pixels.
+    pub projection_scale: f32,
+}
+
+impl QuadTreeView {
+    pub fn from_position(position: Vec3) -> Self {
+        Self {
+            position,
+            frustum: None,
+            projection_scale: 1.0,
+        }
+    }
+
+    /// Whether any part of `rect` can be seen from this view
+    pub fn is_visible(&self, rect: Rect, config: &QuadTreeConfig) -> bool {
+        !is_below_horizon(rect, self.position, config) && self.intersects_frustum(rect, config)
+    }
+
+    fn intersects_frustum(&self, rect: Rect, config: &QuadTreeConfig) -> bool {
+        let Some((frustum, world_from_local)) = &self.frustum else {
+            return true;
+        };
+        let aabb = Aabb::from_min_max(
+            Vec3::new(rect.min.x, config.min_height, rect.min.y),
+            Vec3::new(rect.max.x, config.max_height, rect.max.y),
+        );
+        frustum.intersects_obb(&aabb, world_from_local, true, false)
+    }
+
+    /// Priority of a node for refinement and loading, lower values are more important.
+    ///
+    /// Nodes are ordered by their distance to the camera relative to their size, nodes that are
+    /// not visible always come last.
+    pub fn get_priority(&self, rect: Rect, config: &QuadTreeConfig) -> f32 {
+        let distance =
+            distance_to_node(rect, self.position, config) / rect.width().max(f32::EPSILON);
+        match self.is_visible(rect, config) {
+            true => distance,
+            false => distance + 1e6,
+        }
+    }
+}
+
+/// Keeps track of how many nodes were spawned during a single refinement pass
+#[derive(Debug, Default)]
+pub struct RefinementBudget {
+    pub nodes_spawned: usize,
+    pub nodes_per_level: Vec<usize>,
+}
+
+impl RefinementBudget {
+    pub fn for_tree(root: &QuadTreeNode) -> Self {
+        let mut budget = Self::default();
+        root.count_nodes_per_level(&mut budget.nodes_per_level);
+        budget
+    }
+
+    fn nodes_at_level(&self, lod: u8) -> usize {
+        self.nodes_per_level.get(lod as usize).copied().unwrap_or(0)
+    }
+
+    fn can_subdivide(&self, lod: u8, config: &QuadTreeConfig) -> bool {
+        config
+            .max_nodes_per_level
+            .is_none_or(|max| self.nodes_at_level(lod + 1) + 4 <= max)
+    }
+
+    fn can_spawn(&self, config: &QuadTreeConfig) -> bool {
+        config
+            .max_nodes_per_frame
+            .is_none_or(|max| self.nodes_spawned < max)
+    }
+
+    fn add_children(&mut self, lod: u8) {
+        let level = lod as usize + 1;
+        if self.nodes_per_level.len() <= level {
+            self.nodes_per_level.resize(level + 1, 0);
+        }
+        self.nodes_per_level[level] += 4;
+    }
+}
+
+/// Horizontal distance from a point to the closest point in a rect
+fn horizontal_distance(rect: Rect, point: Vec2) -> f32 {
+    (point - point.clamp(rect.min, rect.max)).length()
+}
+
+/// Euclidian distance from the camera to the closest point of the bounding box of a node
+fn distance_to_node(rect: Rect, camera_position: Vec3, config: &QuadTreeConfig) -> f32 {
+    let d_horiz = horizontal_distance(rect, camera_position.xz());
+    let d_vert = (camera_position.y
+        - camera_position
+            .y
+            .clamp(config.min_height, config.max_height.max(config.min_height)))
+    .abs();
+    Vec2::new(d_horiz, d_vert).length()
+}
+
+/// Distance to the horizon on a sphere with `radius` when looking from `height` above it
+fn horizon_distance(radius: f32, height: f32) -> f32 {
+    let height = height.max(0.0);
+    (2.0 * radius * height + height * height).sqrt()
+}
+
+/// A node is below the horizon if the horizon of the camera and the horizon of the highest
+/// possible terrain inside the node do not overlap
+fn is_below_horizon(rect: Rect, camera_position: Vec3, config: &QuadTreeConfig) -> bool {
+    let Some(radius) = config.horizon_radius else {
+        return false;
+    };
+    horizontal_distance(rect, camera_position.xz())
+        > horizon_distance(radius, camera_position.y) + horizon_distance(radius, config.max_height)
+}
+
+/// Projected size in pixels of a single mesh cell of a node
+fn screen_space_error(rect: Rect, view: &QuadTreeView, config: &QuadTreeConfig) -> f32 {
+    let geometric_error = rect.width() / config.node_resolution.max(1) as f32;
+    let distance = distance_to_node(rect, view.position, config).max(f32::EPSILON);
+    geometric_error * view.projection_scale / distance
+}
+
 /// subdivide based on non-euclidian max(dx, dy, dz) distance from camera
 ///
 /// https://proland.inrialpes.fr/doc/proland-4.0/core/html/index.html
@@ -141,33 +290,84 @@ impl QuadTreeNode {
         }
     }
 
+    fn count_nodes_per_level(&self, counts: &mut Vec<usize>) {
+        if counts.len() <= self.lod as usize {
+            counts.resize(self.lod as usize + 1, 0);
+        }
+        counts[self.lod as usize] += 1;
+
+        for child in &self.children {
+            child.count_nodes_per_level(counts);
+        }
+    }
+
+    fn should_refine(&self, config: &QuadTreeConfig, view: &QuadTreeView) -> bool {
+        if self.lod < config.min_lod {
+            return true;
+        }
+        if self.lod >= config.max_lod || !view.is_visible(self.rect, config) {
+            return false;
+        }
+
+        match config.max_screen_space_error {
+            Some(max_error) => screen_space_error(self.rect, view, config) > max_error,
+            None => should_subdivide(self.rect, view.position, config.k),
+        }
+    }
+
+    /// A node is ready when it has been loaded itself, or when it was replaced by its children.
+    fn is_ready(
+        &self,
+        nodes_query: &Query<(Entity, Option<&Children>, Option<&ChunkLoaded>)>,
+    ) -> bool {
+        match self.entity {
+            Some(entity) => nodes_query.get(entity).unwrap().2.is_some(),
+            None => !self.children.is_empty(),
+        }
+    }
+
     pub fn build_around_point(
         &mut self,
         config: &QuadTreeConfig,
         root_entity: &Entity,
         commands: &mut Commands,
-        ref_point: Vec3,
+        view: &QuadTreeView,
+        budget: &mut RefinementBudget,
         nodes_query: &Query<(Entity, Option<&Children>, Option<&ChunkLoaded>)>,
     ) {
-        let increase_lod = (should_subdivide(self.rect, ref_point, config.k)
-            && self.lod < config.max_lod)
-            || self.lod < config.min_lod;
+        let increase_lod = self.should_refine(config, view)
+            && (!self.children.is_empty()
+                || self.lod < config.min_lod
+                || budget.can_subdivide(self.lod, config));
 
         if increase_lod {
             if self.children.is_empty() {
                 self.subdivide();
+                budget.add_children(self.lod);
             } else {
-                let all_loaded = self.children.iter().all(|c| {
-                    c.entity.is_none() || nodes_query.get(c.entity.unwrap()).unwrap().2.is_some()
-                });
+                let all_loaded = self.children.iter().all(|c| c.is_ready(nodes_query));
 
                 if all_loaded && self.entity.is_some() {
                     commands.get_entity(self.entity.unwrap()).unwrap().despawn();
                     self.entity = None;
                 }
             }
-            for child in &mut self.children {
-                child.build_around_point(config, root_entity, commands, ref_point, nodes_query);
+
+            // Visit the most important children first, so they get the spawn budget.
+            let mut order = (0..self.children.len()).collect::<Vec<usize>>();
+            order.sort_by(|a, b| {
+                let priority = |i: usize| view.get_priority(self.children[i].rect, config);
+                priority(*a).total_cmp(&priority(*b))
+            });
+            for i in order {
+                self.children[i].build_around_point(
+                    config,
+                    root_entity,
+                    commands,
+                    view,
+                    budget,
+                    nodes_query,
+                );
             }
         } else if let Some(ent) = self.entity {
             let loaded = nodes_query.get(ent).unwrap().2.is_some();
@@ -177,8 +377,9 @@ impl QuadTreeNode {
                 }
                 self.children = Vec::new();
             }
-        } else {
+        } else if budget.can_spawn(config) {
             self.entity = Some(get_mesh(commands, root_entity, self));
+            budget.nodes_spawned += 1;
         }
     }
 }
@@ -200,4 +401,33 @@ mod tests {
             1.1
         ));
     }
+
+    #[test]
+    fn test_horizon_culling() {
+        let config = QuadTreeConfig {
+            horizon_radius: Some(6.371e6),
+            ..default()
+        };
+        let rect = Rect::from_center_size(Vec2::new(1e5, 0.0), Vec2::splat(1e3));
+
+        // The horizon is roughly 3.6 km away at a height of 1 meter, and 113 km at 1 km
+        assert!(is_below_horizon(rect, Vec3::Y, &config));
+        assert!(!is_below_horizon(rect, Vec3::Y * 1e3, &config));
+    }
+
+    #[test]
+    fn test_screen_space_error() {
+        let config = QuadTreeConfig {
+            node_resolution: 64,
+            ..default()
+        };
+        let view = QuadTreeView {
+            projection_scale: 1000.0,
+            ..QuadTreeView::from_position(Vec3::new(0.0, 100.0, 0.0))
+        };
+        let rect = Rect::from_center_size(Vec2::ZERO, Vec2::splat(640.0));
+
+        // Every cell is 10 meters wide, seen from 100 meters away
+        assert!((screen_space_error(rect, &view, &config) - 100.0).abs() < 1e-3);
+    }
 }
@@ -1,8 +1,8 @@
 use std::f32::consts::PI;
 
-use bevy::prelude::*;
+use bevy::{camera::primitives::Frustum, prelude::*};
 
-use crate::quadtree::ChunkLoaded;
+use crate::quadtree::{ChunkLoaded, QuadTreeView, RefinementBudget};
 
 use super::quadtree::{QuadTree, QuadTreeConfig, QuadTreeNode};
 
@@ -16,6 +16,7 @@ pub fn build_planet(mut commands: Commands, radius: f32) {
         max_lod: 24,
         min_lod: 2,
         size: radius,
+        ..default()
     };
     let quadtree = QuadTree {
         root: QuadTreeNode::new(Vec2::ZERO, Vec2::splat(radius), 0, 0),
@@ -87,16 +88,35 @@ pub fn build_planet(mut commands: Commands, radius: f32) {
 
 pub fn update_terrain_quadtree(
     mut commands: Commands,
-    camera: Single<&Transform, With<Camera>>,
+    camera: Single<(&Transform, &Camera, Option<&Projection>, Option<&Frustum>)>,
     mut quadtrees: Query<(Entity, &mut QuadTree, &QuadTreeConfig, &Transform)>,
     nodes_query: Query<(Entity, Option<&Children>, Option<&ChunkLoaded>)>,
 ) {
+    let (camera_transform, camera, projection, frustum) = *camera;
+
+    let projection_scale = match (projection, camera.logical_viewport_size()) {
+        (Some(Projection::Perspective(perspective)), Some(viewport)) => {
+            viewport.y / (2.0 * (perspective.fov * 0.5).tan())
+        }
+        _ => 1.0,
+    };
+
     for (entity, mut quadtree, config, transform) in quadtrees.iter_mut() {
+        let view = QuadTreeView {
+            position: camera_transform.translation - transform.translation,
+            frustum: frustum
+                .filter(|_| config.frustum_culling)
+                .map(|frustum| (*frustum, transform.compute_affine())),
+            projection_scale,
+        };
+        let mut budget = RefinementBudget::for_tree(&quadtree.root);
+
         quadtree.root.build_around_point(
             config,
             &entity,
             &mut commands,
-            camera.translation - transform.translation,
+            &view,
+            &mut budget,
             &nodes_query,
         );
     }
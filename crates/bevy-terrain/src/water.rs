@@ -16,6 +16,9 @@ use bevy::{
 /// This example uses a shader source file from the assets subdirectory
 const SHADER_ASSET_PATH: &str = "shaders/water_material.wgsl";
 
+/// The water material, an animated [`Water`] extension of a black, glossy [`StandardMaterial`]
+pub type WaterMaterial = ExtendedMaterial<StandardMaterial, Water>;
+
 /// A custom [`ExtendedMaterial`] that creates animated water ripples.
 #[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
 pub struct Water {
@@ -28,7 +31,7 @@ pub struct Water {
 
     // Parameters to the water shader.
     #[uniform(102)]
-    settings: WaterSettings,
+    pub settings: WaterSettings,
 }
 
 /// Parameters to the water shader.
@@ -41,51 +44,73 @@ pub struct WaterSettings {
     octave_scales: Vec4,
     /// How high the waves are in each octave.
     octave_strengths: Vec4,
+    /// Width of the foam along the shore and the distance over which shallow water fades to deep
+    /// water (meters). Only used by meshes with the distance to the shore in `ATTRIBUTE_UV_1`.
+    pub shoreline: Vec2,
+    /// Colour of the water at the shore
+    pub shallow_color: LinearRgba,
+}
+
+impl Default for WaterSettings {
+    fn default() -> Self {
+        // These water settings are just random values to create some
+        // variety.
+        Self {
+            octave_vectors: [
+                vec4(0.080, 0.059, 0.073, -0.062),
+                vec4(0.153, 0.138, -0.149, -0.195),
+            ],
+            octave_scales: vec4(1.0, 2.1, 7.9, 14.9) * 1e3,
+            octave_strengths: vec4(0.16, 0.18, 0.093, 0.044),
+            shoreline: Vec2::new(4.0, 40.0),
+            shallow_color: LinearRgba::rgb(0.05, 0.25, 0.25),
+        }
+    }
+}
+
+impl Water {
+    pub fn new(asset_server: &AssetServer) -> Self {
+        Self {
+            normals: asset_server
+                .load_builder()
+                .with_settings(|settings: &mut ImageLoaderSettings| {
+                    settings.is_srgb = false;
+                    settings.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+                        address_mode_u: ImageAddressMode::Repeat,
+                        address_mode_v: ImageAddressMode::Repeat,
+                        mag_filter: ImageFilterMode::Linear,
+                        min_filter: ImageFilterMode::Linear,
+                        ..default()
+                    });
+                })
+                .load("textures/water_normals.png"),
+            settings: WaterSettings::default(),
+        }
+    }
+}
+
+/// Returns the water material with the default settings
+pub fn get_water_material(asset_server: &AssetServer) -> WaterMaterial {
+    ExtendedMaterial {
+        base: StandardMaterial {
+            base_color: BLACK.into(),
+            perceptual_roughness: 0.0,
+            ..default()
+        },
+        extension: Water::new(asset_server),
+    }
 }
 
 /// Spawns the water plane.
 pub(crate) fn spawn_water(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut water_materials: ResMut<Assets<ExtendedMaterial<StandardMaterial, Water>>>,
+    mut water_materials: ResMut<Assets<WaterMaterial>>,
     asset_server: Res<AssetServer>,
 ) {
     commands.spawn((
         Mesh3d(meshes.add(Plane3d::new(Vec3::Y, Vec2::splat(1.0)))),
-        MeshMaterial3d(
-            water_materials.add(ExtendedMaterial {
-                base: StandardMaterial {
-                    base_color: BLACK.into(),
-                    perceptual_roughness: 0.0,
-                    ..default()
-                },
-                extension: Water {
-                    normals: asset_server
-                        .load_builder()
-                        .with_settings(|settings: &mut ImageLoaderSettings| {
-                            settings.is_srgb = false;
-                            settings.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
-                                address_mode_u: ImageAddressMode::Repeat,
-                                address_mode_v: ImageAddressMode::Repeat,
-                                mag_filter: ImageFilterMode::Linear,
-                                min_filter: ImageFilterMode::Linear,
-                                ..default()
-                            });
-                        })
-                        .load("textures/water_normals.png"),
-                    // These water settings are just random values to create some
-                    // variety.
-                    settings: WaterSettings {
-                        octave_vectors: [
-                            vec4(0.080, 0.059, 0.073, -0.062),
-                            vec4(0.153, 0.138, -0.149, -0.195),
-                        ],
-                        octave_scales: vec4(1.0, 2.1, 7.9, 14.9) * 1e3,
-                        octave_strengths: vec4(0.16, 0.18, 0.093, 0.044),
-                    },
-                },
-            }),
-        ),
+        MeshMaterial3d(water_materials.add(get_water_material(&asset_server))),
         Transform::from_scale(Vec3::splat(1e5)),
     ));
 }
@@ -6,7 +6,7 @@ use bevy_egui::EguiPlugin;
 use bevy_flight_sim::flightdeck::spawn_flightdeck;
 use bevy_flight_sim::runway::spawn_aircraft;
 use bevy_osm::config::OSMConfig;
-use bevy_osm::{OSMPlugin, location::Location};
+use bevy_osm::{OSMPlugin, airport::Runway, location::Location, storage::TileStoragePlugin};
 use bevy_terrain::camera::{
     get_camera_bundle_for_open_world, rotate_sun, setup_lighting_for_open_world,
 };
@@ -23,6 +23,7 @@ fn main() {
             ..Default::default()
         })
         .add_plugins((
+            TileStoragePlugin,
             DefaultPlugins,
             InfiniteGridPlugin,
             OSMPlugin,
@@ -40,7 +41,7 @@ fn main() {
                 spawn_camera,
             ),
         )
-        .add_systems(Update, rotate_sun)
+        .add_systems(Update, (rotate_sun, take_off_from_nearest_runway))
         .run();
 }
 
@@ -54,3 +55,26 @@ fn spawn_camera(mut commands: Commands) {
         ..default()
     }));
 }
+
+/// Places the camera on the threshold of the nearest runway when R is pressed
+fn take_off_from_nearest_runway(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut camera: Single<&mut Transform, With<FlyCam>>,
+    runways: Query<(&Runway, &GlobalTransform)>,
+) {
+    if !keys.just_pressed(KeyCode::KeyR) {
+        return;
+    }
+    let takeoff = runways
+        .iter()
+        .flat_map(|(runway, transform)| {
+            [0, 1].map(|end| runway.get_takeoff_transform(end, transform))
+        })
+        .min_by(|a, b| {
+            let distance = |t: &Transform| t.translation.distance_squared(camera.translation);
+            distance(a).total_cmp(&distance(b))
+        });
+    if let Some(takeoff) = takeoff {
+        **camera = takeoff.with_translation(takeoff.translation + Vec3::Y * 3.0);
+    }
+}
@@ -1,28 +1,41 @@
+//! Usage: `cargo run --example osm -- [--location <name>] [--source <name>]`
+//!
+//! The settings of the previous session are restored, the options override the saved ones.
+use std::{env, process};
+
 use bevy::DefaultPlugins;
 use bevy::pbr::DefaultOpaqueRendererMethod;
 use bevy::prelude::*;
 use bevy_egui::EguiPlugin;
 use bevy_flight_sim::runway::spawn_aircraft;
-use bevy_osm::OSMPlugin;
 use bevy_osm::config::OSMConfig;
-use bevy_terrain::WaterPlugin;
+use bevy_osm::settings::{SettingsOverrides, USAGE};
+use bevy_osm::storage::TileStoragePlugin;
+use bevy_osm::{OSMPlugin, SettingsPlugin};
 use bevy_terrain::camera::{
-    get_camera_bundle_for_open_world, rotate_sun, setup_lighting_for_open_world,
+    TerrainCamera, get_camera_bundle_for_open_world, rotate_sun, setup_lighting_for_open_world,
 };
-use bevy_volumetric_clouds::fly_camera::{FlyCam, FlyCameraPlugin, MovementSettings};
+use bevy_terrain::{TerrainCameraPlugin, WaterPlugin};
 use bevy_where_was_i::{WhereWasI, WhereWasIPlugin};
 
 fn main() {
+    let overrides = SettingsOverrides::from_args(env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{err}\n\n{USAGE}");
+        process::exit(2);
+    });
+
     App::new()
         .insert_resource(ClearColor(Color::linear_rgb(0.4, 0.4, 0.4)))
         .insert_resource(DefaultOpaqueRendererMethod::deferred())
-        .insert_resource(MovementSettings { speed: 128.0 })
         .insert_resource(OSMConfig::default())
+        .insert_resource(overrides)
         .add_plugins((
+            TileStoragePlugin,
             DefaultPlugins,
             OSMPlugin,
+            SettingsPlugin,
             WhereWasIPlugin::default(),
-            FlyCameraPlugin,
+            TerrainCameraPlugin,
             EguiPlugin::default(),
             WaterPlugin,
         ))
@@ -41,7 +54,7 @@ fn main() {
 
 fn spawn_camera(mut commands: Commands) {
     let mut camera = commands.spawn(get_camera_bundle_for_open_world());
-    camera.insert(FlyCam);
+    camera.insert(TerrainCamera::default());
     camera.insert(WhereWasI::from_name("osm_camera"));
     camera.insert(Projection::Perspective(PerspectiveProjection {
         near: 0.1,
@@ -0,0 +1,68 @@
+//! Flies the camera along a flight path, records the performance and exits.
+//!
+//! Usage: `cargo run --release --example osm_benchmark -- [flight path] [output.csv|json]`
+//!
+//! The flight path is relative to the `assets` directory. Run the flight once to seed the tile
+//! cache, so later runs do not depend on the network.
+use std::{env, fs, path::Path};
+
+use bevy::DefaultPlugins;
+use bevy::pbr::DefaultOpaqueRendererMethod;
+use bevy::prelude::*;
+use bevy_egui::EguiPlugin;
+use bevy_osm::config::OSMConfig;
+use bevy_osm::flight_path::FlightPathPlayer;
+use bevy_osm::storage::TileStoragePlugin;
+use bevy_osm::{FlightPathPlugin, OSMPlugin};
+use bevy_terrain::camera::{get_camera_bundle_for_open_world, setup_lighting_for_open_world};
+
+const DEFAULT_FLIGHT_PATH: &str = "flight_paths/amsterdam.flight.json";
+const DEFAULT_OUTPUT: &str = "assets/sessions/benchmark.csv";
+
+#[derive(Resource)]
+struct BenchmarkArgs {
+    flight_path: String,
+    output: String,
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let flight_path = args.next().unwrap_or(DEFAULT_FLIGHT_PATH.into());
+    let output = args.next().unwrap_or(DEFAULT_OUTPUT.into());
+
+    App::new()
+        .insert_resource(ClearColor(Color::linear_rgb(0.4, 0.4, 0.4)))
+        .insert_resource(DefaultOpaqueRendererMethod::deferred())
+        .insert_resource(OSMConfig::default())
+        .insert_resource(BenchmarkArgs {
+            flight_path,
+            output,
+        })
+        .add_plugins((
+            TileStoragePlugin,
+            DefaultPlugins,
+            OSMPlugin,
+            FlightPathPlugin,
+            EguiPlugin::default(),
+        ))
+        .add_systems(Startup, (setup_lighting_for_open_world, spawn_camera))
+        .run();
+}
+
+fn spawn_camera(mut commands: Commands, asset_server: Res<AssetServer>, args: Res<BenchmarkArgs>) {
+    if let Some(directory) = Path::new(&args.output).parent() {
+        fs::create_dir_all(directory).expect("Could not create output directory");
+    }
+
+    commands.spawn((
+        get_camera_bundle_for_open_world(),
+        Projection::Perspective(PerspectiveProjection {
+            near: 1.0,
+            far: 1e5,
+            ..default()
+        }),
+        FlightPathPlayer::new(asset_server.load(&args.flight_path))
+            .with_output(&args.output)
+            .with_exit_when_finished(),
+    ));
+}